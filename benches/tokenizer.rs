@@ -0,0 +1,34 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use jq::tokenizer::Tokenizer;
+
+// an array of small log records, roughly `records * 100` bytes
+fn generate_json(records: usize) -> String {
+    let mut s = String::from("[");
+    for i in 0..records {
+        if i > 0 {
+            s.push(',');
+        }
+        s.push_str(&format!(
+            r#"{{"id": {}, "level": "info", "message": "request \"ok\" in {}ms", "ok": true}}"#,
+            i,
+            i % 1000
+        ));
+    }
+    s.push(']');
+    s
+}
+
+fn bench_tokenizer(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tokenize");
+    for records in [1_000, 10_000, 100_000] {
+        let contents = generate_json(records);
+        group.throughput(Throughput::Bytes(contents.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(records), &contents, |b, s| {
+            b.iter(|| Tokenizer::new(s).try_collect().unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_tokenizer);
+criterion_main!(benches);
@@ -1,21 +1,76 @@
-use clap::Parser;
+use std::path::Path;
+
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser};
+use jq::filter::Filter;
 
 #[derive(Parser, Debug)]
-#[command(about = "JSON processor CLI")]
+#[command(name = "rust-jq", about = "JSON processor CLI")]
 pub struct Args {
     #[command(flatten)]
     pub input: Input,
+    #[arg(
+        value_name = "FILTER",
+        help = "jq filter to run on each input value, e.g. `.users[0].name` [default: validate only]"
+    )]
+    pub filter: Option<String>,
+    #[arg(
+        value_name = "FILE",
+        conflicts_with = "raw",
+        help = "Input JSON files, `-` reads stdin [default: stdin]"
+    )]
+    pub files: Vec<String>,
+    #[arg(long, help = "Accept multiple top-level JSON values")]
+    pub multi: bool,
+    #[arg(
+        long,
+        help = "Report all syntax errors instead of stopping at the first"
+    )]
+    pub all_errors: bool,
+    #[arg(
+        long,
+        requires = "multi",
+        conflicts_with = "all_errors",
+        help = "Skip invalid records and continue instead of stopping at the first"
+    )]
+    pub skip_invalid: bool,
+    #[arg(short, long, help = "Print each output on a single line")]
+    pub compact_output: bool,
 }
 
 #[derive(clap::Args, Debug)]
-#[group(required = true, multiple = false)]
+#[group(required = false, multiple = false)]
 pub struct Input {
-    #[arg(short, long, help = "Input JSON file")]
+    #[arg(short, long, help = "Input JSON file, read before any FILE")]
     pub file: Option<String>,
     #[arg(short, long, help = "Raw JSON input")]
     pub raw: Option<String>,
 }
 
 pub fn parse() -> Args {
-    Args::parse()
+    let mut args = Args::parse();
+    // `rust-jq data.json` validates the file, as it did before filters, but
+    // `rust-jq keys data.json` runs `keys` even if there is a file `keys`
+    let is_input = |first: &str| is_file(first) && (args.files.is_empty() || !is_filter(first));
+    if args.filter.as_deref().is_some_and(is_input) {
+        if args.input.raw.is_some() {
+            Args::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "the argument '--raw <RAW>' cannot be used with '[FILE]...'",
+                )
+                .exit();
+        }
+        args.files.insert(0, args.filter.take().unwrap());
+    }
+    args
+}
+
+// `-` is stdin, which is never a filter
+fn is_file(arg: &str) -> bool {
+    arg == "-" || Path::new(arg).is_file()
+}
+
+fn is_filter(arg: &str) -> bool {
+    Filter::parse(arg).is_ok()
 }
@@ -0,0 +1,349 @@
+use std::error::Error;
+use std::fmt::{Display, Write};
+
+use super::filter::SyntaxError;
+use super::parser::{ParseError, RecordError};
+use super::tokenizer::{Span, TokenError};
+use super::FileError;
+
+// number of lines shown before and after the offending line
+const CONTEXT_LINES: usize = 2;
+const TAB_WIDTH: usize = 4;
+
+const RED: &str = "\x1b[1;31m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub span: Span,
+    pub message: String,
+    pub hint: Option<&'static str>,
+}
+
+impl From<&TokenError> for Diagnostic {
+    fn from(err: &TokenError) -> Self {
+        Diagnostic {
+            span: err.span(),
+            message: err.kind().to_string(),
+            hint: err.kind().hint(),
+        }
+    }
+}
+
+impl From<&ParseError> for Diagnostic {
+    fn from(err: &ParseError) -> Self {
+        Diagnostic {
+            span: err.span(),
+            message: err.message(),
+            hint: err.hint(),
+        }
+    }
+}
+
+impl From<&SyntaxError> for Diagnostic {
+    fn from(err: &SyntaxError) -> Self {
+        Diagnostic {
+            span: err.span(),
+            message: err.kind().to_string(),
+            hint: err.kind().hint(),
+        }
+    }
+}
+
+impl Diagnostic {
+    // finds the syntax errors anywhere in the error chain
+    pub fn from_error(err: &anyhow::Error) -> Vec<Diagnostic> {
+        err.chain()
+            .find_map(|e| {
+                if let Some(e) = e.downcast_ref::<Diagnostics>() {
+                    Some(e.0.clone())
+                } else if let Some(e) = e.downcast_ref::<TokenError>() {
+                    Some(vec![e.into()])
+                } else if let Some(e) = e.downcast_ref::<SyntaxError>() {
+                    Some(vec![e.into()])
+                } else {
+                    e.downcast_ref::<ParseError>().map(|e| vec![e.into()])
+                }
+            })
+            .unwrap_or_default()
+    }
+}
+
+// Every syntax error found in an input when recovering from errors.
+#[derive(Debug)]
+pub struct Diagnostics(pub Vec<Diagnostic>);
+
+impl Display for Diagnostics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Some(first) = self.0.first() else {
+            return write!(f, "No syntax errors");
+        };
+        write!(f, "{}: {}", first.span, first.message)?;
+        if self.0.len() > 1 {
+            write!(f, " (and {} more errors)", self.0.len() - 1)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for Diagnostics {}
+
+// Renders the syntax errors found in `err` against the input they came from.
+// Inputs read by the library carry their own contents, `source` is used otherwise.
+pub fn render_error(err: &anyhow::Error, source: Option<&str>, color: bool) -> Option<String> {
+    let mut diagnostics = Diagnostic::from_error(err);
+    if diagnostics.is_empty() {
+        return None;
+    }
+    if let Some(record) = err.chain().find_map(|e| e.downcast_ref::<RecordError>()) {
+        for d in &mut diagnostics {
+            d.message = format!("{} in record #{}", d.message, record.index + 1);
+        }
+    }
+    let (source, filename) = match err.downcast_ref::<FileError>() {
+        Some(file_error) => (file_error.contents(), Some(file_error.filename())),
+        None => (source?, None),
+    };
+
+    let mut out = diagnostics
+        .iter()
+        .map(|d| render(d, source, filename, color))
+        .collect::<Vec<_>>()
+        .join("\n");
+    if diagnostics.len() > 1 {
+        let paint = |style: &'static str| if color { style } else { "" };
+        let _ = writeln!(
+            out,
+            "\n{}error{}: found {} syntax errors",
+            paint(RED),
+            paint(RESET),
+            diagnostics.len()
+        );
+    }
+    Some(out)
+}
+
+// Renders the diagnostic in the style of
+//
+// error: Missing comma
+//  --> file.json:3:5
+//   |
+// 2 |   "a": 1
+// 3 |   "b": 2
+//   |   ^^^ expected `,` or `}` after object value
+// 4 | }
+pub fn render(
+    diagnostic: &Diagnostic,
+    source: &str,
+    filename: Option<&str>,
+    color: bool,
+) -> String {
+    let paint = |style: &'static str| if color { style } else { "" };
+    let reset = paint(RESET);
+
+    let mut lines: Vec<&str> = source.lines().collect();
+    if lines.is_empty() {
+        lines.push("");
+    }
+    let line_idx = diagnostic.span.line.saturating_sub(1).min(lines.len() - 1);
+    let first = line_idx.saturating_sub(CONTEXT_LINES);
+    let last = (line_idx + CONTEXT_LINES).min(lines.len() - 1);
+    let gutter = (last + 1).to_string().len();
+
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "{}error{}{}: {}{}",
+        paint(RED),
+        reset,
+        paint(BOLD),
+        diagnostic.message,
+        reset
+    );
+    let _ = writeln!(
+        out,
+        "{:gutter$}{}-->{} {}{}",
+        "",
+        paint(BLUE),
+        reset,
+        filename.map(|f| format!("{}:", f)).unwrap_or_default(),
+        diagnostic.span,
+    );
+    let _ = writeln!(out, "{:gutter$} {}|{}", "", paint(BLUE), reset);
+
+    for (idx, line) in lines.iter().enumerate().take(last + 1).skip(first) {
+        let _ = writeln!(
+            out,
+            "{}{:>gutter$} |{} {}",
+            paint(BLUE),
+            idx + 1,
+            reset,
+            expand_tabs(line)
+        );
+        if idx == line_idx {
+            let (offset, width) = underline(line, diagnostic.span, source);
+            let _ = writeln!(
+                out,
+                "{}{:gutter$} |{} {:offset$}{}{}{}{}",
+                paint(BLUE),
+                "",
+                reset,
+                "",
+                paint(RED),
+                "^".repeat(width),
+                diagnostic
+                    .hint
+                    .map(|hint| format!(" {}", hint))
+                    .unwrap_or_default(),
+                reset,
+            );
+        }
+    }
+
+    out
+}
+
+fn expand_tabs(line: &str) -> String {
+    line.replace('\t', &" ".repeat(TAB_WIDTH))
+}
+
+// display offset and width of the span within its line
+fn underline(line: &str, span: Span, source: &str) -> (usize, usize) {
+    let display_width = |s: &str| {
+        s.chars()
+            .map(|c| if c == '\t' { TAB_WIDTH } else { 1 })
+            .sum::<usize>()
+    };
+
+    let before: String = line.chars().take(span.column.saturating_sub(1)).collect();
+    let end = (span.offset + span.len).min(source.len());
+    let spanned = source.get(span.offset..end).unwrap_or_default();
+    // underline only the part of the span on this line
+    let spanned = spanned.split('\n').next().unwrap_or_default();
+
+    (display_width(&before), display_width(spanned).max(1))
+}
+
+#[cfg(test)]
+mod diagnostics {
+    use super::*;
+    use crate::tokenizer::TokenErrorKind;
+
+    #[test]
+    fn test_render_parse_error() {
+        let source = "{\n  \"a\": 1\n  \"b\": 2\n}";
+        let diagnostic: Diagnostic = (&ParseError::MissingComma(Span {
+            offset: 12,
+            len: 3,
+            line: 3,
+            column: 3,
+        }))
+            .into();
+        let rendered = render(&diagnostic, source, Some("test.json"), false);
+        assert_eq!(
+            rendered,
+            [
+                "error: Missing comma",
+                " --> test.json:3:3",
+                "  |",
+                "1 | {",
+                "2 |   \"a\": 1",
+                "3 |   \"b\": 2",
+                "  |   ^^^ expected `,` or `}` after object value",
+                "4 | }",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_render_token_error_with_tabs() {
+        let source = "[\n\t\"a\\qb\"\n]";
+        let err = TokenError::new(
+            Span {
+                offset: 5,
+                len: 2,
+                line: 2,
+                column: 4,
+            },
+            TokenErrorKind::InvalidEscape('q'),
+        );
+        let rendered = render(&(&err).into(), source, None, false);
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert_eq!(lines[0], "error: Invalid escape sequence `\\q`");
+        assert_eq!(lines[1], " --> 2:4");
+        assert_eq!(lines[4], "2 |     \"a\\qb\"");
+        assert!(lines[5].starts_with("  |       ^^ valid escapes"));
+    }
+
+    #[test]
+    fn test_render_color() {
+        let source = "[1 2]";
+        let diagnostic: Diagnostic = (&ParseError::MissingCommaInArray(Span {
+            offset: 3,
+            len: 1,
+            line: 1,
+            column: 4,
+        }))
+            .into();
+        let rendered = render(&diagnostic, source, None, true);
+        assert!(rendered.contains(RED));
+        assert!(rendered.contains(RESET));
+
+        let rendered = render(&diagnostic, source, None, false);
+        assert!(!rendered.contains('\x1b'));
+    }
+
+    #[test]
+    fn test_render_filter_syntax_error() {
+        let err = crate::filter::Filter::parse(".a | .[0").unwrap_err();
+        let rendered = render_error(&err.into(), Some(".a | .[0"), false).unwrap();
+        assert_eq!(
+            rendered,
+            [
+                "error: Unexpected end of filter",
+                " --> 1:9",
+                "  |",
+                "1 | .a | .[0",
+                "  |         ^ the filter is incomplete",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_render_error_from_chain() {
+        let err = crate::process_str("[1,]").unwrap_err();
+        let rendered = render_error(&err, Some("[1,]"), false).unwrap();
+        assert!(rendered.starts_with("error: Unexpected comma before end of array\n --> 1:3\n"));
+
+        let err = crate::parse_str("").unwrap_err();
+        let rendered = render_error(&err, Some(""), false).unwrap();
+        assert!(rendered.starts_with("error: Unexpected end of input\n --> 1:1\n"));
+
+        let contents = "{\"a\" 1, \"b\": [tru]}";
+        let err = crate::process_str_with(
+            contents,
+            crate::ParseOptions {
+                all_errors: true,
+                ..Default::default()
+            },
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "1:6: Expected colon after string key (and 1 more errors)"
+        );
+        let rendered = render_error(&err, Some(contents), false).unwrap();
+        assert!(rendered.contains("error: Expected colon after string key\n --> 1:6\n"));
+        assert!(rendered.contains("\nerror: Unexpected token\n --> 1:15\n"));
+        assert!(rendered.ends_with("\nerror: found 2 syntax errors\n"));
+
+        let err = anyhow::anyhow!("not a syntax error");
+        assert!(render_error(&err, Some("[1,]"), false).is_none());
+    }
+}
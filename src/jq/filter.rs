@@ -0,0 +1,129 @@
+use std::error::Error;
+use std::fmt::Display;
+
+use super::tokenizer::Span;
+use super::Value;
+
+pub mod builtins;
+pub mod env;
+pub mod eval;
+pub mod format;
+pub mod lexer;
+pub mod ops;
+pub mod parser;
+pub mod path;
+
+pub use eval::{Outputs, RuntimeError};
+pub use parser::{AssignOp, BinaryOp, Expr, FuncDef, Param, Pattern, StringPart};
+
+/// A jq program such as `.users[0] | .name`, parsed once and run against any
+/// number of inputs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    expr: Expr,
+}
+
+impl Filter {
+    pub fn parse(source: &str) -> Result<Filter, SyntaxError> {
+        let tokens = lexer::tokenize(source)?;
+        let expr = parser::parse(&tokens, lexer::end_of_input(source))?;
+
+        Ok(Filter { expr })
+    }
+
+    /// Lazily yields each output of the filter for `input`. Recursive
+    /// functions grow the stack as they need it, on any thread, and fail with
+    /// an error once nested [`eval::MAX_CALL_DEPTH`] calls deep.
+    pub fn run(&self, input: Value) -> Outputs<'_> {
+        eval::eval(&self.expr, &env::Env::default(), input)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyntaxErrorKind {
+    UnexpectedCharacter(char),
+    UnterminatedString,
+    InvalidEscape(char),
+    InvalidUnicodeEscape,
+    InvalidNumber,
+    // display form of the token
+    UnexpectedToken(String),
+    UnexpectedEndOfFilter,
+    // name and arity, e.g. `foo/0`
+    UndefinedFunction(String, usize),
+    // name without the `$`
+    UndefinedVariable(String),
+    // name without the `@`
+    UnknownFormat(String),
+}
+
+impl SyntaxErrorKind {
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            SyntaxErrorKind::UnexpectedCharacter(_) => None,
+            SyntaxErrorKind::UnterminatedString => Some("add a closing `\"`"),
+            SyntaxErrorKind::InvalidEscape(_) => {
+                Some("valid escapes are `\\\"`, `\\\\`, `\\/`, `\\b`, `\\f`, `\\n`, `\\r`, `\\t` and `\\uXXXX`")
+            }
+            SyntaxErrorKind::InvalidUnicodeEscape => {
+                Some("`\\u` must be followed by 4 hex digits of a unicode scalar value")
+            }
+            SyntaxErrorKind::InvalidNumber => None,
+            SyntaxErrorKind::UnexpectedToken(_) => None,
+            SyntaxErrorKind::UnexpectedEndOfFilter => Some("the filter is incomplete"),
+            SyntaxErrorKind::UndefinedFunction(_, _) => None,
+            SyntaxErrorKind::UndefinedVariable(_) => None,
+            SyntaxErrorKind::UnknownFormat(_) => Some(
+                "formats are @text, @json, @html, @uri, @csv, @tsv, @sh, @base64, @base64d, @base32 and @base32d",
+            ),
+        }
+    }
+}
+
+impl Display for SyntaxErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SyntaxErrorKind::UnexpectedCharacter(c) => write!(f, "Unexpected character `{}`", c),
+            SyntaxErrorKind::UnterminatedString => write!(f, "Unterminated string"),
+            SyntaxErrorKind::InvalidEscape(c) => write!(f, "Invalid escape sequence `\\{}`", c),
+            SyntaxErrorKind::InvalidUnicodeEscape => write!(f, "Invalid unicode escape"),
+            SyntaxErrorKind::InvalidNumber => write!(f, "Invalid number literal"),
+            SyntaxErrorKind::UnexpectedToken(token) => write!(f, "Unexpected `{}`", token),
+            SyntaxErrorKind::UnexpectedEndOfFilter => write!(f, "Unexpected end of filter"),
+            SyntaxErrorKind::UndefinedFunction(name, arity) => {
+                write!(f, "{}/{} is not defined", name, arity)
+            }
+            SyntaxErrorKind::UndefinedVariable(name) => write!(f, "${} is not defined", name),
+            SyntaxErrorKind::UnknownFormat(name) => write!(f, "@{} is not a valid format", name),
+        }
+    }
+}
+
+// Error in the source of a filter, located by its span within the filter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxError {
+    span: Span,
+    kind: SyntaxErrorKind,
+}
+
+impl SyntaxError {
+    pub fn new(span: Span, kind: SyntaxErrorKind) -> SyntaxError {
+        SyntaxError { span, kind }
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn kind(&self) -> &SyntaxErrorKind {
+        &self.kind
+    }
+}
+
+impl Display for SyntaxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.span, self.kind)
+    }
+}
+
+impl Error for SyntaxError {}
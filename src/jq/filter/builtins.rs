@@ -0,0 +1,384 @@
+use std::iter;
+use std::sync::OnceLock;
+
+use super::env::Env;
+use super::eval::{self, eval, Outputs, RuntimeError};
+use super::parser::{self, FuncDef};
+use super::path::{self, Path, PathOutputs, Tracked};
+use super::{lexer, Expr};
+use crate::Value;
+
+// builtins written in jq, in terms of the native ones and of each other
+const PRELUDE: &str = include_str!("prelude.jq");
+
+// native implementation of a builtin, called with its unevaluated arguments
+pub type Builtin = for<'a> fn(&'a [Expr], &Env<'a>, Value) -> Outputs<'a>;
+
+// native implementation of a builtin in a path expression such as `path(f)`
+pub type PathBuiltin = for<'a> fn(&'a [Expr], &Env<'a>, Tracked) -> PathOutputs<'a>;
+
+// name, arity and implementation of every builtin function
+const BUILTINS: &[(&str, usize, Builtin)] = &[
+    ("empty", 0, empty),
+    ("not", 0, not),
+    ("error", 0, error),
+    ("error", 1, error_with),
+    ("type", 0, type_of),
+    ("sort", 0, sort),
+    ("unique", 0, unique),
+    ("group_by", 1, group_by),
+    ("recurse", 0, recurse),
+    ("recurse", 1, recurse_with),
+    ("path", 1, path_of),
+    ("getpath", 1, getpath),
+    ("setpath", 2, setpath),
+    ("delpaths", 1, delpaths),
+    ("to_entries", 0, to_entries),
+    ("from_entries", 0, from_entries),
+];
+
+// the builtins that are paths, other builtins only output new values
+const PATH_BUILTINS: &[(&str, usize, PathBuiltin)] = &[
+    ("recurse", 0, recurse_paths),
+    ("recurse", 1, recurse_with_paths),
+    ("getpath", 1, getpath_paths),
+];
+
+pub fn lookup(name: &str, arity: usize) -> Option<Builtin> {
+    BUILTINS
+        .iter()
+        .find(|(n, a, _)| *n == name && *a == arity)
+        .map(|(_, _, f)| *f)
+}
+
+pub fn lookup_path(name: &str, arity: usize) -> Option<PathBuiltin> {
+    PATH_BUILTINS
+        .iter()
+        .find(|(n, a, _)| *n == name && *a == arity)
+        .map(|(_, _, f)| *f)
+}
+
+/// Definitions of the builtins written in jq, parsed on first use.
+pub fn prelude() -> &'static [FuncDef] {
+    static DEFS: OnceLock<Vec<FuncDef>> = OnceLock::new();
+    DEFS.get_or_init(|| {
+        let tokens = lexer::tokenize(PRELUDE).expect("the prelude is a valid filter");
+        parser::parse_defs(&tokens, lexer::end_of_input(PRELUDE))
+            .expect("the prelude is a valid filter")
+    })
+}
+
+// a builtin written in jq
+pub fn definition(name: &str, arity: usize) -> Option<&'static FuncDef> {
+    prelude()
+        .iter()
+        .find(|def| def.name == name && def.params.len() == arity)
+}
+
+fn empty<'a>(_: &'a [Expr], _: &Env<'a>, _: Value) -> Outputs<'a> {
+    Box::new(iter::empty())
+}
+
+fn not<'a>(_: &'a [Expr], _: &Env<'a>, input: Value) -> Outputs<'a> {
+    Box::new(iter::once(Ok(Value::Boolean(!input.is_truthy()))))
+}
+
+// raises the input as an error
+fn error<'a>(_: &'a [Expr], _: &Env<'a>, input: Value) -> Outputs<'a> {
+    Box::new(iter::once(Err(RuntimeError::new(input))))
+}
+
+// `error(msg)` raises the first output of `msg`
+fn error_with<'a>(args: &'a [Expr], env: &Env<'a>, input: Value) -> Outputs<'a> {
+    Box::new(
+        eval(&args[0], env, input)
+            .take(1)
+            .map(|message| Err(message.map_or_else(|e| e, RuntimeError::new))),
+    )
+}
+
+fn type_of<'a>(_: &'a [Expr], _: &Env<'a>, input: Value) -> Outputs<'a> {
+    Box::new(iter::once(Ok(Value::String(input.type_name().to_string()))))
+}
+
+fn sort<'a>(_: &'a [Expr], _: &Env<'a>, input: Value) -> Outputs<'a> {
+    Box::new(iter::once(elements(input, "sorted").map(|mut elements| {
+        elements.sort();
+        Value::Array(elements.into())
+    })))
+}
+
+fn unique<'a>(_: &'a [Expr], _: &Env<'a>, input: Value) -> Outputs<'a> {
+    Box::new(iter::once(elements(input, "sorted").map(|mut elements| {
+        elements.sort();
+        elements.dedup();
+        Value::Array(elements.into())
+    })))
+}
+
+// elements with the same outputs of `f` are grouped, groups are sorted by them
+fn group_by<'a>(args: &'a [Expr], env: &Env<'a>, input: Value) -> Outputs<'a> {
+    let grouped = elements(input, "grouped").and_then(|elements| {
+        let mut keyed = elements
+            .into_iter()
+            .map(|element| {
+                let key = eval(&args[0], env, element.clone()).collect::<Result<Vec<_>, _>>()?;
+                Ok((key, element))
+            })
+            .collect::<Result<Vec<_>, RuntimeError>>()?;
+        // stable, so a group keeps the order of its elements
+        keyed.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let mut groups: Vec<(Vec<Value>, Vec<Value>)> = Vec::new();
+        for (key, element) in keyed {
+            match groups.last_mut() {
+                Some((last, group)) if *last == key => group.push(element),
+                _ => groups.push((key, vec![element])),
+            }
+        }
+        Ok(Value::Array(
+            groups
+                .into_iter()
+                .map(|(_, group)| Value::Array(group.into()))
+                .collect::<Vec<_>>()
+                .into(),
+        ))
+    });
+    Box::new(iter::once(grouped))
+}
+
+// `..`, the input and every value nested in it, in pre-order. The walk keeps
+// its own stack of containers, so deeply nested documents can't overflow the
+// native one.
+fn recurse<'a>(_: &'a [Expr], _: &Env<'a>, input: Value) -> Outputs<'a> {
+    let mut next = Some(input);
+    // containers being walked, with the position of their next child
+    let mut stack: Vec<(Value, usize)> = Vec::new();
+    Box::new(iter::from_fn(move || {
+        let value = match next.take() {
+            Some(value) => value,
+            None => loop {
+                let (container, i) = stack.last_mut()?;
+                let child = match container {
+                    Value::Array(elements) => elements.get(*i).cloned(),
+                    Value::Object(fields) => fields.get(*i).map(|(_, v)| v.clone()),
+                    _ => None,
+                };
+                match child {
+                    Some(child) => {
+                        *i += 1;
+                        break child;
+                    }
+                    None => {
+                        stack.pop();
+                    }
+                }
+            },
+        };
+        if matches!(value, Value::Array(_) | Value::Object(_)) {
+            stack.push((value.clone(), 0));
+        }
+        Some(Ok(value))
+    }))
+}
+
+// `recurse(f)`: the input, then recursively each output of `f`, in
+// pre-order. Like `..`, the pending outputs of each level are kept on a stack
+// rather than in nested calls.
+fn recurse_with<'a>(args: &'a [Expr], env: &Env<'a>, input: Value) -> Outputs<'a> {
+    let env = env.clone();
+    descend(input, move |value| eval(&args[0], &env, value))
+}
+
+fn recurse_paths<'a>(_: &'a [Expr], _: &Env<'a>, input: Tracked) -> PathOutputs<'a> {
+    descend(input, path::children)
+}
+
+fn recurse_with_paths<'a>(args: &'a [Expr], env: &Env<'a>, input: Tracked) -> PathOutputs<'a> {
+    let env = env.clone();
+    descend(input, move |input| path::eval_paths(&args[0], &env, input))
+}
+
+// `input`, then the outputs of `f` on it and on each of its outputs, in
+// pre-order
+fn descend<'a, T: Clone + 'a>(
+    input: T,
+    f: impl Fn(T) -> Box<dyn Iterator<Item = Result<T, RuntimeError>> + 'a> + 'a,
+) -> Box<dyn Iterator<Item = Result<T, RuntimeError>> + 'a> {
+    let mut stack: Vec<Box<dyn Iterator<Item = Result<T, RuntimeError>> + 'a>> =
+        vec![Box::new(iter::once(Ok(input)))];
+    Box::new(iter::from_fn(move || loop {
+        match stack.last_mut()?.next() {
+            Some(Ok(value)) => {
+                stack.push(f(value.clone()));
+                return Some(Ok(value));
+            }
+            Some(Err(e)) => return Some(Err(e)),
+            None => {
+                stack.pop();
+            }
+        }
+    }))
+}
+
+// `path(f)`, the path of each output of `f` as an array
+fn path_of<'a>(args: &'a [Expr], env: &Env<'a>, input: Value) -> Outputs<'a> {
+    Box::new(
+        path::paths(&args[0], env, input).map(|path| path.map(|path| Value::Array(path.into()))),
+    )
+}
+
+fn getpath<'a>(args: &'a [Expr], env: &Env<'a>, input: Value) -> Outputs<'a> {
+    Box::new(
+        eval(&args[0], env, input.clone())
+            .map(move |keys| path::get_path(&input, &path_keys(keys?)?)),
+    )
+}
+
+// `getpath(p)` in a path expression, the input's path followed by `p`
+fn getpath_paths<'a>(args: &'a [Expr], env: &Env<'a>, input: Tracked) -> PathOutputs<'a> {
+    Box::new(eval(&args[0], env, input.1.clone()).map(move |keys| {
+        let keys = path_keys(keys?)?;
+        let value = path::get_path(&input.1, &keys)?;
+        let path = input.0.clone().map(|mut path| {
+            path.extend(keys);
+            path
+        });
+        Ok((path, value))
+    }))
+}
+
+// The outputs of the arguments are collected before the input is changed, so
+// that it can be changed in place.
+fn setpath<'a>(args: &'a [Expr], env: &Env<'a>, input: Value) -> Outputs<'a> {
+    let mut updates = Vec::new();
+    for keys in eval(&args[0], env, input.clone()) {
+        let keys = match keys.and_then(path_keys) {
+            Ok(keys) => keys,
+            Err(e) => return Box::new(iter::once(Err(e))),
+        };
+        for value in eval(&args[1], env, input.clone()) {
+            updates.push(value.map(|value| (keys.clone(), value)));
+        }
+    }
+    each_on(input, updates, |input, (keys, value)| {
+        path::set_path(input, &keys, value)
+    })
+}
+
+fn delpaths<'a>(args: &'a [Expr], env: &Env<'a>, input: Value) -> Outputs<'a> {
+    let paths = eval(&args[0], env, input.clone())
+        .map(|paths| match paths? {
+            Value::Array(paths) => paths.iter().cloned().map(path_keys).collect(),
+            _ => Err(RuntimeError::msg("Paths must be specified as an array")),
+        })
+        .collect::<Vec<_>>();
+    each_on(input, paths, path::delete_paths)
+}
+
+// `f(input, item)` for each item, the last one taking the input itself
+fn each_on<'a, T: 'a>(
+    input: Value,
+    items: Vec<Result<T, RuntimeError>>,
+    f: impl Fn(Value, T) -> Result<Value, RuntimeError> + 'a,
+) -> Outputs<'a> {
+    let mut input = Some(input);
+    let last = items.len().saturating_sub(1);
+    Box::new(items.into_iter().enumerate().map(move |(i, item)| {
+        let input = if i == last {
+            input.take().unwrap_or(Value::Null)
+        } else {
+            input.clone().unwrap_or(Value::Null)
+        };
+        f(input, item?)
+    }))
+}
+
+fn path_keys(path: Value) -> Result<Path, RuntimeError> {
+    match path {
+        Value::Array(keys) => Ok(keys.to_vec()),
+        _ => Err(RuntimeError::msg("Path must be specified as an array")),
+    }
+}
+
+// `{"a": 1}` is `[{"key": "a", "value": 1}]`, array elements have their
+// index as the key
+fn to_entries<'a>(_: &'a [Expr], _: &Env<'a>, input: Value) -> Outputs<'a> {
+    let entry = |key: Value, value: Value| {
+        Value::Object(vec![("key".to_string(), key), ("value".to_string(), value)].into())
+    };
+    let entries = match input {
+        Value::Object(fields) => Ok(fields
+            .iter()
+            .map(|(k, v)| entry(Value::String(k.clone()), v.clone()))
+            .collect::<Vec<_>>()),
+        Value::Array(elements) => Ok(elements
+            .iter()
+            .enumerate()
+            .map(|(i, v)| entry(Value::Number(i as f64), v.clone()))
+            .collect()),
+        input => Err(RuntimeError::msg(format!(
+            "{} ({}) has no keys",
+            input.type_name(),
+            input
+        ))),
+    };
+    Box::new(iter::once(
+        entries.map(|entries| Value::Array(entries.into())),
+    ))
+}
+
+// The inverse of `to_entries`. As in jq, the key can also be named `k`,
+// `name`, `Name`, `K` or `Key` and the value `v`, and keys that aren't strings
+// are converted to JSON text.
+fn from_entries<'a>(_: &'a [Expr], _: &Env<'a>, input: Value) -> Outputs<'a> {
+    let object = eval::iterate(input).try_fold(Vec::new(), |mut fields, entry| {
+        let entry = entry?;
+        let field = |name: &str| eval::index_value(&entry, &Value::String(name.to_string()));
+        let mut key = field("key")?;
+        if let Value::Null = key {
+            for name in ["k", "name", "Name", "K", "Key"] {
+                key = field(name)?;
+                if key.is_truthy() {
+                    break;
+                }
+            }
+        }
+        let key = match key {
+            Value::String(key) => key,
+            key => key.to_string(),
+        };
+        let value = match &entry {
+            Value::Object(fields) if fields.iter().any(|(k, _)| k == "value") => field("value")?,
+            Value::Object(_) => field("v")?,
+            entry => {
+                return Err(RuntimeError::msg(format!(
+                    "Cannot check whether {} has a string key",
+                    entry.type_name()
+                )))
+            }
+        };
+        // a repeated key keeps its first position, with the last value
+        match fields.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, v)) => *v = value,
+            None => fields.push((key, value)),
+        }
+        Ok(fields)
+    });
+    Box::new(iter::once(
+        object.map(|fields| Value::Object(fields.into())),
+    ))
+}
+
+// elements of an array input, `verb` describes what the builtin does to them
+fn elements(input: Value, verb: &str) -> Result<Vec<Value>, RuntimeError> {
+    match input {
+        Value::Array(elements) => Ok(elements.to_vec()),
+        input => Err(RuntimeError::msg(format!(
+            "{} ({}) cannot be {}, as it is not an array",
+            input.type_name(),
+            input,
+            verb
+        ))),
+    }
+}
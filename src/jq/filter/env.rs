@@ -0,0 +1,124 @@
+use std::rc::Rc;
+
+use super::parser::FuncDef;
+use super::Expr;
+use crate::Value;
+
+/// Variables and functions in scope while a filter runs, innermost first.
+/// Scopes are shared by the lazy outputs that captured them, so binding a
+/// name never copies the outer ones.
+#[derive(Debug, Clone, Default)]
+pub struct Env<'a> {
+    head: Option<Rc<Binding<'a>>>,
+}
+
+#[derive(Debug)]
+struct Binding<'a> {
+    name: &'a str,
+    bound: Bound<'a>,
+    parent: Env<'a>,
+}
+
+// variables and functions have separate namespaces, `$f` doesn't hide `f`
+#[derive(Debug)]
+enum Bound<'a> {
+    Var(Value),
+    Function(Function<'a>),
+}
+
+/// A callable filter, with the scope it was defined in.
+#[derive(Debug, Clone)]
+pub enum Function<'a> {
+    // `def name(params): body;`, the scope doesn't include the function
+    // itself, which is bound again on every call
+    Def(&'a FuncDef, Env<'a>),
+    // argument passed for a filter parameter, evaluated in the caller's scope
+    Arg(&'a Expr, Env<'a>),
+}
+
+impl Function<'_> {
+    pub fn arity(&self) -> usize {
+        match self {
+            Function::Def(def, _) => def.params.len(),
+            Function::Arg(_, _) => 0,
+        }
+    }
+}
+
+impl<'a> Env<'a> {
+    /// A new scope with `$name` bound to `value`, shadowing outer bindings.
+    pub fn bind(&self, name: &'a str, value: Value) -> Env<'a> {
+        self.push(name, Bound::Var(value))
+    }
+
+    /// A new scope with `function` callable as `name`, shadowing outer
+    /// functions with the same name and arity.
+    pub fn bind_function(&self, name: &'a str, function: Function<'a>) -> Env<'a> {
+        self.push(name, Bound::Function(function))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.find(name, |bound| match bound {
+            Bound::Var(value) => Some(value),
+            Bound::Function(_) => None,
+        })
+    }
+
+    pub fn function(&self, name: &str, arity: usize) -> Option<&Function<'a>> {
+        self.find(name, |bound| match bound {
+            Bound::Function(function) if function.arity() == arity => Some(function),
+            _ => None,
+        })
+    }
+
+    fn push(&self, name: &'a str, bound: Bound<'a>) -> Env<'a> {
+        Env {
+            head: Some(Rc::new(Binding {
+                name,
+                bound,
+                parent: self.clone(),
+            })),
+        }
+    }
+
+    // the innermost binding of `name` that `f` accepts
+    fn find<'s, T>(&'s self, name: &str, f: impl Fn(&'s Bound<'a>) -> Option<T>) -> Option<T> {
+        let mut scope = self.head.as_deref();
+        while let Some(binding) = scope {
+            if binding.name == name {
+                if let Some(found) = f(&binding.bound) {
+                    return Some(found);
+                }
+            }
+            scope = binding.parent.head.as_deref();
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod env {
+    use super::*;
+
+    #[test]
+    fn test_env_scopes() {
+        let outer = Env::default().bind("a", Value::Number(1.0));
+        let inner = outer.bind("b", Value::Number(2.0)).bind("a", Value::Null);
+        assert_eq!(inner.get("a"), Some(&Value::Null));
+        assert_eq!(inner.get("b"), Some(&Value::Number(2.0)));
+        assert_eq!(outer.get("a"), Some(&Value::Number(1.0)));
+        assert_eq!(outer.get("b"), None);
+    }
+
+    #[test]
+    fn test_env_functions() {
+        let one = Expr::Literal(Value::Number(1.0));
+        let env = Env::default()
+            .bind("f", Value::Null)
+            .bind_function("f", Function::Arg(&one, Env::default()));
+        assert_eq!(env.get("f"), Some(&Value::Null));
+        assert!(env.function("f", 0).is_some());
+        assert!(env.function("f", 1).is_none());
+        assert!(env.function("g", 0).is_none());
+    }
+}
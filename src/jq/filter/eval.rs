@@ -0,0 +1,1811 @@
+use std::cell::Cell;
+use std::error::Error;
+use std::fmt::Display;
+use std::iter;
+
+use super::env::{Env, Function};
+use super::parser::{FuncDef, Param, Pattern, StringPart};
+use super::{builtins, format, ops, path, AssignOp, BinaryOp, Expr};
+use crate::Value;
+
+/// Lazy stream of the outputs of an expression. Every expression may produce
+/// any number of outputs, and an error ends the stream it occurs in.
+pub type Outputs<'a> = Box<dyn Iterator<Item = Result<Value, RuntimeError>> + 'a>;
+
+/// How many calls to functions defined in jq a call may be nested in before
+/// it fails. Each level takes a few KiB of stack, which nested calls allocate
+/// as they need it, so that deep recursion runs on any thread.
+pub const MAX_CALL_DEPTH: usize = 100_000;
+
+// stack left below which a nested call continues on a new segment, and the
+// size of the segments
+const STACK_RED_ZONE: usize = 256 << 10;
+const STACK_SEGMENT: usize = 16 << 20;
+
+thread_local! {
+    // calls to functions defined in jq whose outputs are being evaluated
+    static CALL_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+pub fn eval<'a>(expr: &'a Expr, env: &Env<'a>, input: Value) -> Outputs<'a> {
+    match expr {
+        Expr::Identity => single(Ok(input)),
+        Expr::Literal(value) => single(Ok(value.clone())),
+        Expr::Index(target, index) => {
+            // for each index, every target is indexed, as in jq
+            let (target_env, target_input) = (env.clone(), input.clone());
+            then(eval(index, env, input), move |index| {
+                Box::new(
+                    eval(target, &target_env, target_input.clone())
+                        .map(move |value| value.and_then(|value| index_value(&value, &index))),
+                )
+            })
+        }
+        Expr::Slice(target, start, end) => {
+            // bounds vary slowest, like indices
+            let (env, end_input, target_input) = (env.clone(), input.clone(), input.clone());
+            then(eval_bound(start, &env, input), move |start| {
+                let (target_env, target_input) = (env.clone(), target_input.clone());
+                then(eval_bound(end, &env, end_input.clone()), move |end| {
+                    let start = start.clone();
+                    Box::new(
+                        eval(target, &target_env, target_input.clone()).map(move |value| {
+                            value.and_then(|value| slice_value(&value, &start, &end))
+                        }),
+                    )
+                })
+            })
+        }
+        Expr::Iterate(expr) => then(eval(expr, env, input), iterate),
+        Expr::Array(None) => single(Ok(Value::Array(Vec::new().into()))),
+        Expr::Array(Some(expr)) => {
+            // collected once the array is wanted
+            let env = env.clone();
+            Box::new(iter::once_with(move || {
+                eval(expr, &env, input)
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(|elements| Value::Array(elements.into()))
+            }))
+        }
+        Expr::Object(entries) => object(entries, env.clone(), input, Vec::new()),
+        Expr::Var(name) => single(variable(name, env)),
+        Expr::Neg(expr) => Box::new(eval(expr, env, input).map(|value| match value? {
+            Value::Number(n) => Ok(Value::Number(-n)),
+            value => Err(RuntimeError::msg(format!(
+                "{} ({}) cannot be negated",
+                value.type_name(),
+                value
+            ))),
+        })),
+        Expr::Binary(op, lhs, rhs) => binary(*op, lhs, rhs, env.clone(), input),
+        Expr::If(cond, then_branch, else_branch) => {
+            // every output of the condition picks a branch
+            let (env, branch_input) = (env.clone(), input.clone());
+            then(eval(cond, &env, input), move |cond| {
+                match (cond.is_truthy(), else_branch) {
+                    (true, _) => eval(then_branch, &env, branch_input.clone()),
+                    (false, Some(else_branch)) => eval(else_branch, &env, branch_input.clone()),
+                    (false, None) => single(Ok(branch_input.clone())),
+                }
+            })
+        }
+        Expr::Try(body, handler) => {
+            // the first error ends the body, and is handled or dropped
+            let mut body = eval(body, env, input);
+            let mut failed = false;
+            let outputs = iter::from_fn(move || {
+                if failed {
+                    return None;
+                }
+                let output = body.next()?;
+                failed = output.is_err();
+                Some(output)
+            });
+            let env = env.clone();
+            Box::new(outputs.flat_map(move |output| match (output, handler) {
+                (Ok(value), _) => single(Ok(value)),
+                (Err(e), Some(handler)) => eval(handler, &env, e.into_value()),
+                (Err(_), None) => Box::new(iter::empty()),
+            }))
+        }
+        Expr::Alternative(lhs, rhs) => alternative(lhs, rhs, env.clone(), input),
+        Expr::Assign(op, lhs, rhs) => assign(*op, lhs, rhs, env.clone(), input),
+        Expr::And(lhs, rhs) => logical(lhs, rhs, env.clone(), input, false),
+        Expr::Or(lhs, rhs) => logical(lhs, rhs, env.clone(), input, true),
+        Expr::Bind(source, patterns, body) => bind(source, patterns, body, env, input),
+        Expr::Reduce(source, patterns, init, update) => {
+            let (env, source_input) = (env.clone(), input.clone());
+            then(eval(init, &env, input), move |init| {
+                single(reduce(
+                    source,
+                    patterns,
+                    update,
+                    &env,
+                    source_input.clone(),
+                    init,
+                ))
+            })
+        }
+        Expr::Foreach(source, patterns, init, update, extract) => {
+            let (env, source_input) = (env.clone(), input.clone());
+            then(eval(init, &env, input), move |init| {
+                foreach(
+                    source,
+                    patterns,
+                    update,
+                    extract.as_deref(),
+                    &env,
+                    source_input.clone(),
+                    init,
+                )
+            })
+        }
+        Expr::Call(name, args) => call(name, args, env, input),
+        Expr::Def(def, rest) => {
+            let env = env.bind_function(&def.name, Function::Def(def, env.clone()));
+            eval(rest, &env, input)
+        }
+        Expr::Comma(lhs, rhs) => {
+            Box::new(eval(lhs, env, input.clone()).chain(eval(rhs, env, input)))
+        }
+        Expr::Pipe(lhs, rhs) => {
+            let env = env.clone();
+            then(eval(lhs, &env, input), move |value| eval(rhs, &env, value))
+        }
+        Expr::Format(name) => single(format::apply(name, &input).map(Value::String)),
+        Expr::Interpolate(name, parts) => {
+            let name = name.as_deref().unwrap_or("text");
+            interpolate(name, parts, env.clone(), input, String::new())
+        }
+    }
+}
+
+// `lhs op rhs`: for each output of the right side, every left side, as in
+// jq. The last run of the left side gets the input itself rather than a copy,
+// so that `. + [$x]` appends in place when nothing else holds the array.
+fn binary<'a>(
+    op: BinaryOp,
+    lhs: &'a Expr,
+    rhs: &'a Expr,
+    env: Env<'a>,
+    input: Value,
+) -> Outputs<'a> {
+    let mut rhs_outputs = Some(eval(rhs, &env, input.clone()).peekable());
+    let mut input = Some(input);
+    let mut outputs: Outputs<'a> = Box::new(iter::empty());
+    Box::new(iter::from_fn(move || loop {
+        if let Some(output) = outputs.next() {
+            return Some(output);
+        }
+        let rhs_values = rhs_outputs.as_mut()?;
+        let rhs = match rhs_values.next()? {
+            Ok(rhs) => rhs,
+            Err(e) => return Some(Err(e)),
+        };
+        let lhs_input = match rhs_values.peek() {
+            Some(_) => input.clone(),
+            None => {
+                rhs_outputs = None;
+                input.take()
+            }
+        }?;
+        outputs = Box::new(
+            eval(lhs, &env, lhs_input)
+                .map(move |lhs| lhs.and_then(|lhs| ops::binary(op, lhs, rhs.clone()))),
+        );
+    }))
+}
+
+// Calls the innermost function in scope with that name and arity, then the
+// builtins written in jq, then the native ones.
+fn call<'a>(name: &'a str, args: &'a [Expr], env: &Env<'a>, input: Value) -> Outputs<'a> {
+    match env.function(name, args.len()) {
+        Some(Function::Def(def, scope)) => {
+            let (def, scope, env) = (*def, scope.clone(), env.clone());
+            return nested(move || apply(def, &scope, args, &env, input));
+        }
+        Some(Function::Arg(arg, scope)) => return eval(arg, scope, input),
+        None => {}
+    }
+    if let Some(def) = builtins::definition(name, args.len()) {
+        let env = env.clone();
+        return nested(move || apply(def, &Env::default(), args, &env, input));
+    }
+    match builtins::lookup(name, args.len()) {
+        Some(builtin) => builtin(args, env, input),
+        // calls are resolved when parsing
+        None => single(Err(RuntimeError::msg(format!(
+            "{}/{} is not defined",
+            name,
+            args.len()
+        )))),
+    }
+}
+
+// Outputs of a call, started on the first output wanted. Evaluating them
+// counts as one level of nesting for the calls within, so that recursion
+// deeper than `MAX_CALL_DEPTH` fails, and grows the stack when it runs low.
+fn nested<'a>(start: impl FnOnce() -> Outputs<'a> + 'a) -> Outputs<'a> {
+    let mut start = Some(start);
+    let mut outputs = NestedOutputs(None);
+    let mut failed = false;
+    Box::new(iter::from_fn(move || {
+        let depth = CALL_DEPTH.get();
+        if failed {
+            return None;
+        } else if depth > MAX_CALL_DEPTH {
+            failed = true;
+            return Some(Err(RuntimeError::msg(
+                "Exceeds depth limit for function calls",
+            )));
+        }
+        CALL_DEPTH.set(depth + 1);
+        let output = stacker::maybe_grow(STACK_RED_ZONE, STACK_SEGMENT, || match &mut outputs.0 {
+            Some(outputs) => outputs.next(),
+            None => outputs.0.insert(start.take()?()).next(),
+        });
+        CALL_DEPTH.set(depth);
+        output
+    }))
+}
+
+// The outputs of a call, which drop the calls nested in them on a grown stack
+// as well.
+struct NestedOutputs<'a>(Option<Outputs<'a>>);
+
+impl Drop for NestedOutputs<'_> {
+    fn drop(&mut self) {
+        let outputs = self.0.take();
+        stacker::maybe_grow(STACK_RED_ZONE, STACK_SEGMENT, || drop(outputs));
+    }
+}
+
+// Runs the body of `def` in the scope it was defined in, with the function
+// bound again for recursive calls and the arguments bound to the parameters.
+// Arguments are closures over the caller's scope.
+fn apply<'a>(
+    def: &'a FuncDef,
+    scope: &Env<'a>,
+    args: &'a [Expr],
+    env: &Env<'a>,
+    input: Value,
+) -> Outputs<'a> {
+    let mut body_scope = scope.bind_function(&def.name, Function::Def(def, scope.clone()));
+    for (param, arg) in def.params.iter().zip(args) {
+        // a filter parameter passed on is bound to its own argument, rather
+        // than to a closure that calls it, so that recursion doesn't build up
+        // a chain of closures to go through
+        let function = match arg {
+            Expr::Call(name, call_args) if call_args.is_empty() => match env.function(name, 0) {
+                Some(function @ Function::Arg(_, _)) => function.clone(),
+                _ => Function::Arg(arg, env.clone()),
+            },
+            _ => Function::Arg(arg, env.clone()),
+        };
+        body_scope = body_scope.bind_function(param.name(), function);
+    }
+    bind_values(&def.params, args, env.clone(), body_scope, &def.body, input)
+}
+
+// `def f($x): body` is `def f(x): x as $x | body`, the first value parameter
+// varying slowest
+fn bind_values<'a>(
+    params: &'a [Param],
+    args: &'a [Expr],
+    env: Env<'a>,
+    scope: Env<'a>,
+    body: &'a Expr,
+    input: Value,
+) -> Outputs<'a> {
+    let Some(i) = params.iter().position(|p| matches!(p, Param::Value(_))) else {
+        return eval(body, &scope, input);
+    };
+    let (name, rest, rest_args) = (params[i].name(), &params[i + 1..], &args[i + 1..]);
+    let body_input = input.clone();
+    then(eval(&args[i], &env, input), move |value| {
+        bind_values(
+            rest,
+            rest_args,
+            env.clone(),
+            scope.bind(name, value),
+            body,
+            body_input.clone(),
+        )
+    })
+}
+
+// `a // b`, errors of `a` count as false outputs
+fn alternative<'a>(lhs: &'a Expr, rhs: &'a Expr, env: Env<'a>, input: Value) -> Outputs<'a> {
+    let mut lhs_outputs = Some(eval(lhs, &env, input.clone()));
+    let mut rhs_outputs: Option<Outputs<'a>> = None;
+    let mut found = false;
+    Box::new(iter::from_fn(move || {
+        if let Some(outputs) = &mut lhs_outputs {
+            for output in outputs.by_ref() {
+                match output {
+                    Ok(value) if value.is_truthy() => {
+                        found = true;
+                        return Some(Ok(value));
+                    }
+                    _ => {}
+                }
+            }
+            lhs_outputs = None;
+            if !found {
+                rhs_outputs = Some(eval(rhs, &env, input.clone()));
+            }
+        }
+        rhs_outputs.as_mut()?.next()
+    }))
+}
+
+// `lhs |= rhs` updates the input once. The other assignments update it for
+// each output of `rhs` on the input, like `binary` moving the input into the
+// last update.
+fn assign<'a>(
+    op: AssignOp,
+    lhs: &'a Expr,
+    rhs: &'a Expr,
+    env: Env<'a>,
+    input: Value,
+) -> Outputs<'a> {
+    if op == AssignOp::Update {
+        return single(modify(lhs, &env, input, |value| {
+            eval(rhs, &env, value).next().transpose()
+        }));
+    }
+    let mut rhs_outputs = Some(eval(rhs, &env, input.clone()).peekable());
+    let mut input = Some(input);
+    Box::new(iter::from_fn(move || {
+        let rhs_values = rhs_outputs.as_mut()?;
+        let rhs = match rhs_values.next()? {
+            Ok(rhs) => rhs,
+            Err(e) => return Some(Err(e)),
+        };
+        let input = match rhs_values.peek() {
+            Some(_) => input.clone(),
+            None => {
+                rhs_outputs = None;
+                input.take()
+            }
+        }?;
+        Some(modify(lhs, &env, input, |value| {
+            Ok(Some(match op {
+                AssignOp::Arithmetic(op) => ops::binary(op, value, rhs.clone())?,
+                AssignOp::Alternative if value.is_truthy() => value,
+                _ => rhs.clone(),
+            }))
+        }))
+    }))
+}
+
+// Replaces the value at each path of `lhs` in the input with `f` of it, or
+// deletes it if `f` gives `None`. The paths are all found before the input
+// changes, and deleted paths are removed last, so that deleting an element
+// doesn't shift the ones after it. Values are moved into `f` rather than
+// copied, so updates to arrays and objects that nothing else holds are made
+// in place.
+fn modify<'a>(
+    lhs: &'a Expr,
+    env: &Env<'a>,
+    input: Value,
+    mut f: impl FnMut(Value) -> Result<Option<Value>, RuntimeError>,
+) -> Result<Value, RuntimeError> {
+    let paths = path::paths(lhs, env, input.clone()).collect::<Result<Vec<_>, _>>()?;
+    let mut root = input;
+    let mut deleted = Vec::new();
+    for path in paths {
+        let mut keep = true;
+        root = path::update_path(root, &path, |value| {
+            Ok(f(value)?.unwrap_or_else(|| {
+                keep = false;
+                Value::Null
+            }))
+        })?;
+        if !keep {
+            deleted.push(path);
+        }
+    }
+    path::delete_paths(root, deleted)
+}
+
+// `a and b` and `a or b`: an output of `a` whose truthiness is `short_circuit`
+// decides the result on its own, otherwise each output of `b` does
+fn logical<'a>(
+    lhs: &'a Expr,
+    rhs: &'a Expr,
+    env: Env<'a>,
+    input: Value,
+    short_circuit: bool,
+) -> Outputs<'a> {
+    let rhs_input = input.clone();
+    then(eval(lhs, &env, input), move |lhs| {
+        if lhs.is_truthy() == short_circuit {
+            return single(Ok(Value::Boolean(short_circuit)));
+        }
+        Box::new(
+            eval(rhs, &env, rhs_input.clone())
+                .map(|rhs| rhs.map(|rhs| Value::Boolean(rhs.is_truthy()))),
+        )
+    })
+}
+
+// `source as patterns | body`: the body runs with `.` unchanged for each
+// output of the source, with the variables of the patterns bound to its parts
+fn bind<'a>(
+    source: &'a Expr,
+    patterns: &'a [Pattern],
+    body: &'a Expr,
+    env: &Env<'a>,
+    input: Value,
+) -> Outputs<'a> {
+    let scope = pattern_scope(patterns, env);
+    let body_input = input.clone();
+    then(eval(source, env, input), move |value| {
+        alternatives(patterns, body, &scope, value, body_input.clone())
+    })
+}
+
+// Runs the body with the first pattern. A pattern that doesn't match, or an
+// error in its body, moves on to the next one. The errors of the last pattern
+// are reported.
+fn alternatives<'a>(
+    patterns: &'a [Pattern],
+    body: &'a Expr,
+    env: &Env<'a>,
+    value: Value,
+    input: Value,
+) -> Outputs<'a> {
+    let Some((pattern, rest)) = patterns.split_first() else {
+        return Box::new(iter::empty());
+    };
+    let outputs: Outputs<'a> = match destructure(pattern, env, &value, &input) {
+        Ok(scopes) => {
+            let input = input.clone();
+            Box::new(
+                scopes
+                    .into_iter()
+                    .flat_map(move |scope| eval(body, &scope, input.clone())),
+            )
+        }
+        Err(e) if rest.is_empty() => single(Err(e)),
+        Err(_) => return alternatives(rest, body, env, value, input),
+    };
+    if rest.is_empty() {
+        return outputs;
+    }
+
+    let env = env.clone();
+    let mut outputs = Some(outputs);
+    let mut fallback: Option<Outputs<'a>> = None;
+    Box::new(iter::from_fn(move || {
+        if let Some(current) = &mut outputs {
+            match current.next() {
+                Some(Err(_)) => {
+                    outputs = None;
+                    fallback = Some(alternatives(rest, body, &env, value.clone(), input.clone()));
+                }
+                output => return output,
+            }
+        }
+        fallback.as_mut()?.next()
+    }))
+}
+
+// `reduce source as patterns (init; update)`: each output of the source runs
+// the update on the accumulator, whose last output is the next accumulator, or
+// `null` if there is none. The accumulator is moved into the update rather than
+// copied, so the update can change arrays and objects in place.
+fn reduce<'a>(
+    source: &'a Expr,
+    patterns: &'a [Pattern],
+    update: &'a Expr,
+    env: &Env<'a>,
+    input: Value,
+    init: Value,
+) -> Result<Value, RuntimeError> {
+    let outer = pattern_scope(patterns, env);
+    let mut acc = init;
+    for value in eval(source, env, input.clone()) {
+        for scope in first_match(patterns, &outer, &value?, &input)? {
+            let mut next = Value::Null;
+            for output in eval(update, &scope, acc) {
+                next = output?;
+            }
+            acc = next;
+        }
+    }
+    Ok(acc)
+}
+
+// `foreach source as patterns (init; update; extract)`: like `reduce`, but
+// every output of the update is a state that goes through the extract.
+fn foreach<'a>(
+    source: &'a Expr,
+    patterns: &'a [Pattern],
+    update: &'a Expr,
+    extract: Option<&'a Expr>,
+    env: &Env<'a>,
+    input: Value,
+    init: Value,
+) -> Outputs<'a> {
+    let outer = pattern_scope(patterns, env);
+    let mut values = eval(source, env, input.clone());
+    let mut scopes = Vec::new().into_iter();
+    let mut scope = outer.clone();
+    let mut updates: Outputs<'a> = Box::new(iter::empty());
+    let mut extracted: Outputs<'a> = Box::new(iter::empty());
+    let mut state = init;
+    Box::new(iter::from_fn(move || loop {
+        if let Some(output) = extracted.next() {
+            return Some(output);
+        }
+        match updates.next() {
+            Some(Ok(value)) => {
+                state = value.clone();
+                extracted = match extract {
+                    Some(extract) => eval(extract, &scope, value),
+                    None => single(Ok(value)),
+                };
+                continue;
+            }
+            Some(Err(e)) => return Some(Err(e)),
+            None => {}
+        }
+        if let Some(next) = scopes.next() {
+            scope = next;
+            // as in `reduce`, an update without outputs leaves a `null` state
+            let state = std::mem::replace(&mut state, Value::Null);
+            updates = eval(update, &scope, state);
+            continue;
+        }
+        match values
+            .next()?
+            .and_then(|value| first_match(patterns, &outer, &value, &input))
+        {
+            Ok(next) => scopes = next.into_iter(),
+            Err(e) => return Some(Err(e)),
+        }
+    }))
+}
+
+// with `?//`, variables that the matching pattern doesn't bind are `null`
+pub fn pattern_scope<'a>(patterns: &'a [Pattern], env: &Env<'a>) -> Env<'a> {
+    let mut scope = env.clone();
+    if patterns.len() > 1 {
+        for name in patterns.iter().flat_map(Pattern::variables) {
+            scope = scope.bind(name, Value::Null);
+        }
+    }
+    scope
+}
+
+// the scopes of the first pattern that matches `value`, or the error of the last
+pub fn first_match<'a>(
+    patterns: &'a [Pattern],
+    env: &Env<'a>,
+    value: &Value,
+    input: &Value,
+) -> Result<Vec<Env<'a>>, RuntimeError> {
+    let (last, rest) = patterns.split_last().expect("at least one pattern");
+    for pattern in rest {
+        if let Ok(scopes) = destructure(pattern, env, value, input) {
+            return Ok(scopes);
+        }
+    }
+    destructure(last, env, value, input)
+}
+
+// Binds the variables of `pattern` to the parts of `value`, once for each
+// output of the key expressions of object patterns. Those keys are evaluated
+// against the input of the binding, and see the variables bound before them.
+fn destructure<'a>(
+    pattern: &'a Pattern,
+    env: &Env<'a>,
+    value: &Value,
+    input: &Value,
+) -> Result<Vec<Env<'a>>, RuntimeError> {
+    let parts: Vec<(Value, &Pattern)> = match pattern {
+        Pattern::Var(name) => return Ok(vec![env.bind(name, value.clone())]),
+        Pattern::Array(elements) => elements
+            .iter()
+            .enumerate()
+            .map(|(i, element)| Ok((index_value(value, &Value::Number(i as f64))?, element)))
+            .collect::<Result<_, RuntimeError>>()?,
+        Pattern::Object(entries) => {
+            let mut scopes = vec![env.clone()];
+            for (key, pattern) in entries {
+                let mut next = Vec::new();
+                for scope in &scopes {
+                    for key in eval(key, scope, input.clone()) {
+                        let part = index_value(value, &key?)?;
+                        next.extend(destructure(pattern, scope, &part, input)?);
+                    }
+                }
+                scopes = next;
+            }
+            return Ok(scopes);
+        }
+    };
+
+    let mut scopes = vec![env.clone()];
+    for (part, pattern) in parts {
+        let mut next = Vec::new();
+        for scope in &scopes {
+            next.extend(destructure(pattern, scope, &part, input)?);
+        }
+        scopes = next;
+    }
+    Ok(scopes)
+}
+
+// a missing slice bound is `null`
+pub fn eval_bound<'a>(bound: &'a Option<Box<Expr>>, env: &Env<'a>, input: Value) -> Outputs<'a> {
+    match bound {
+        Some(bound) => eval(bound, env, input),
+        None => single(Ok(Value::Null)),
+    }
+}
+
+fn single<'a>(output: Result<Value, RuntimeError>) -> Outputs<'a> {
+    Box::new(iter::once(output))
+}
+
+// feeds each output to `f`, passing errors through
+fn then<'a>(outputs: Outputs<'a>, f: impl Fn(Value) -> Outputs<'a> + 'a) -> Outputs<'a> {
+    Box::new(outputs.flat_map(move |output| match output {
+        Ok(value) => f(value),
+        Err(e) => single(Err(e)),
+    }))
+}
+
+// `.[]`, elements of an array or values of an object
+pub fn iterate<'a>(value: Value) -> Outputs<'a> {
+    match value {
+        Value::Array(elements) => {
+            Box::new((0..elements.len()).map(move |i| Ok(elements[i].clone())))
+        }
+        Value::Object(fields) => Box::new((0..fields.len()).map(move |i| Ok(fields[i].1.clone()))),
+        value => single(Err(RuntimeError::msg(format!(
+            "Cannot iterate over {} ({})",
+            value.type_name(),
+            value
+        )))),
+    }
+}
+
+// `{k: v, ...}` with the entries built so far in `fields`. Every combination
+// of key and value outputs gives an object, the last entry varying fastest.
+fn object<'a>(
+    entries: &'a [(Expr, Expr)],
+    env: Env<'a>,
+    input: Value,
+    fields: Vec<(String, Value)>,
+) -> Outputs<'a> {
+    let Some(((key, value), rest)) = entries.split_first() else {
+        return single(Ok(Value::Object(fields.into())));
+    };
+    let value_input = input.clone();
+    then(eval(key, &env, input.clone()), move |key| {
+        let key = match key {
+            Value::String(key) => key,
+            key => {
+                return single(Err(RuntimeError::msg(format!(
+                    "Object keys must be strings, not {} ({})",
+                    key.type_name(),
+                    key
+                ))))
+            }
+        };
+        let (env, input, fields) = (env.clone(), input.clone(), fields.clone());
+        then(eval(value, &env, value_input.clone()), move |value| {
+            // a repeated key keeps its first position, with the last value
+            let mut fields = fields.clone();
+            match fields.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, v)) => *v = value,
+                None => fields.push((key.clone(), value)),
+            }
+            object(rest, env.clone(), input.clone(), fields)
+        })
+    })
+}
+
+// `"a\(e)b"` with the string after `parts` built so far in `suffix`. Every
+// combination of outputs gives a string, the first interpolation varying
+// fastest, as in jq.
+fn interpolate<'a>(
+    format: &'a str,
+    parts: &'a [StringPart],
+    env: Env<'a>,
+    input: Value,
+    suffix: String,
+) -> Outputs<'a> {
+    let Some((part, rest)) = parts.split_last() else {
+        return single(Ok(Value::String(suffix)));
+    };
+    match part {
+        StringPart::Literal(s) => interpolate(format, rest, env, input, s.clone() + &suffix),
+        StringPart::Expr(expr) => {
+            let rest_input = input.clone();
+            then(eval(expr, &env, input), move |value| {
+                match format::apply(format, &value) {
+                    Ok(piece) => interpolate(
+                        format,
+                        rest,
+                        env.clone(),
+                        rest_input.clone(),
+                        piece + &suffix,
+                    ),
+                    Err(e) => single(Err(e)),
+                }
+            })
+        }
+    }
+}
+
+// `$ENV` is the environment of the process, unless it is shadowed
+fn variable(name: &str, env: &Env) -> Result<Value, RuntimeError> {
+    if let Some(value) = env.get(name) {
+        return Ok(value.clone());
+    }
+    match name {
+        "ENV" => Ok(Value::Object(
+            std::env::vars()
+                .map(|(k, v)| (k, Value::String(v)))
+                .collect::<Vec<_>>()
+                .into(),
+        )),
+        // variables are resolved when parsing
+        _ => Err(RuntimeError::msg(format!("${} is not defined", name))),
+    }
+}
+
+// `.[index]`; missing keys, out of range indices and `null` give `null`
+pub fn index_value(value: &Value, index: &Value) -> Result<Value, RuntimeError> {
+    match (value, index) {
+        (Value::Object(fields), Value::String(key)) => Ok(fields
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.clone())
+            .unwrap_or(Value::Null)),
+        (Value::Array(elements), Value::Number(n)) => {
+            let i = n.floor();
+            let i = if i < 0.0 {
+                i + elements.len() as f64
+            } else {
+                i
+            };
+            Ok(if i < 0.0 {
+                Value::Null
+            } else {
+                elements.get(i as usize).cloned().unwrap_or(Value::Null)
+            })
+        }
+        (Value::Null, Value::String(_) | Value::Number(_)) => Ok(Value::Null),
+        // `{"start": s, "end": e}` is the path component of a slice
+        (Value::Array(_) | Value::String(_) | Value::Null, Value::Object(bounds)) => {
+            let (start, end) = slice_bounds(bounds);
+            slice_value(value, &start, &end)
+        }
+        (value, Value::String(key)) => Err(RuntimeError::msg(format!(
+            "Cannot index {} with \"{}\"",
+            value.type_name(),
+            key
+        ))),
+        (value, index) => Err(RuntimeError::msg(format!(
+            "Cannot index {} with {}",
+            value.type_name(),
+            index.type_name()
+        ))),
+    }
+}
+
+// `.[start:end]` of an array, or of a string counted in codepoints
+pub fn slice_value(value: &Value, start: &Value, end: &Value) -> Result<Value, RuntimeError> {
+    let len = match value {
+        Value::Null => return Ok(Value::Null),
+        Value::Array(elements) => elements.len(),
+        Value::String(s) => s.chars().count(),
+        value => {
+            return Err(RuntimeError::msg(format!(
+                "Cannot index {} with object",
+                value.type_name()
+            )))
+        }
+    };
+    let (start, end) = slice_range(len, start, end)?;
+
+    Ok(match value {
+        Value::Array(elements) => Value::Array(elements[start..end].to_vec().into()),
+        Value::String(s) => Value::String(s.chars().skip(start).take(end - start).collect()),
+        _ => unreachable!(),
+    })
+}
+
+// The range of positions `[start:end]` selects in a sequence of `len`.
+// Negative bounds count from the end, and `null` bounds are the start or the
+// end.
+pub fn slice_range(len: usize, start: &Value, end: &Value) -> Result<(usize, usize), RuntimeError> {
+    let bound = |bound: &Value, default: f64| match bound {
+        Value::Null => Ok(default),
+        Value::Number(n) if *n < 0.0 => Ok((n + len as f64).max(0.0)),
+        Value::Number(n) => Ok(n.min(len as f64)),
+        _ => Err(RuntimeError::msg(
+            "Start and end indices of an array slice must be numbers",
+        )),
+    };
+    // fractional bounds widen the slice, as in jq
+    let start = bound(start, 0.0)?.floor() as usize;
+    let end = (bound(end, len as f64)?.ceil() as usize).max(start);
+    Ok((start, end))
+}
+
+// bounds of `{"start": s, "end": e}`, the path component of a slice
+pub fn slice_bounds(component: &[(String, Value)]) -> (Value, Value) {
+    let bound = |name: &str| {
+        component
+            .iter()
+            .find(|(k, _)| k == name)
+            .map_or(Value::Null, |(_, v)| v.clone())
+    };
+    (bound("start"), bound("end"))
+}
+
+// Error raised while running a filter. jq errors are arbitrary values,
+// usually a message string.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError {
+    value: Value,
+}
+
+impl RuntimeError {
+    pub fn new(value: Value) -> RuntimeError {
+        RuntimeError { value }
+    }
+
+    pub fn msg(message: impl Into<String>) -> RuntimeError {
+        RuntimeError::new(Value::String(message.into()))
+    }
+
+    pub fn value(&self) -> &Value {
+        &self.value
+    }
+
+    pub fn into_value(self) -> Value {
+        self.value
+    }
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.value {
+            Value::String(message) => write!(f, "{}", message),
+            value => write!(f, "{}", value),
+        }
+    }
+}
+
+impl Error for RuntimeError {}
+
+#[cfg(test)]
+mod eval {
+    use crate::filter::Filter;
+
+    #[test]
+    fn test_eval_identity_and_fields() {
+        let input = r#"{"a": {"b c": [1, 2]}, "n": null}"#;
+        assert_eq!(
+            run(".", input),
+            Ok(vec![r#"{"a":{"b c":[1,2]},"n":null}"#.to_string()])
+        );
+        assert_eq!(run(r#".a."b c""#, input), Ok(vec!["[1,2]".to_string()]));
+        assert_eq!(
+            run(r#".["a"] | .["b c"]"#, input),
+            Ok(vec!["[1,2]".to_string()])
+        );
+        assert_eq!(run(".missing", input), Ok(vec!["null".to_string()]));
+        assert_eq!(run(".n.x.y", input), Ok(vec!["null".to_string()]));
+    }
+
+    #[test]
+    fn test_eval_array_index() {
+        let input = "[10, 20, 30]";
+        assert_eq!(run(".[0]", input), Ok(vec!["10".to_string()]));
+        assert_eq!(run(".[-1]", input), Ok(vec!["30".to_string()]));
+        assert_eq!(run(".[1.7]", input), Ok(vec!["20".to_string()]));
+        assert_eq!(run(".[3]", input), Ok(vec!["null".to_string()]));
+        assert_eq!(run(".[-4]", input), Ok(vec!["null".to_string()]));
+    }
+
+    #[test]
+    fn test_eval_errors() {
+        assert_eq!(
+            run(".foo", "1"),
+            Err("Cannot index number with \"foo\"".to_string())
+        );
+        assert_eq!(
+            run(".[0]", "{}"),
+            Err("Cannot index object with number".to_string())
+        );
+        assert_eq!(
+            run(".a | -.", r#"{"a": "x"}"#),
+            Err("string (\"x\") cannot be negated".to_string())
+        );
+    }
+
+    #[test]
+    fn test_eval_generators() {
+        let input = r#"{"a": [1, 2], "b": {"x": true, "y": null}}"#;
+        assert_eq!(
+            run(".a[], .b[]", input),
+            Ok(vec!["1", "2", "true", "null"]
+                .into_iter()
+                .map(String::from)
+                .collect())
+        );
+        assert_eq!(
+            run("[.a[], .b[]]", input),
+            Ok(vec!["[1,2,true,null]".to_string()])
+        );
+        assert_eq!(
+            run("[.[][]]", input),
+            Ok(vec!["[1,2,true,null]".to_string()])
+        );
+        assert_eq!(run("[]", input), Ok(vec!["[]".to_string()]));
+        assert_eq!(run("empty", input), Ok(vec![]));
+        assert_eq!(run("[.a[], empty]", input), Ok(vec!["[1,2]".to_string()]));
+        assert_eq!(
+            run(".a[1, 0]", input),
+            Ok(vec!["2".to_string(), "1".to_string()])
+        );
+        assert_eq!(
+            run("[[1, 2], [3, 4]] | [.[][0, 1]]", input),
+            Ok(vec!["[1,3,2,4]".to_string()])
+        );
+        assert_eq!(
+            run(".a | .[]", "{\"a\": 1}"),
+            Err("Cannot iterate over number (1)".to_string())
+        );
+    }
+
+    #[test]
+    fn test_eval_arithmetic() {
+        let input = r#"{"a": 7, "b": 2, "s": "x,y", "o": {"k": 1}}"#;
+        assert_eq!(
+            run(".a + .b * 3, .a % .b, -.a / .b", input),
+            Ok(vec!["13", "1", "-3.5"]
+                .into_iter()
+                .map(String::from)
+                .collect())
+        );
+        assert_eq!(
+            run(r#".s / "," | .[1] * 2"#, input),
+            Ok(vec![r#""yy""#.to_string()])
+        );
+        assert_eq!(
+            run(r#".o + {"k": 2, "l": 3} | .missing + ."#, input),
+            Ok(vec![r#"{"k":2,"l":3}"#.to_string()])
+        );
+        assert_eq!(
+            run("[(1, 2) + (10, 20)]", input),
+            Ok(vec!["[11,12,21,22]".to_string()])
+        );
+        assert_eq!(
+            run(".o - 1", input),
+            Err(r#"object ({"k":1}) and number (1) cannot be subtracted"#.to_string())
+        );
+    }
+
+    #[test]
+    fn test_eval_logic() {
+        let input = r#"{"a": 1, "b": null, "c": [1, 2]}"#;
+        assert_eq!(
+            run(".a == 1, .a != 1, .c > .a, .b < false", input),
+            Ok(vec!["true", "false", "true", "true"]
+                .into_iter()
+                .map(String::from)
+                .collect())
+        );
+        assert_eq!(
+            run(".a and .c, .b or false, (.b | not), (.a | not)", input),
+            Ok(vec!["true", "false", "true", "false"]
+                .into_iter()
+                .map(String::from)
+                .collect())
+        );
+        // the right side is not evaluated when the left side decides
+        assert_eq!(
+            run(".b and (1 / 0), .a or (1 / 0)", input),
+            Ok(vec!["false".to_string(), "true".to_string()])
+        );
+        assert_eq!(
+            run("[(true, false) and (true, false)]", input),
+            Ok(vec!["[true,false,false]".to_string()])
+        );
+        assert_eq!(
+            run("[(true, false) or (true, false)]", input),
+            Ok(vec!["[true,true,false]".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_eval_bindings() {
+        let input = r#"{"a": [1, {"b": 2, "d": 3}], "k": "b"}"#;
+        assert_eq!(
+            run(".a[0] as $x | .a[1] | .b + $x", input),
+            Ok(vec!["3".to_string()])
+        );
+        assert_eq!(
+            run(".a as [$x, {b: $y, $d}] | [$x, $y, $d]", input),
+            Ok(vec!["[1,2,3]".to_string()])
+        );
+        assert_eq!(
+            run(".k as $k | .a as [$_, {($k): $v}] | $v", input),
+            Ok(vec!["2".to_string()])
+        );
+        assert_eq!(
+            run(
+                r#". as {"a": [$first], $k: $key} | [$first, $k, $key]"#,
+                input
+            ),
+            Ok(vec![r#"[1,"b","b"]"#.to_string()])
+        );
+        // one run of the body per output of the source
+        assert_eq!(
+            run("[(1, 2) as $x | $x * 10]", input),
+            Ok(vec!["[10,20]".to_string()])
+        );
+        // inner bindings shadow outer ones, and the input is unchanged
+        assert_eq!(
+            run("1 as $x | [2 as $x | $x, .k], $x", input),
+            Ok(vec![r#"[2,"b"]"#.to_string(), "1".to_string()])
+        );
+        assert_eq!(
+            run(". as [$a] | $a", input),
+            Err("Cannot index object with number".to_string())
+        );
+        assert_eq!(run(". as [$a] | $a", "null"), Ok(vec!["null".to_string()]));
+    }
+
+    #[test]
+    fn test_eval_destructuring_alternatives() {
+        let filter = ".[] as [$a] ?// {a: $a} ?// $a | [$a]";
+        assert_eq!(
+            run(filter, r#"[[1], {"a": 2}, 3]"#),
+            Ok(vec!["[1]", "[2]", "[3]"]
+                .into_iter()
+                .map(String::from)
+                .collect())
+        );
+        // variables of the other patterns are null
+        assert_eq!(
+            run(". as [$a] ?// {$b} | [$a, $b]", r#"{"b": 1}"#),
+            Ok(vec!["[null,1]".to_string()])
+        );
+        // an error in the body tries the next pattern, the last one reports it
+        assert_eq!(
+            run(
+                r#".[] as [$a] ?// $a | if $a == 3 then error("three") else $a end"#,
+                "[[3]]"
+            ),
+            Ok(vec!["[3]".to_string()])
+        );
+        assert_eq!(
+            run(".[] as [$a] ?// $a | error($a)", "[[3]]"),
+            Err("[3]".to_string())
+        );
+    }
+
+    #[test]
+    fn test_eval_reduce() {
+        let input = r#"[{"k": "a", "n": 1}, {"k": "b", "n": 2}, {"k": "a", "n": 3}]"#;
+        assert_eq!(
+            run("reduce .[] as $r (0; . + $r.n)", input),
+            Ok(vec!["6".to_string()])
+        );
+        assert_eq!(
+            run("reduce .[] as {$k, $n} ({}; . + {($k): $n})", input),
+            Ok(vec![r#"{"a":3,"b":2}"#.to_string()])
+        );
+        assert_eq!(
+            run("reduce .[].n as $n ([]; [$n] + .)", input),
+            Ok(vec!["[3,2,1]".to_string()])
+        );
+        // one reduction per output of the init, and the last output of the
+        // update is the next accumulator
+        assert_eq!(
+            run("[reduce (1, 2) as $x (0, 10; . + $x)]", input),
+            Ok(vec!["[3,13]".to_string()])
+        );
+        assert_eq!(
+            run("reduce (1, 2) as $x (1; (. + $x, . * 10))", input),
+            Ok(vec!["100".to_string()])
+        );
+        assert_eq!(
+            run(
+                "reduce empty as $x (1; 2), reduce 1 as $x (0; empty)",
+                input
+            ),
+            Ok(vec!["1".to_string(), "null".to_string()])
+        );
+        assert_eq!(
+            run("reduce .[] as [$x] (0; . + $x)", input),
+            Err("Cannot index object with number".to_string())
+        );
+        assert_eq!(
+            run("reduce .[] as $r (0; . + $r.k)", input),
+            Err(r#"number (0) and string ("a") cannot be added"#.to_string())
+        );
+    }
+
+    #[test]
+    fn test_eval_foreach() {
+        let input = "[1, 2, 3]";
+        assert_eq!(
+            run("[foreach .[] as $x (0; . + $x)]", input),
+            Ok(vec!["[1,3,6]".to_string()])
+        );
+        assert_eq!(
+            run("[foreach .[] as $x (0; . + $x; [$x, .])]", input),
+            Ok(vec!["[[1,1],[2,3],[3,6]]".to_string()])
+        );
+        // every output of the update is a state, the last one is kept
+        assert_eq!(
+            run("[foreach (1, 2) as $x (0; (. + $x, . - $x))]", input),
+            Ok(vec!["[1,-1,1,-3]".to_string()])
+        );
+        assert_eq!(
+            run(
+                "[foreach .[] as $x (0; if $x == 2 then empty else . end)]",
+                input
+            ),
+            Ok(vec!["[0,null]".to_string()])
+        );
+        assert_eq!(
+            run("[foreach .[] as $x (0, 10; . + $x; -.)]", input),
+            Ok(vec!["[-1,-3,-6,-11,-13,-16]".to_string()])
+        );
+        assert_eq!(
+            run("[foreach .[] as [$a] ?// $a (0; . + $a)]", "[[1], 2]"),
+            Ok(vec!["[1,3]".to_string()])
+        );
+
+        // states are produced as the source is consumed
+        let filter = Filter::parse("foreach .[] as $x (0; . + $x.n)").unwrap();
+        let mut outputs = filter.run(crate::parse_str(r#"[{"n": 1}, 2]"#).unwrap());
+        assert_eq!(outputs.next(), Some(Ok(crate::Value::Number(1.0))));
+        assert!(outputs.next().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_eval_definitions() {
+        let input = "[1, 2, 3]";
+        assert_eq!(
+            run("def double: . * 2; map(double)", input),
+            Ok(vec!["[2,4,6]".to_string()])
+        );
+        // filter parameters run on the input of each call, value parameters
+        // are bound once per output of the argument
+        assert_eq!(
+            run("def f(g): [g, (10 | g)]; f(. + 1)", "1"),
+            Ok(vec!["[2,11]".to_string()])
+        );
+        assert_eq!(
+            run("def f($a; $b): [$a, $b]; [f(1, 2; 3, 4)]", "null"),
+            Ok(vec!["[[1,3],[1,4],[2,3],[2,4]]".to_string()])
+        );
+        assert_eq!(
+            run("def f($a): [$a, a]; [f(1, 2)]", "null"),
+            Ok(vec!["[[1,1,2],[2,1,2]]".to_string()])
+        );
+        // overloading on arity, and recursion
+        assert_eq!(
+            run("def f: 1; def f(g): g + f; f(10)", "null"),
+            Ok(vec!["11".to_string()])
+        );
+        assert_eq!(
+            run(
+                "def fac: if . <= 1 then 1 else . * (. - 1 | fac) end; [.[] | fac]",
+                "[1, 5, 10]"
+            ),
+            Ok(vec!["[1,120,3628800]".to_string()])
+        );
+        assert_eq!(
+            run("def f: if . < 1000 then . + 1 | f else . end; f", "0"),
+            Ok(vec!["1000".to_string()])
+        );
+        // definitions see the variables and functions of their own scope,
+        // not those of the caller
+        assert_eq!(
+            run("1 as $x | def f: $x; 2 as $x | [f, $x]", "null"),
+            Ok(vec!["[1,2]".to_string()])
+        );
+        assert_eq!(
+            run("def g: 1; def f: g; def g: 2; [f, g]", "null"),
+            Ok(vec!["[1,2]".to_string()])
+        );
+        assert_eq!(
+            run("def f(g): def h: g; 5 | h; def g: 0; f(g, . * 2)", "1"),
+            Ok(vec!["0".to_string(), "10".to_string()])
+        );
+        assert_eq!(
+            run("def f: def g: 3; g * 2; f", "null"),
+            Ok(vec!["6".to_string()])
+        );
+        assert_eq!(
+            run("def map(f): \"mine\"; map(.)", input),
+            Ok(vec![r#""mine""#.to_string()])
+        );
+    }
+
+    #[test]
+    fn test_eval_recursion_deep() {
+        // as deep as jq goes, on a stack smaller than the depth limit needs
+        let deep = std::thread::Builder::new()
+            .stack_size(1 << 20)
+            .spawn(|| {
+                assert_eq!(
+                    run("def f: if . < 100000 then . + 1 | f else . end; f", "0"),
+                    Ok(vec!["100000".to_string()])
+                );
+                assert_eq!(
+                    run(
+                        "def f(g): if . < 100000 then g | f(g) else . end; f(. + 1)",
+                        "0"
+                    ),
+                    Ok(vec!["100000".to_string()])
+                );
+                assert_eq!(
+                    run("def f: f; f", "null"),
+                    Err("Exceeds depth limit for function calls".to_string())
+                );
+                assert_eq!(
+                    run("def f: [f]; try f catch .", "null"),
+                    Ok(vec![
+                        r#""Exceeds depth limit for function calls""#.to_string()
+                    ])
+                );
+            })
+            .unwrap();
+        deep.join().unwrap();
+    }
+
+    #[test]
+    fn test_eval_prelude() {
+        let input = r#"{"a": [{"b": 1}, 2], "c": null}"#;
+        assert_eq!(
+            run(".a | map(. == 2)", input),
+            Ok(vec!["[false,true]".to_string()])
+        );
+        assert_eq!(
+            run("[.a[] | select(. == 2, true)]", input),
+            Ok(vec![r#"[{"b":1},2,2]"#.to_string()])
+        );
+        assert_eq!(
+            run("[recurse]", input),
+            Ok(vec![
+                r#"[{"a":[{"b":1},2],"c":null},[{"b":1},2],{"b":1},1,2,null]"#.to_string()
+            ])
+        );
+        assert_eq!(
+            run("[2 | recurse(. * .; . < 100)]", input),
+            Ok(vec!["[2,4,16]".to_string()])
+        );
+        assert_eq!(
+            run("[.a | recurse(.[0]?)]", input),
+            Ok(vec![r#"[[{"b":1},2],{"b":1}]"#.to_string()])
+        );
+        assert_eq!(
+            run("map(1)", "3"),
+            Err("Cannot iterate over number (3)".to_string())
+        );
+    }
+
+    #[test]
+    fn test_eval_recurse() {
+        let input = r#"{"a": [1, {"b": 2}], "c": "x"}"#;
+        let expected = r#"[{"a":[1,{"b":2}],"c":"x"},[1,{"b":2}],1,{"b":2},2,"x"]"#;
+        assert_eq!(run("[..]", input), Ok(vec![expected.to_string()]));
+        assert_eq!(run("[recurse]", input), Ok(vec![expected.to_string()]));
+        assert_eq!(
+            run("[recurse(.[]?)]", input),
+            Ok(vec![expected.to_string()])
+        );
+        assert_eq!(
+            run("[recurse(if . < 3 then . + 1, . + 10 else empty end)]", "0"),
+            Ok(vec!["[0,1,2,3,12,11,10]".to_string()])
+        );
+        assert_eq!(
+            run("[recurse(. * .; . < 100)]", "2"),
+            Ok(vec!["[2,4,16]".to_string()])
+        );
+        assert_eq!(
+            run("[recurse(.a)]", r#"{"a": {"a": 1}}"#),
+            Err("Cannot index number with \"a\"".to_string())
+        );
+        // `..` calls whatever `recurse` is in scope
+        assert_eq!(
+            run("def recurse: 1; [..]", input),
+            Ok(vec!["[1]".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_eval_recurse_deep() {
+        // a jq definition of `recurse` would nest a call per level; the
+        // deepest input that parses takes more than the default stack of a
+        // test to build and drop
+        let deep = std::thread::Builder::new()
+            .stack_size(1 << 28)
+            .spawn(|| {
+                let mut value = crate::Value::Number(1.0);
+                for _ in 0..crate::parser::MAX_DEPTH {
+                    value = crate::Value::Array(vec![value].into());
+                }
+                for filter in [
+                    "[..][-1]",
+                    "[recurse(.[0]?)][-1]",
+                    "[recurse(.[0]; . != 1)][-1]",
+                ] {
+                    let outputs: Vec<_> =
+                        Filter::parse(filter).unwrap().run(value.clone()).collect();
+                    assert_eq!(outputs.len(), 1, "{}", filter);
+                }
+            })
+            .unwrap();
+        deep.join().unwrap();
+    }
+
+    #[test]
+    fn test_eval_paths() {
+        let input = r#"{"a": [1, {"b": null}], "c": "x"}"#;
+        assert_eq!(
+            run("[paths]", input),
+            Ok(vec![
+                r#"[["a"],["a",0],["a",1],["a",1,"b"],["c"]]"#.to_string()
+            ])
+        );
+        // as in jq, `null` and `false` leaves don't pass the `select`
+        assert_eq!(
+            run("[leaf_paths]", input),
+            Ok(vec![r#"[["a",0],["c"]]"#.to_string()])
+        );
+        assert_eq!(
+            run(r#"[paths(type == "object")]"#, input),
+            Ok(vec![r#"[["a",1]]"#.to_string()])
+        );
+        assert_eq!(
+            run(r#"[getpath(["a", 1, "b"], ["x", "y"])]"#, input),
+            Ok(vec!["[null,null]".to_string()])
+        );
+        assert_eq!(
+            run(r#"[setpath(["a", 0], ["d"]; 2, 3) | [.a[0], .d]]"#, input),
+            Ok(vec!["[[2,null],[3,null],[1,2],[1,3]]".to_string()])
+        );
+        assert_eq!(
+            run(r#"delpaths([["a", 0], ["c"]])"#, input),
+            Ok(vec![r#"{"a":[{"b":null}]}"#.to_string()])
+        );
+        assert_eq!(
+            run(
+                r#"del(.a[] | select(. == 1)), del(.a, .c), del(.[])"#,
+                input
+            ),
+            Ok(vec![
+                r#"{"a":[{"b":null}],"c":"x"}"#.to_string(),
+                "{}".to_string(),
+                "{}".to_string()
+            ])
+        );
+        // paths reaching the same element delete it once
+        assert_eq!(
+            run(
+                "delpaths([[0], [0]]), del(.[0, 0, 2]), del(.[-1], .[2])",
+                "[0, 1, 2, 3]"
+            ),
+            Ok(vec![
+                "[1,2,3]".to_string(),
+                "[1,3]".to_string(),
+                "[0,1]".to_string()
+            ])
+        );
+        assert_eq!(
+            run("del(.[1:3], .[2]), del(.[1:3], .[1:3])", "[0, 1, 2, 3, 4]"),
+            Ok(vec!["[0,3,4]".to_string(), "[0,3,4]".to_string()])
+        );
+        assert_eq!(
+            run("pick(.a[1].b, .c)", input),
+            Ok(vec![r#"{"a":[null,{"b":null}],"c":"x"}"#.to_string()])
+        );
+        assert_eq!(
+            run("del(1)", input),
+            Err("Invalid path expression with result 1".to_string())
+        );
+        assert_eq!(
+            run(r#"setpath("a"; 1)"#, input),
+            Err("Path must be specified as an array".to_string())
+        );
+        assert_eq!(
+            run("setpath([1e18]; 1)", "null"),
+            Err("Array index too large".to_string())
+        );
+    }
+
+    #[test]
+    fn test_eval_entries() {
+        let input = r#"{"a": 1, "b": [2]}"#;
+        assert_eq!(
+            run("to_entries", input),
+            Ok(vec![
+                r#"[{"key":"a","value":1},{"key":"b","value":[2]}]"#.to_string()
+            ])
+        );
+        assert_eq!(
+            run("to_entries | from_entries", input),
+            Ok(vec![r#"{"a":1,"b":[2]}"#.to_string()])
+        );
+        assert_eq!(
+            run(
+                r#"with_entries(select(.key != "a") | {key: "x", value: .key})"#,
+                input
+            ),
+            Ok(vec![r#"{"x":"b"}"#.to_string()])
+        );
+        assert_eq!(
+            run("[.b | to_entries[].key]", input),
+            Ok(vec!["[0]".to_string()])
+        );
+        assert_eq!(
+            run(
+                "from_entries",
+                r#"[{"k": "a", "v": 1}, {"name": 2, "value": null}, {"key": false}]"#
+            ),
+            Ok(vec![r#"{"a":1,"2":null,"false":null}"#.to_string()])
+        );
+        assert_eq!(
+            run(".a | to_entries", input),
+            Err("number (1) has no keys".to_string())
+        );
+    }
+
+    #[test]
+    fn test_eval_assignments() {
+        let input = r#"{"spec": {"replicas": 1}, "items": [1, 2, 3, 4]}"#;
+        assert_eq!(
+            run(".spec.replicas = 3 | .spec", input),
+            Ok(vec![r#"{"replicas":3}"#.to_string()])
+        );
+        // `=` runs the right side on `.`, `|=` on the old value
+        assert_eq!(
+            run(".spec.replicas = .items[1] | .spec", input),
+            Ok(vec![r#"{"replicas":2}"#.to_string()])
+        );
+        assert_eq!(
+            run(".items[] |= . * 10 | .items", input),
+            Ok(vec!["[10,20,30,40]".to_string()])
+        );
+        assert_eq!(
+            run(".a.b[1] = 1 | .a", input),
+            Ok(vec![r#"{"b":[null,1]}"#.to_string()])
+        );
+        // one output per output of the right side of `=`, only the first
+        // output of `|=` counts
+        assert_eq!(
+            run("[.spec.replicas = (5, 6) | .spec.replicas]", input),
+            Ok(vec!["[5,6]".to_string()])
+        );
+        assert_eq!(
+            run(".spec.replicas |= (5, 6) | .spec", input),
+            Ok(vec![r#"{"replicas":5}"#.to_string()])
+        );
+        // `|= empty` deletes, without shifting the elements still to update
+        assert_eq!(
+            run(".items[] |= empty | .items", input),
+            Ok(vec!["[]".to_string()])
+        );
+        assert_eq!(
+            run("(.items[] | select(. % 2 == 1)) |= empty | .items", input),
+            Ok(vec!["[2,4]".to_string()])
+        );
+        assert_eq!(
+            run("(.[0, 0, -1, 3]) |= empty", "[0, 1, 2, 3]"),
+            Ok(vec!["[1,2]".to_string()])
+        );
+        assert_eq!(
+            run(".items[1:3] |= map(-.) | .items", input),
+            Ok(vec!["[1,-2,-3,4]".to_string()])
+        );
+        assert_eq!(
+            run(
+                ".items[0] += 10 | .items[1] -= 1 | .items[2] *= 2 | .items[3] /= 8 | .items",
+                input
+            ),
+            Ok(vec!["[11,1,6,0.5]".to_string()])
+        );
+        assert_eq!(
+            run(".items[] %= 2 | .items", input),
+            Ok(vec!["[1,0,1,0]".to_string()])
+        );
+        assert_eq!(
+            run(".spec.replicas //= 9 | .spec.extra //= 9 | .spec", input),
+            Ok(vec![r#"{"replicas":1,"extra":9}"#.to_string()])
+        );
+        assert_eq!(
+            run(".items += (.items | .[:1]) | .items", input),
+            Ok(vec!["[1,2,3,4,1]".to_string()])
+        );
+        assert_eq!(
+            run(".spec.replicas += \"x\"", input),
+            Err(r#"number (1) and string ("x") cannot be added"#.to_string())
+        );
+        assert_eq!(
+            run("(.items[0] + 1) = 5", input),
+            Err("Invalid path expression with result 2".to_string())
+        );
+        assert_eq!(
+            run(".[1e18] = 1", "null"),
+            Err("Array index too large".to_string())
+        );
+        assert_eq!(
+            run(".items[1e18] |= 1", input),
+            Err("Array index too large".to_string())
+        );
+        assert_eq!(
+            run(".items[1e18] += 1", input),
+            Err("Array index too large".to_string())
+        );
+    }
+
+    #[test]
+    fn test_eval_try() {
+        let input = r#"[{"a": 1}, "x", {"a": 2}]"#;
+        assert_eq!(run("[.[] | .a?]", input), Ok(vec!["[1,2]".to_string()]));
+        assert_eq!(run("[.[].a?]", input), Ok(vec!["[1]".to_string()]));
+        assert_eq!(
+            run("[.[0][]?, .[1][]?]", input),
+            Ok(vec!["[1]".to_string()])
+        );
+        assert_eq!(
+            run("[.[] | try .a catch \"bad\"]", input),
+            Ok(vec![r#"[1,"bad",2]"#.to_string()])
+        );
+        assert_eq!(
+            run("try (1, error, 2) catch .", "{\"code\": 7}"),
+            Ok(vec!["1".to_string(), r#"{"code":7}"#.to_string()])
+        );
+        assert_eq!(
+            run(r#"try error({"x": .}) catch .x"#, "3"),
+            Ok(vec!["3".to_string()])
+        );
+        // errors after the body are not caught
+        assert_eq!(run("try 1 | error", "null"), Err("1".to_string()));
+        assert_eq!(run(r#"error("x")?, 5"#, "null"), Ok(vec!["5".to_string()]));
+        assert_eq!(run(r#"error("boom")"#, "null"), Err("boom".to_string()));
+    }
+
+    #[test]
+    fn test_eval_alternative() {
+        let input = r#"{"a": null, "b": false, "c": [0, null, 2]}"#;
+        assert_eq!(run(".a // .b // 1", input), Ok(vec!["1".to_string()]));
+        assert_eq!(run("[.c[] // 5]", input), Ok(vec!["[0,2]".to_string()]));
+        assert_eq!(run("[.a // empty]", input), Ok(vec!["[]".to_string()]));
+        assert_eq!(run("(.c.x, .a) // 3", input), Ok(vec!["3".to_string()]));
+        assert_eq!(
+            run("[(.b, 1, error(\"x\"), 2) // 3]", input),
+            Ok(vec!["[1,2]".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_eval_if() {
+        let filter = r#".[] | if . == 0 then "zero" elif . == 1 then "one" else "many" end"#;
+        assert_eq!(
+            run(filter, "[0, 1, 2]"),
+            Ok(vec![r#""zero""#, r#""one""#, r#""many""#]
+                .into_iter()
+                .map(String::from)
+                .collect())
+        );
+        assert_eq!(
+            run(
+                r#"[.[] | if . then "yes" end]"#,
+                r#"[0, "", false, null, []]"#
+            ),
+            Ok(vec![r#"["yes","yes",false,null,"yes"]"#.to_string()])
+        );
+        assert_eq!(
+            run("[if (true, false, true) then 1 else 2 end]", "null"),
+            Ok(vec!["[1,2,1]".to_string()])
+        );
+        assert_eq!(
+            run("if .a then 1 else 2 end", "3"),
+            Err("Cannot index number with \"a\"".to_string())
+        );
+    }
+
+    #[test]
+    fn test_eval_sorting() {
+        let input = r#"[{"a": 2}, null, "b", [1], {"a": 1, "b": 0}, 3, true, "a", 3]"#;
+        assert_eq!(
+            run("sort", input),
+            Ok(vec![
+                r#"[null,true,3,3,"a","b",[1],{"a":2},{"a":1,"b":0}]"#.to_string()
+            ])
+        );
+        assert_eq!(
+            run("unique", input),
+            Ok(vec![
+                r#"[null,true,3,"a","b",[1],{"a":2},{"a":1,"b":0}]"#.to_string()
+            ])
+        );
+        assert_eq!(
+            run(
+                "group_by(.k)",
+                r#"[{"k": "y", "n": 1}, {"k": "x", "n": 2}, {"k": "y", "n": 3}]"#
+            ),
+            Ok(vec![
+                r#"[[{"k":"x","n":2}],[{"k":"y","n":1},{"k":"y","n":3}]]"#.to_string()
+            ])
+        );
+        assert_eq!(
+            run("sort", "{}"),
+            Err("object ({}) cannot be sorted, as it is not an array".to_string())
+        );
+    }
+
+    #[test]
+    fn test_eval_slices() {
+        let input = "[0, 1, 2, 3, 4, 5]";
+        assert_eq!(run(".[2:5]", input), Ok(vec!["[2,3,4]".to_string()]));
+        assert_eq!(run(".[:-1]", input), Ok(vec!["[0,1,2,3,4]".to_string()]));
+        assert_eq!(run(".[3:]", input), Ok(vec!["[3,4,5]".to_string()]));
+        assert_eq!(run(".[-2:]", input), Ok(vec!["[4,5]".to_string()]));
+        assert_eq!(run(".[-10:2]", input), Ok(vec!["[0,1]".to_string()]));
+        assert_eq!(run(".[4:100]", input), Ok(vec!["[4,5]".to_string()]));
+        assert_eq!(run(".[4:2]", input), Ok(vec!["[]".to_string()]));
+        assert_eq!(run(".[1.5:2.5]", input), Ok(vec!["[1,2]".to_string()]));
+        assert_eq!(run(".[null:2]", input), Ok(vec!["[0,1]".to_string()]));
+        assert_eq!(
+            run(".[{\"start\": 1, \"end\": 3}]", input),
+            Ok(vec!["[1,2]".to_string()])
+        );
+        assert_eq!(
+            run("[.[0, 1:3]]", input),
+            Ok(vec!["[[0,1,2],[1,2]]".to_string()])
+        );
+
+        assert_eq!(run(".[1:3]", r#""héllo""#), Ok(vec![r#""él""#.to_string()]));
+        assert_eq!(
+            run(".[-2:]", r#""日本語""#),
+            Ok(vec![r#""本語""#.to_string()])
+        );
+        assert_eq!(run(".[1:]", "null"), Ok(vec!["null".to_string()]));
+
+        assert_eq!(
+            run(".[1:]", "{}"),
+            Err("Cannot index object with object".to_string())
+        );
+        assert_eq!(
+            run(r#".["a":]"#, input),
+            Err("Start and end indices of an array slice must be numbers".to_string())
+        );
+    }
+
+    #[test]
+    fn test_eval_objects() {
+        let input = r#"{"id": 1, "user": {"name": "ann"}, "k": "b", "tags": ["x", "y"]}"#;
+        assert_eq!(
+            run(r#"{id, name: .user.name, (.k): 2, "a b": -.id}"#, input),
+            Ok(vec![r#"{"id":1,"name":"ann","b":2,"a b":-1}"#.to_string()])
+        );
+        assert_eq!(run("{}", input), Ok(vec!["{}".to_string()]));
+        assert_eq!(
+            run("{b: 1, a: 2, b: 3}", input),
+            Ok(vec![r#"{"b":3,"a":2}"#.to_string()])
+        );
+        assert_eq!(
+            run("{id, tag: .tags[]}", input),
+            Ok(vec![
+                r#"{"id":1,"tag":"x"}"#.to_string(),
+                r#"{"id":1,"tag":"y"}"#.to_string(),
+            ])
+        );
+        assert_eq!(
+            run("[{a: (1, 2), (.tags[]): (3, 4)}]", input),
+            Ok(vec![concat!(
+                r#"[{"a":1,"x":3},{"a":1,"x":4},{"a":1,"y":3},{"a":1,"y":4},"#,
+                r#"{"a":2,"x":3},{"a":2,"x":4},{"a":2,"y":3},{"a":2,"y":4}]"#
+            )
+            .to_string()])
+        );
+        assert_eq!(
+            run("{(.id): 1}", input),
+            Err("Object keys must be strings, not number (1)".to_string())
+        );
+    }
+
+    #[test]
+    fn test_eval_env() {
+        std::env::set_var("RUST_JQ_TEST_ENV", "set");
+        assert_eq!(
+            run("$ENV.RUST_JQ_TEST_ENV, {$ENV}.ENV.RUST_JQ_TEST_ENV", "null"),
+            Ok(vec![r#""set""#.to_string(), r#""set""#.to_string()])
+        );
+    }
+
+    #[test]
+    fn test_eval_is_lazy() {
+        // the second element would fail, but is never evaluated
+        let filter = Filter::parse(".[] | .a").unwrap();
+        let mut outputs = filter.run(crate::parse_str(r#"[{"a": 1}, 2]"#).unwrap());
+        assert_eq!(outputs.next(), Some(Ok(crate::Value::Number(1.0))));
+        assert!(outputs.next().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_eval_interpolation() {
+        let input = r#"{"name": "Ann", "n": 2, "tags": ["a", "b"], "row": [1, "x,\"y\"", null]}"#;
+        assert_eq!(
+            run(r#""Hello \(.name), \(.n + 1) \(.tags) \(null)!""#, input),
+            Ok(vec![r#""Hello Ann, 3 [\"a\",\"b\"] null!""#.to_string()])
+        );
+        // the first interpolation varies fastest
+        assert_eq!(
+            run(r#""\(.tags[])\(1, 2)""#, input),
+            Ok(vec![
+                r#""a1""#.to_string(),
+                r#""b1""#.to_string(),
+                r#""a2""#.to_string(),
+                r#""b2""#.to_string(),
+            ])
+        );
+        assert_eq!(run(r#""\(empty)x""#, input), Ok(vec![]));
+        assert_eq!(
+            run(r#"@csv "\(.row);\(.tags)""#, input),
+            Ok(vec![r#""1,\"x,\"\"y\"\"\",;\"a\",\"b\"""#.to_string()])
+        );
+        assert_eq!(
+            run(r#"@sh "echo \(.name, "it's")""#, input),
+            Ok(vec![
+                r#""echo 'Ann'""#.to_string(),
+                r#""echo 'it'\\''s'""#.to_string()
+            ])
+        );
+        assert_eq!(
+            run(
+                r#"@json "x=\(.tags)", (.name | @base64 | ., @base64d)"#,
+                input
+            ),
+            Ok(vec![
+                r#""x=[\"a\",\"b\"]""#.to_string(),
+                r#""QW5u""#.to_string(),
+                r#""Ann""#.to_string()
+            ])
+        );
+        assert_eq!(
+            run(
+                r#"{"x-\(.n)": 1, @base64 "\(.name)": 2, @json: 3} | to_entries[:2]"#,
+                input
+            ),
+            Ok(vec![
+                r#"[{"key":"x-2","value":1},{"key":"QW5u","value":2}]"#.to_string()
+            ])
+        );
+        assert_eq!(
+            run(r#".tags[] as $t | {"\($t)"}"#, r#"{"tags": ["a"], "a": 1}"#),
+            Ok(vec![r#"{"a":1}"#.to_string()])
+        );
+        assert_eq!(
+            run(r#"@csv "\(.name)""#, input),
+            Err(r#"string ("Ann") cannot be csv-formatted, only an array can be"#.to_string())
+        );
+    }
+
+    fn run(filter: &str, input: &str) -> Result<Vec<String>, String> {
+        let filter = Filter::parse(filter).unwrap();
+        filter
+            .run(crate::parse_str(input).unwrap())
+            .map(|output| output.map(|v| v.to_string()).map_err(|e| e.to_string()))
+            .collect()
+    }
+}
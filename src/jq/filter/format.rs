@@ -0,0 +1,322 @@
+use super::RuntimeError;
+use crate::Value;
+
+// converts a value to a string, e.g. `@csv` turns `[1, "a"]` into `1,"a"`
+pub type Format = fn(&Value) -> Result<String, RuntimeError>;
+
+// name without the `@` and implementation of every format
+const FORMATS: &[(&str, Format)] = &[
+    ("text", text),
+    ("json", json),
+    ("html", html),
+    ("uri", uri),
+    ("csv", csv),
+    ("tsv", tsv),
+    ("sh", sh),
+    ("base64", base64),
+    ("base64d", base64d),
+    ("base32", base32),
+    ("base32d", base32d),
+];
+
+pub fn lookup(name: &str) -> Option<Format> {
+    FORMATS.iter().find(|(n, _)| *n == name).map(|(_, f)| *f)
+}
+
+/// Applies the format `@name` to a value.
+pub fn apply(name: &str, value: &Value) -> Result<String, RuntimeError> {
+    match lookup(name) {
+        Some(format) => format(value),
+        // formats are checked when parsing
+        None => Err(RuntimeError::msg(format!(
+            "@{} is not a valid format",
+            name
+        ))),
+    }
+}
+
+// strings as they are, other values as JSON
+pub fn text(value: &Value) -> Result<String, RuntimeError> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        value => Ok(value.to_string()),
+    }
+}
+
+fn json(value: &Value) -> Result<String, RuntimeError> {
+    Ok(value.to_string())
+}
+
+fn html(value: &Value) -> Result<String, RuntimeError> {
+    let mut escaped = String::new();
+    for c in text(value)?.chars() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            '\'' => escaped.push_str("&#39;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    Ok(escaped)
+}
+
+// percent-encodes every byte but the unreserved characters of RFC 3986
+fn uri(value: &Value) -> Result<String, RuntimeError> {
+    let mut escaped = String::new();
+    for byte in text(value)?.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                escaped.push(byte as char)
+            }
+            byte => escaped.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    Ok(escaped)
+}
+
+// strings are quoted, with `"` doubled
+fn csv(value: &Value) -> Result<String, RuntimeError> {
+    row(value, "csv", |s| format!("\"{}\"", s.replace('"', "\"\"")))
+}
+
+// strings are unquoted, with `\`, tabs and line breaks escaped
+fn tsv(value: &Value) -> Result<String, RuntimeError> {
+    row(value, "tsv", |s| {
+        s.replace('\\', "\\\\")
+            .replace('\t', "\\t")
+            .replace('\n', "\\n")
+            .replace('\r', "\\r")
+    })
+}
+
+// an array of scalars as a line of `@csv` or `@tsv`, `null` is an empty cell
+fn row(value: &Value, name: &str, string: fn(&str) -> String) -> Result<String, RuntimeError> {
+    let Value::Array(cells) = value else {
+        return Err(RuntimeError::msg(format!(
+            "{} ({}) cannot be {}-formatted, only an array can be",
+            value.type_name(),
+            value,
+            name
+        )));
+    };
+    let separator = if name == "csv" { "," } else { "\t" };
+    let cells = cells
+        .iter()
+        .map(|cell| match cell {
+            Value::String(s) => Ok(string(s)),
+            Value::Number(_) | Value::Boolean(_) => Ok(cell.to_string()),
+            Value::Null => Ok(String::new()),
+            // jq says csv for both formats
+            cell => Err(RuntimeError::msg(format!(
+                "{} ({}) is not valid in a csv row",
+                cell.type_name(),
+                cell
+            ))),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(cells.join(separator))
+}
+
+// A scalar, or each element of an array separated by spaces, as shell words.
+// Strings are single quoted, with `'` written as `'\''`.
+fn sh(value: &Value) -> Result<String, RuntimeError> {
+    let words = match value {
+        Value::Array(elements) => elements.as_slice(),
+        value => std::slice::from_ref(value),
+    };
+    let words = words
+        .iter()
+        .map(|word| match word {
+            Value::String(s) => Ok(format!("'{}'", s.replace('\'', "'\\''"))),
+            Value::Array(_) | Value::Object(_) => Err(RuntimeError::msg(format!(
+                "{} ({}) can not be escaped for shell",
+                word.type_name(),
+                word
+            ))),
+            word => Ok(word.to_string()),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(words.join(" "))
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const BASE32: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+fn base64(value: &Value) -> Result<String, RuntimeError> {
+    Ok(encode(text(value)?.as_bytes(), BASE64, 6))
+}
+
+fn base64d(value: &Value) -> Result<String, RuntimeError> {
+    decode(value, BASE64, 6, "base64")
+}
+
+fn base32(value: &Value) -> Result<String, RuntimeError> {
+    Ok(encode(text(value)?.as_bytes(), BASE32, 5))
+}
+
+fn base32d(value: &Value) -> Result<String, RuntimeError> {
+    decode(value, BASE32, 5, "base32")
+}
+
+// Encodes `bytes` with `bits` bits per character, padded with `=` to whole
+// groups of 4 characters for base64 and 8 for base32.
+fn encode(bytes: &[u8], alphabet: &[u8], bits: u32) -> String {
+    let mut encoded = String::new();
+    let (mut buffer, mut buffered) = (0u32, 0);
+    for &byte in bytes {
+        buffer = (buffer << 8) | byte as u32;
+        buffered += 8;
+        while buffered >= bits {
+            buffered -= bits;
+            encoded.push(alphabet[((buffer >> buffered) & ((1 << bits) - 1)) as usize] as char);
+        }
+    }
+    if buffered > 0 {
+        let index = (buffer << (bits - buffered)) & ((1 << bits) - 1);
+        encoded.push(alphabet[index as usize] as char);
+    }
+    let group = if bits == 6 { 4 } else { 8 };
+    while !encoded.len().is_multiple_of(group) {
+        encoded.push('=');
+    }
+    encoded
+}
+
+// The inverse of `encode`, padding is optional. Decoded bytes that aren't
+// UTF-8 are replaced, as in jq.
+fn decode(value: &Value, alphabet: &[u8], bits: u32, name: &str) -> Result<String, RuntimeError> {
+    let invalid = || {
+        RuntimeError::msg(format!(
+            "{} ({}) is not valid {} data",
+            value.type_name(),
+            value,
+            name
+        ))
+    };
+    let input = text(value)?;
+    let mut decoded = Vec::new();
+    let (mut buffer, mut buffered) = (0u32, 0);
+    for c in input.trim_end_matches('=').bytes() {
+        let index = alphabet.iter().position(|&a| a == c).ok_or_else(invalid)?;
+        buffer = (buffer << bits) | index as u32;
+        buffered += bits;
+        if buffered >= 8 {
+            buffered -= 8;
+            decoded.push((buffer >> buffered) as u8);
+        }
+    }
+    // leftover bits are padding, a whole leftover character is an error
+    if buffered >= bits {
+        return Err(invalid());
+    }
+    Ok(String::from_utf8_lossy(&decoded).into_owned())
+}
+
+#[cfg(test)]
+mod format {
+    use super::*;
+
+    #[test]
+    fn test_text_and_json() {
+        assert_eq!(apply_str("text", r#""a\"b""#), Ok(r#"a"b"#.to_string()));
+        assert_eq!(
+            apply_str("text", r#"[1, "a"]"#),
+            Ok(r#"[1,"a"]"#.to_string())
+        );
+        assert_eq!(apply_str("json", r#""a\"b""#), Ok(r#""a\"b""#.to_string()));
+        assert_eq!(apply_str("json", "null"), Ok("null".to_string()));
+    }
+
+    #[test]
+    fn test_html_and_uri() {
+        assert_eq!(
+            apply_str("html", r#""<a href='x'>\"Tom\" & Jerry</a>""#),
+            Ok("&lt;a href=&#39;x&#39;&gt;&quot;Tom&quot; &amp; Jerry&lt;/a&gt;".to_string())
+        );
+        assert_eq!(
+            apply_str("uri", r#""a b/c?d=é~_.-""#),
+            Ok("a%20b%2Fc%3Fd%3D%C3%A9~_.-".to_string())
+        );
+    }
+
+    #[test]
+    fn test_csv_and_tsv() {
+        let row = r#"[1.5, "a,b", "say \"hi\"", null, true, "x\ty\\z"]"#;
+        assert_eq!(
+            apply_str("csv", row),
+            Ok(r#"1.5,"a,b","say ""hi""",,true,"x	y\z""#.to_string())
+        );
+        assert_eq!(
+            apply_str("tsv", row),
+            Ok(r#"1.5	a,b	say "hi"		true	x\ty\\z"#.to_string())
+        );
+        assert_eq!(
+            apply_str("csv", r#"{"a": 1}"#),
+            Err(r#"object ({"a":1}) cannot be csv-formatted, only an array can be"#.to_string())
+        );
+        assert_eq!(
+            apply_str("tsv", "[[1]]"),
+            Err("array ([1]) is not valid in a csv row".to_string())
+        );
+    }
+
+    #[test]
+    fn test_sh() {
+        assert_eq!(
+            apply_str("sh", r#""it's $HOME""#),
+            Ok(r#"'it'\''s $HOME'"#.to_string())
+        );
+        assert_eq!(
+            apply_str("sh", r#"["rm", "-rf", "my file", 1, null, false]"#),
+            Ok("'rm' '-rf' 'my file' 1 null false".to_string())
+        );
+        assert_eq!(
+            apply_str("sh", r#"["a", {"b": 1}]"#),
+            Err(r#"object ({"b":1}) can not be escaped for shell"#.to_string())
+        );
+        assert_eq!(
+            apply_str("sh", "[[]]"),
+            Err("array ([]) can not be escaped for shell".to_string())
+        );
+    }
+
+    #[test]
+    fn test_base64_and_base32() {
+        for (plain, base64, base32) in [
+            ("", "", ""),
+            ("f", "Zg==", "MY======"),
+            ("fo", "Zm8=", "MZXQ===="),
+            ("foo", "Zm9v", "MZXW6==="),
+            ("foob", "Zm9vYg==", "MZXW6YQ="),
+            ("fooba", "Zm9vYmE=", "MZXW6YTB"),
+            ("foobar", "Zm9vYmFy", "MZXW6YTBOI======"),
+        ] {
+            let quote = |s: &str| format!("{:?}", s);
+            assert_eq!(apply_str("base64", &quote(plain)), Ok(base64.to_string()));
+            assert_eq!(apply_str("base64d", &quote(base64)), Ok(plain.to_string()));
+            assert_eq!(apply_str("base32", &quote(plain)), Ok(base32.to_string()));
+            assert_eq!(apply_str("base32d", &quote(base32)), Ok(plain.to_string()));
+        }
+        assert_eq!(apply_str("base64", "[1]"), Ok("WzFd".to_string()));
+        assert_eq!(apply_str("base64d", r#""Zm9vYg""#), Ok("foob".to_string()));
+        assert_eq!(
+            apply_str("base64d", r#""Zm9v!""#),
+            Err(r#"string ("Zm9v!") is not valid base64 data"#.to_string())
+        );
+        assert_eq!(
+            apply_str("base64d", r#""Zm9vY""#),
+            Err(r#"string ("Zm9vY") is not valid base64 data"#.to_string())
+        );
+        assert_eq!(
+            apply_str("base32d", r#""mzxw6""#),
+            Err(r#"string ("mzxw6") is not valid base32 data"#.to_string())
+        );
+    }
+
+    fn apply_str(name: &str, value: &str) -> Result<String, String> {
+        let value = crate::parse_str(value).unwrap();
+        apply(name, &value).map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,684 @@
+use std::fmt::Display;
+
+use super::{SyntaxError, SyntaxErrorKind};
+use crate::tokenizer::Span;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    // `.`
+    Dot,
+    // `..`
+    DotDot,
+    // `.foo`
+    Field(String),
+    Ident(String),
+    // `$name`
+    Variable(String),
+    String(String),
+    // `"a\(e)b"`, a string with interpolations
+    InterpolatedString(Vec<Segment>),
+    // `@name`
+    Format(String),
+    Number(f64),
+    Pipe,
+    Comma,
+    Semicolon,
+    Question,
+    // `//`
+    DoubleSlash,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    // `==`, `!=`, `<`, `<=`, `>` and `>=`
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    // `=`, `|=`, `+=`, `-=`, `*=`, `/=`, `%=` and `//=`
+    Assign,
+    PipeAssign,
+    PlusAssign,
+    MinusAssign,
+    StarAssign,
+    SlashAssign,
+    PercentAssign,
+    DoubleSlashAssign,
+    Colon,
+    BracketOpen,
+    BracketClose,
+    BraceOpen,
+    BraceClose,
+    ParenOpen,
+    ParenClose,
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Dot => write!(f, "."),
+            Token::DotDot => write!(f, ".."),
+            Token::Field(name) => write!(f, ".{}", name),
+            Token::Ident(name) => write!(f, "{}", name),
+            Token::Variable(name) => write!(f, "${}", name),
+            Token::String(s) => write!(f, "{:?}", s),
+            Token::InterpolatedString(segments) => {
+                write!(f, "\"")?;
+                for segment in segments {
+                    match segment {
+                        Segment::Literal(s) => write!(f, "{}", s.escape_debug())?,
+                        Segment::Interpolation(_, _) => write!(f, "\\(...)")?,
+                    }
+                }
+                write!(f, "\"")
+            }
+            Token::Format(name) => write!(f, "@{}", name),
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Pipe => write!(f, "|"),
+            Token::Comma => write!(f, ","),
+            Token::Semicolon => write!(f, ";"),
+            Token::Question => write!(f, "?"),
+            Token::DoubleSlash => write!(f, "//"),
+            Token::Plus => write!(f, "+"),
+            Token::Minus => write!(f, "-"),
+            Token::Star => write!(f, "*"),
+            Token::Slash => write!(f, "/"),
+            Token::Percent => write!(f, "%"),
+            Token::Eq => write!(f, "=="),
+            Token::Ne => write!(f, "!="),
+            Token::Lt => write!(f, "<"),
+            Token::Le => write!(f, "<="),
+            Token::Gt => write!(f, ">"),
+            Token::Ge => write!(f, ">="),
+            Token::Assign => write!(f, "="),
+            Token::PipeAssign => write!(f, "|="),
+            Token::PlusAssign => write!(f, "+="),
+            Token::MinusAssign => write!(f, "-="),
+            Token::StarAssign => write!(f, "*="),
+            Token::SlashAssign => write!(f, "/="),
+            Token::PercentAssign => write!(f, "%="),
+            Token::DoubleSlashAssign => write!(f, "//="),
+            Token::Colon => write!(f, ":"),
+            Token::BracketOpen => write!(f, "["),
+            Token::BracketClose => write!(f, "]"),
+            Token::BraceOpen => write!(f, "{{"),
+            Token::BraceClose => write!(f, "}}"),
+            Token::ParenOpen => write!(f, "("),
+            Token::ParenClose => write!(f, ")"),
+        }
+    }
+}
+
+// part of an interpolated string
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment {
+    Literal(String),
+    // tokens between `\(` and `)`, with the span of the `)`
+    Interpolation(Vec<SpannedToken>, Span),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+}
+
+pub fn tokenize(source: &str) -> Result<Vec<SpannedToken>, SyntaxError> {
+    let mut lexer = Lexer {
+        source,
+        pos: 0,
+        line: 1,
+        line_start: 0,
+    };
+    let mut tokens = Vec::new();
+    while let Some(token) = lexer.next_token()? {
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+// zero-width span after the last character, for errors about missing tokens
+pub fn end_of_input(source: &str) -> Span {
+    let line_start = source.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    Span {
+        offset: source.len(),
+        len: 0,
+        line: source.matches('\n').count() + 1,
+        column: source[line_start..].chars().count() + 1,
+    }
+}
+
+struct Lexer<'a> {
+    source: &'a str,
+    // byte offset into `source`, always on a char boundary
+    pos: usize,
+    line: usize,
+    line_start: usize,
+}
+
+impl Lexer<'_> {
+    fn peek_char(&self) -> Option<char> {
+        self.source[self.pos..].chars().next()
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        let c = self.peek_char()?;
+        self.pos += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.line_start = self.pos;
+        }
+        Some(c)
+    }
+
+    // consumes the next char if it is `c`
+    fn eat_char(&mut self, c: char) -> bool {
+        if self.peek_char() == Some(c) {
+            self.next_char();
+            return true;
+        }
+        false
+    }
+
+    // span from `start` to the current position
+    fn span_from(&self, start: usize) -> Span {
+        // strings can span several lines
+        let (line, line_start) = match start >= self.line_start {
+            true => (self.line, self.line_start),
+            false => {
+                let before = &self.source[..start];
+                let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+                (before.matches('\n').count() + 1, line_start)
+            }
+        };
+        Span {
+            offset: start,
+            len: self.pos - start,
+            line,
+            column: self.source[line_start..start].chars().count() + 1,
+        }
+    }
+
+    fn error_at(&self, start: usize, kind: SyntaxErrorKind) -> SyntaxError {
+        SyntaxError::new(self.span_from(start), kind)
+    }
+
+    fn skip_whitespace_and_comments(&mut self) {
+        while let Some(c) = self.peek_char() {
+            match c {
+                '#' => {
+                    while self.peek_char().is_some_and(|c| c != '\n') {
+                        self.next_char();
+                    }
+                }
+                c if c.is_whitespace() => {
+                    self.next_char();
+                }
+                _ => break,
+            }
+        }
+    }
+
+    fn next_token(&mut self) -> Result<Option<SpannedToken>, SyntaxError> {
+        self.skip_whitespace_and_comments();
+
+        let start = self.pos;
+        let Some(c) = self.next_char() else {
+            return Ok(None);
+        };
+        let token = match c {
+            '.' if self.eat_char('.') => Token::DotDot,
+            '.' => match self.peek_char() {
+                Some(c) if is_ident_start(c) => Token::Field(self.read_ident()),
+                _ => Token::Dot,
+            },
+            '|' if self.eat_char('=') => Token::PipeAssign,
+            '|' => Token::Pipe,
+            ',' => Token::Comma,
+            ';' => Token::Semicolon,
+            '?' => Token::Question,
+            '/' if self.eat_char('/') => match self.eat_char('=') {
+                true => Token::DoubleSlashAssign,
+                false => Token::DoubleSlash,
+            },
+            '+' if self.eat_char('=') => Token::PlusAssign,
+            '+' => Token::Plus,
+            '-' if self.eat_char('=') => Token::MinusAssign,
+            '-' => Token::Minus,
+            '*' if self.eat_char('=') => Token::StarAssign,
+            '*' => Token::Star,
+            '/' if self.eat_char('=') => Token::SlashAssign,
+            '/' => Token::Slash,
+            '%' if self.eat_char('=') => Token::PercentAssign,
+            '%' => Token::Percent,
+            '=' if self.eat_char('=') => Token::Eq,
+            '=' => Token::Assign,
+            '!' if self.eat_char('=') => Token::Ne,
+            '<' if self.eat_char('=') => Token::Le,
+            '<' => Token::Lt,
+            '>' if self.eat_char('=') => Token::Ge,
+            '>' => Token::Gt,
+            ':' => Token::Colon,
+            '[' => Token::BracketOpen,
+            ']' => Token::BracketClose,
+            '{' => Token::BraceOpen,
+            '}' => Token::BraceClose,
+            '(' => Token::ParenOpen,
+            ')' => Token::ParenClose,
+            '$' if self.peek_char().is_some_and(is_ident_start) => {
+                Token::Variable(self.read_ident())
+            }
+            '"' => self.read_string(start)?,
+            '@' if self.peek_char().is_some_and(is_ident_start) => Token::Format(self.read_ident()),
+            c if c.is_ascii_digit() => self.read_number(start)?,
+            c if is_ident_start(c) => {
+                self.pos = start;
+                Token::Ident(self.read_ident())
+            }
+            c => return Err(self.error_at(start, SyntaxErrorKind::UnexpectedCharacter(c))),
+        };
+
+        Ok(Some(SpannedToken {
+            token,
+            span: self.span_from(start),
+        }))
+    }
+
+    fn read_ident(&mut self) -> String {
+        let start = self.pos;
+        while self.peek_char().is_some_and(is_ident_char) {
+            self.next_char();
+        }
+        self.source[start..self.pos].to_string()
+    }
+
+    // the first digit is already consumed
+    fn read_number(&mut self, start: usize) -> Result<Token, SyntaxError> {
+        while self
+            .peek_char()
+            .is_some_and(|c| c.is_ascii_digit() || c == '.')
+        {
+            self.next_char();
+        }
+        if matches!(self.peek_char(), Some('e' | 'E')) {
+            self.next_char();
+            if matches!(self.peek_char(), Some('+' | '-')) {
+                self.next_char();
+            }
+            while self.peek_char().is_some_and(|c| c.is_ascii_digit()) {
+                self.next_char();
+            }
+        }
+        self.source[start..self.pos]
+            .parse()
+            .map(Token::Number)
+            .map_err(|_| self.error_at(start, SyntaxErrorKind::InvalidNumber))
+    }
+
+    // the opening quote is already consumed
+    // A string, or an interpolated string if it contains `\(e)`. The tokens
+    // of `e` are read up to the matching `)`.
+    fn read_string(&mut self, start: usize) -> Result<Token, SyntaxError> {
+        let mut segments = Vec::new();
+        let mut s = String::new();
+        loop {
+            match self.next_char() {
+                Some('"') => break,
+                Some('\\') if self.eat_char('(') => {
+                    segments.push(Segment::Literal(std::mem::take(&mut s)));
+                    let mut tokens = Vec::new();
+                    let mut depth = 0;
+                    let close = loop {
+                        let Some(token) = self.next_token()? else {
+                            return Err(self.unterminated_string(start));
+                        };
+                        match token.token {
+                            Token::ParenClose if depth == 0 => break token.span,
+                            Token::ParenClose => depth -= 1,
+                            Token::ParenOpen => depth += 1,
+                            _ => {}
+                        }
+                        tokens.push(token);
+                    };
+                    segments.push(Segment::Interpolation(tokens, close));
+                }
+                Some('\\') => s.push(self.read_escape()?),
+                Some(c) => s.push(c),
+                None => return Err(self.unterminated_string(start)),
+            }
+        }
+        if segments.is_empty() {
+            return Ok(Token::String(s));
+        }
+        segments.push(Segment::Literal(s));
+        Ok(Token::InterpolatedString(segments))
+    }
+
+    // points at the opening quote rather than the whole rest of the filter
+    fn unterminated_string(&self, start: usize) -> SyntaxError {
+        let mut err = self.error_at(start, SyntaxErrorKind::UnterminatedString);
+        err.span = Span { len: 1, ..err.span };
+        err
+    }
+
+    // the backslash is already consumed
+    fn read_escape(&mut self) -> Result<char, SyntaxError> {
+        let escape_start = self.pos - 1;
+        let c = match self.next_char() {
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some('/') => '/',
+            Some('b') => '\u{8}',
+            Some('f') => '\u{c}',
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('u') => {
+                let mut code = self.read_hex4(escape_start)?;
+                // a high surrogate is combined with the low one that follows it
+                if (0xD800..0xDC00).contains(&code) && self.source[self.pos..].starts_with("\\u") {
+                    self.pos += 2;
+                    let low = self.read_hex4(escape_start)?;
+                    if !(0xDC00..0xE000).contains(&low) {
+                        return Err(
+                            self.error_at(escape_start, SyntaxErrorKind::InvalidUnicodeEscape)
+                        );
+                    }
+                    code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+                }
+                // lone surrogates are not valid chars
+                return char::from_u32(code).ok_or_else(|| {
+                    self.error_at(escape_start, SyntaxErrorKind::InvalidUnicodeEscape)
+                });
+            }
+            Some(c) => return Err(self.error_at(escape_start, SyntaxErrorKind::InvalidEscape(c))),
+            None => return Err(self.error_at(escape_start, SyntaxErrorKind::UnterminatedString)),
+        };
+        Ok(c)
+    }
+
+    fn read_hex4(&mut self, escape_start: usize) -> Result<u32, SyntaxError> {
+        let code = self
+            .source
+            .get(self.pos..self.pos + 4)
+            .filter(|digits| digits.chars().all(|c| c.is_ascii_hexdigit()))
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok());
+        match code {
+            Some(code) => {
+                self.pos += 4;
+                Ok(code)
+            }
+            None => Err(self.error_at(escape_start, SyntaxErrorKind::InvalidUnicodeEscape)),
+        }
+    }
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+#[cfg(test)]
+mod lexer {
+    use super::*;
+
+    #[test]
+    fn test_tokenize_paths() {
+        assert_eq!(
+            tokens(r#". .foo | ."a b" .[-1] (.x_1), .[]"#),
+            vec![
+                Token::Dot,
+                Token::Field("foo".to_string()),
+                Token::Pipe,
+                Token::Dot,
+                Token::String("a b".to_string()),
+                Token::Dot,
+                Token::BracketOpen,
+                Token::Minus,
+                Token::Number(1.0),
+                Token::BracketClose,
+                Token::ParenOpen,
+                Token::Field("x_1".to_string()),
+                Token::ParenClose,
+                Token::Comma,
+                Token::Dot,
+                Token::BracketOpen,
+                Token::BracketClose,
+            ]
+        );
+        assert_eq!(
+            tokens("..|.a?,..."),
+            vec![
+                Token::DotDot,
+                Token::Pipe,
+                Token::Field("a".to_string()),
+                Token::Question,
+                Token::Comma,
+                Token::DotDot,
+                Token::Dot,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_literals() {
+        assert_eq!(
+            tokens(r#"12 1.5e3 "\"é😀\n" empty # comment"#),
+            vec![
+                Token::Number(12.0),
+                Token::Number(1500.0),
+                Token::String("\"é😀\n".to_string()),
+                Token::Ident("empty".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_operators() {
+        assert_eq!(
+            tokens(".a+1 - -2*3/.b%4"),
+            vec![
+                Token::Field("a".to_string()),
+                Token::Plus,
+                Token::Number(1.0),
+                Token::Minus,
+                Token::Minus,
+                Token::Number(2.0),
+                Token::Star,
+                Token::Number(3.0),
+                Token::Slash,
+                Token::Field("b".to_string()),
+                Token::Percent,
+                Token::Number(4.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_comparisons() {
+        assert_eq!(
+            tokens(".a==1 != 2<3 <= 4>5>=6"),
+            vec![
+                Token::Field("a".to_string()),
+                Token::Eq,
+                Token::Number(1.0),
+                Token::Ne,
+                Token::Number(2.0),
+                Token::Lt,
+                Token::Number(3.0),
+                Token::Le,
+                Token::Number(4.0),
+                Token::Gt,
+                Token::Number(5.0),
+                Token::Ge,
+                Token::Number(6.0),
+            ]
+        );
+        let err = tokenize("1 ! 2").unwrap_err();
+        assert_eq!(err.kind(), &SyntaxErrorKind::UnexpectedCharacter('!'));
+    }
+
+    #[test]
+    fn test_tokenize_assignments() {
+        assert_eq!(
+            tokens(".a=1|.b|=.+=-=*=/=%=//=//"),
+            vec![
+                Token::Field("a".to_string()),
+                Token::Assign,
+                Token::Number(1.0),
+                Token::Pipe,
+                Token::Field("b".to_string()),
+                Token::PipeAssign,
+                Token::Dot,
+                Token::PlusAssign,
+                Token::MinusAssign,
+                Token::StarAssign,
+                Token::SlashAssign,
+                Token::PercentAssign,
+                Token::DoubleSlashAssign,
+                Token::DoubleSlash,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_error_handling() {
+        assert_eq!(
+            tokens(".a? // .b[]?/2"),
+            vec![
+                Token::Field("a".to_string()),
+                Token::Question,
+                Token::DoubleSlash,
+                Token::Field("b".to_string()),
+                Token::BracketOpen,
+                Token::BracketClose,
+                Token::Question,
+                Token::Slash,
+                Token::Number(2.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_objects() {
+        assert_eq!(
+            tokens(r#"{a, "b": $x}"#),
+            vec![
+                Token::BraceOpen,
+                Token::Ident("a".to_string()),
+                Token::Comma,
+                Token::String("b".to_string()),
+                Token::Colon,
+                Token::Variable("x".to_string()),
+                Token::BraceClose,
+            ]
+        );
+        assert!(tokenize("$ x").is_err());
+    }
+
+    #[test]
+    fn test_tokenize_interpolation() {
+        let tokens = tokens(r#"@csv "a\(.b + (1))\n" "c\\(d""#);
+        assert_eq!(tokens[0], Token::Format("csv".to_string()));
+        assert_eq!(tokens[1].to_string(), r#""a\(...)\n""#);
+        let Token::InterpolatedString(segments) = &tokens[1] else {
+            panic!("expected an interpolated string, got {:?}", tokens[1]);
+        };
+        assert_eq!(segments[0], Segment::Literal("a".to_string()));
+        assert_eq!(segments[2], Segment::Literal("\n".to_string()));
+        let Segment::Interpolation(inner, close) = &segments[1] else {
+            panic!("expected an interpolation, got {:?}", segments[1]);
+        };
+        assert_eq!(
+            inner.iter().map(|t| t.token.clone()).collect::<Vec<_>>(),
+            vec![
+                Token::Field("b".to_string()),
+                Token::Plus,
+                Token::ParenOpen,
+                Token::Number(1.0),
+                Token::ParenClose,
+            ]
+        );
+        assert_eq!(close.offset, 17);
+        // an escaped backslash doesn't start an interpolation
+        assert_eq!(tokens[2], Token::String("c\\(d".to_string()));
+        assert_eq!(tokens.len(), 3);
+    }
+
+    #[test]
+    fn test_tokenize_multiline_strings() {
+        let tokens = tokenize("\"a\n\\(.b)\" | .c").unwrap();
+        assert_eq!(
+            (
+                tokens[0].span.offset,
+                tokens[0].span.line,
+                tokens[0].span.column
+            ),
+            (0, 1, 1)
+        );
+        assert_eq!((tokens[2].span.line, tokens[2].span.column), (2, 10));
+
+        let err = tokenize("1,\n\"a\n\\(1").unwrap_err();
+        assert_eq!(err.kind(), &SyntaxErrorKind::UnterminatedString);
+        assert_eq!(
+            (err.span().line, err.span().column, err.span().len),
+            (2, 1, 1)
+        );
+    }
+
+    #[test]
+    fn test_tokenize_spans() {
+        let tokens = tokenize(".a |\n  .[0]").unwrap();
+        let spans: Vec<(usize, usize, usize)> = tokens
+            .iter()
+            .map(|t| (t.span.offset, t.span.line, t.span.column))
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                (0, 1, 1),
+                (3, 1, 4),
+                (7, 2, 3),
+                (8, 2, 4),
+                (9, 2, 5),
+                (10, 2, 6)
+            ]
+        );
+        assert_eq!(end_of_input(".a |\n  .[0]").column, 7);
+    }
+
+    #[test]
+    fn test_tokenize_errors() {
+        let err = tokenize(".a | ^").unwrap_err();
+        assert_eq!(err.kind(), &SyntaxErrorKind::UnexpectedCharacter('^'));
+        assert_eq!(err.to_string(), "1:6: Unexpected character `^`");
+
+        let err = tokenize(r#".["abc"#).unwrap_err();
+        assert_eq!(err.kind(), &SyntaxErrorKind::UnterminatedString);
+        assert_eq!((err.span().offset, err.span().len), (2, 1));
+
+        let err = tokenize(r#""\q""#).unwrap_err();
+        assert_eq!(err.kind(), &SyntaxErrorKind::InvalidEscape('q'));
+
+        let err = tokenize(r#""\u12""#).unwrap_err();
+        assert_eq!(err.kind(), &SyntaxErrorKind::InvalidUnicodeEscape);
+
+        let err = tokenize("1.2.3").unwrap_err();
+        assert_eq!(err.kind(), &SyntaxErrorKind::InvalidNumber);
+    }
+
+    fn tokens(source: &str) -> Vec<Token> {
+        tokenize(source)
+            .unwrap()
+            .into_iter()
+            .map(|t| t.token)
+            .collect()
+    }
+}
@@ -0,0 +1,348 @@
+use std::sync::Arc;
+
+use super::{BinaryOp, RuntimeError};
+use crate::Value;
+
+// Longest string `*` repeats a string to, jq's `INT_MAX` bytes.
+const MAX_STRING_LEN: usize = i32::MAX as usize;
+
+/// Applies a binary operator with jq's semantics. Arithmetic depends on the
+/// types of both operands, e.g. `+` also concatenates and merges, while
+/// comparisons use the total order of values and never fail. Only `==` and
+/// `!=` compare numbers as floats, so that `nan` equals nothing.
+pub fn binary(op: BinaryOp, lhs: Value, rhs: Value) -> Result<Value, RuntimeError> {
+    match op {
+        BinaryOp::Add => add(lhs, rhs),
+        BinaryOp::Sub => sub(lhs, rhs),
+        BinaryOp::Mul => mul(lhs, rhs),
+        BinaryOp::Div => div(lhs, rhs),
+        BinaryOp::Mod => rem(lhs, rhs),
+        BinaryOp::Eq => Ok(Value::Boolean(equal(&lhs, &rhs))),
+        BinaryOp::Ne => Ok(Value::Boolean(!equal(&lhs, &rhs))),
+        BinaryOp::Lt => Ok(Value::Boolean(lhs < rhs)),
+        BinaryOp::Le => Ok(Value::Boolean(lhs <= rhs)),
+        BinaryOp::Gt => Ok(Value::Boolean(lhs > rhs)),
+        BinaryOp::Ge => Ok(Value::Boolean(lhs >= rhs)),
+    }
+}
+
+// `null` is the identity, objects are merged with the right side winning
+fn add(lhs: Value, rhs: Value) -> Result<Value, RuntimeError> {
+    match (lhs, rhs) {
+        (Value::Null, value) | (value, Value::Null) => Ok(value),
+        (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a + b)),
+        (Value::String(a), Value::String(b)) => Ok(Value::String(a + &b)),
+        (Value::Array(mut a), Value::Array(b)) => {
+            Arc::make_mut(&mut a).extend(b.iter().cloned());
+            Ok(Value::Array(a))
+        }
+        (Value::Object(mut a), Value::Object(b)) => {
+            let fields = Arc::make_mut(&mut a);
+            for (key, value) in b.iter() {
+                insert(fields, key, value.clone());
+            }
+            Ok(Value::Object(a))
+        }
+        (lhs, rhs) => Err(type_error(&lhs, &rhs, "added")),
+    }
+}
+
+// arrays lose every element that is in the right side
+fn sub(lhs: Value, rhs: Value) -> Result<Value, RuntimeError> {
+    match (lhs, rhs) {
+        (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a - b)),
+        (Value::Array(mut a), Value::Array(b)) => {
+            Arc::make_mut(&mut a).retain(|element| !b.iter().any(|b| equal(element, b)));
+            Ok(Value::Array(a))
+        }
+        (lhs, rhs) => Err(type_error(&lhs, &rhs, "subtracted")),
+    }
+}
+
+// strings are repeated, objects are merged recursively
+fn mul(lhs: Value, rhs: Value) -> Result<Value, RuntimeError> {
+    match (lhs, rhs) {
+        (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a * b)),
+        (Value::String(s), Value::Number(n)) | (Value::Number(n), Value::String(s)) => {
+            // no repetitions is `null`, a fraction still repeats once
+            if n.is_nan() || n <= 0.0 {
+                return Ok(Value::Null);
+            }
+            let count = (n as usize).max(1);
+            match s.len().checked_mul(count) {
+                Some(len) if len <= MAX_STRING_LEN => Ok(Value::String(s.repeat(count))),
+                _ => Err(RuntimeError::msg("Repeat string result too long")),
+            }
+        }
+        (Value::Object(a), Value::Object(b)) => Ok(Value::Object(deep_merge(a, &b))),
+        (lhs, rhs) => Err(type_error(&lhs, &rhs, "multiplied")),
+    }
+}
+
+// strings are split on the right side
+fn div(lhs: Value, rhs: Value) -> Result<Value, RuntimeError> {
+    match (lhs, rhs) {
+        (Value::Number(a), Value::Number(b)) if b == 0.0 => Err(zero_divisor(a, b, "divided")),
+        (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a / b)),
+        (Value::String(s), Value::String(separator)) => Ok(Value::Array(
+            split(&s, &separator)
+                .map(|part| Value::String(part.to_string()))
+                .collect::<Vec<_>>()
+                .into(),
+        )),
+        (lhs, rhs) => Err(type_error(&lhs, &rhs, "divided")),
+    }
+}
+
+// remainder of the operands truncated to integers, with the sign of the dividend
+fn rem(lhs: Value, rhs: Value) -> Result<Value, RuntimeError> {
+    match (lhs, rhs) {
+        (Value::Number(a), Value::Number(b)) => match (a as i64, b as i64) {
+            (_, 0) => Err(zero_divisor(a, b, "divided (remainder)")),
+            // `i64::MIN % -1` overflows
+            (_, -1) => Ok(Value::Number(0.0)),
+            (a, b) => Ok(Value::Number((a % b) as f64)),
+        },
+        (lhs, rhs) => Err(type_error(&lhs, &rhs, "divided")),
+    }
+}
+
+// jq's equality: like the order of values, except that numbers are equal as
+// floats, so `nan` isn't equal to itself
+fn equal(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a == b,
+        (Value::Array(a), Value::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| equal(a, b))
+        }
+        (Value::Object(a), Value::Object(b)) => {
+            a.len() == b.len()
+                && a.iter().all(|(key, a)| {
+                    b.iter()
+                        .find(|(k, _)| k == key)
+                        .is_some_and(|(_, b)| equal(a, b))
+                })
+        }
+        (a, b) => a == b,
+    }
+}
+
+fn deep_merge(
+    mut a: Arc<Vec<(String, Value)>>,
+    b: &[(String, Value)],
+) -> Arc<Vec<(String, Value)>> {
+    let fields = Arc::make_mut(&mut a);
+    for (key, value) in b {
+        let merged = match (fields.iter_mut().find(|(k, _)| k == key), value) {
+            (Some((_, Value::Object(inner))), Value::Object(other)) => {
+                *inner = deep_merge(inner.clone(), other);
+                true
+            }
+            _ => false,
+        };
+        if !merged {
+            insert(fields, key, value.clone());
+        }
+    }
+    a
+}
+
+// replaces the value of an existing key in place, or appends the key
+fn insert(fields: &mut Vec<(String, Value)>, key: &str, value: Value) {
+    match fields.iter_mut().find(|(k, _)| k == key) {
+        Some((_, v)) => *v = value,
+        None => fields.push((key.to_string(), value)),
+    }
+}
+
+// an empty string has no parts, an empty separator splits every character
+fn split<'a>(s: &'a str, separator: &'a str) -> Box<dyn Iterator<Item = &'a str> + 'a> {
+    if s.is_empty() {
+        Box::new(std::iter::empty())
+    } else if separator.is_empty() {
+        Box::new(s.char_indices().map(|(i, c)| &s[i..i + c.len_utf8()]))
+    } else {
+        Box::new(s.split(separator))
+    }
+}
+
+fn type_error(lhs: &Value, rhs: &Value, verb: &str) -> RuntimeError {
+    RuntimeError::msg(format!(
+        "{} ({}) and {} ({}) cannot be {}",
+        lhs.type_name(),
+        lhs,
+        rhs.type_name(),
+        rhs,
+        verb
+    ))
+}
+
+fn zero_divisor(a: f64, b: f64, verb: &str) -> RuntimeError {
+    RuntimeError::msg(format!(
+        "number ({}) and number ({}) cannot be {} because the divisor is zero",
+        Value::Number(a),
+        Value::Number(b),
+        verb
+    ))
+}
+
+#[cfg(test)]
+mod ops {
+    use super::*;
+
+    #[test]
+    fn test_add() {
+        assert_eq!(eval(BinaryOp::Add, "1", "2.5"), Ok("3.5".to_string()));
+        assert_eq!(
+            eval(BinaryOp::Add, r#""ab""#, r#""c""#),
+            Ok(r#""abc""#.to_string())
+        );
+        assert_eq!(
+            eval(BinaryOp::Add, "[1]", "[2, 1]"),
+            Ok("[1,2,1]".to_string())
+        );
+        assert_eq!(
+            eval(
+                BinaryOp::Add,
+                r#"{"a": 1, "b": {"c": 2}}"#,
+                r#"{"b": {"d": 3}, "e": 4}"#
+            ),
+            Ok(r#"{"a":1,"b":{"d":3},"e":4}"#.to_string())
+        );
+        assert_eq!(eval(BinaryOp::Add, "null", "[1]"), Ok("[1]".to_string()));
+        assert_eq!(eval(BinaryOp::Add, "{}", "null"), Ok("{}".to_string()));
+        assert_eq!(
+            eval(BinaryOp::Add, "[]", "{}"),
+            Err("array ([]) and object ({}) cannot be added".to_string())
+        );
+    }
+
+    #[test]
+    fn test_sub() {
+        assert_eq!(eval(BinaryOp::Sub, "1", "3"), Ok("-2".to_string()));
+        assert_eq!(
+            eval(BinaryOp::Sub, r#"[1, "a", 2, 1, [3]]"#, "[1, [3]]"),
+            Ok(r#"["a",2]"#.to_string())
+        );
+        assert_eq!(
+            eval(BinaryOp::Sub, "{}", "1"),
+            Err("object ({}) and number (1) cannot be subtracted".to_string())
+        );
+        assert_eq!(
+            eval(BinaryOp::Sub, "null", "1"),
+            Err("null (null) and number (1) cannot be subtracted".to_string())
+        );
+    }
+
+    #[test]
+    fn test_mul() {
+        assert_eq!(eval(BinaryOp::Mul, "4", "2.5"), Ok("10".to_string()));
+        assert_eq!(
+            eval(BinaryOp::Mul, r#""ab""#, "3"),
+            Ok(r#""ababab""#.to_string())
+        );
+        assert_eq!(
+            eval(BinaryOp::Mul, "2", r#""ab""#),
+            Ok(r#""abab""#.to_string())
+        );
+        assert_eq!(
+            eval(BinaryOp::Mul, r#""ab""#, "0.5"),
+            Ok(r#""ab""#.to_string())
+        );
+        assert_eq!(eval(BinaryOp::Mul, r#""ab""#, "0"), Ok("null".to_string()));
+        assert_eq!(
+            eval(BinaryOp::Mul, r#""ab""#, "1e20"),
+            Err("Repeat string result too long".to_string())
+        );
+        assert_eq!(
+            eval(BinaryOp::Mul, "2e9", r#""ab""#),
+            Err("Repeat string result too long".to_string())
+        );
+        assert_eq!(
+            eval(
+                BinaryOp::Mul,
+                r#"{"a": {"b": 1, "c": {"d": 2}}, "e": 3}"#,
+                r#"{"a": {"c": {"f": 4}, "b": null}, "e": {"g": 5}}"#
+            ),
+            Ok(r#"{"a":{"b":null,"c":{"d":2,"f":4}},"e":{"g":5}}"#.to_string())
+        );
+        assert_eq!(
+            eval(BinaryOp::Mul, "[]", "2"),
+            Err("array ([]) and number (2) cannot be multiplied".to_string())
+        );
+    }
+
+    #[test]
+    fn test_div_and_rem() {
+        assert_eq!(eval(BinaryOp::Div, "1", "4"), Ok("0.25".to_string()));
+        assert_eq!(
+            eval(BinaryOp::Div, r#""a, b, c""#, r#"", ""#),
+            Ok(r#"["a","b","c"]"#.to_string())
+        );
+        assert_eq!(eval(BinaryOp::Div, r#""""#, r#"",""#), Ok("[]".to_string()));
+        assert_eq!(
+            eval(BinaryOp::Div, r#""hé""#, r#""""#),
+            Ok(r#"["h","é"]"#.to_string())
+        );
+        assert_eq!(
+            eval(BinaryOp::Div, "1", "0"),
+            Err(
+                "number (1) and number (0) cannot be divided because the divisor is zero"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            eval(BinaryOp::Div, "[]", "1"),
+            Err("array ([]) and number (1) cannot be divided".to_string())
+        );
+
+        assert_eq!(eval(BinaryOp::Mod, "7", "3"), Ok("1".to_string()));
+        assert_eq!(eval(BinaryOp::Mod, "-7.9", "3.9"), Ok("-1".to_string()));
+        assert_eq!(eval(BinaryOp::Mod, "7", "-1"), Ok("0".to_string()));
+        assert_eq!(
+            eval(BinaryOp::Mod, "7", "0.5"),
+            Err(
+                "number (7) and number (0.5) cannot be divided (remainder) because the divisor is zero"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_compare() {
+        assert_eq!(eval(BinaryOp::Eq, "1", "1.0"), Ok("true".to_string()));
+        assert_eq!(
+            eval(BinaryOp::Eq, r#"{"a": 1, "b": 2}"#, r#"{"b": 2, "a": 1}"#),
+            Ok("true".to_string())
+        );
+        assert_eq!(eval(BinaryOp::Ne, "null", "false"), Ok("true".to_string()));
+        assert_eq!(eval(BinaryOp::Lt, r#""10""#, "9"), Ok("false".to_string()));
+        assert_eq!(
+            eval(BinaryOp::Le, "[1, 2]", "[1, 2]"),
+            Ok("true".to_string())
+        );
+        assert_eq!(eval(BinaryOp::Gt, "{}", "[0]"), Ok("true".to_string()));
+        assert_eq!(eval(BinaryOp::Ge, "true", "false"), Ok("true".to_string()));
+
+        // `nan` is equal to nothing, but still sorts below every number
+        let nan = || Value::Number(f64::NAN);
+        let compare = |op, lhs, rhs| binary(op, lhs, rhs).unwrap().to_string();
+        assert_eq!(compare(BinaryOp::Eq, nan(), nan()), "false");
+        assert_eq!(compare(BinaryOp::Ne, nan(), nan()), "true");
+        let nested = || Value::Array(vec![nan()].into());
+        assert_eq!(compare(BinaryOp::Eq, nested(), nested()), "false");
+        assert_eq!(compare(BinaryOp::Sub, nested(), nested()), "[null]");
+        assert_eq!(
+            compare(BinaryOp::Lt, nan(), Value::Number(f64::MIN)),
+            "true"
+        );
+    }
+
+    fn eval(op: BinaryOp, lhs: &str, rhs: &str) -> Result<String, String> {
+        let lhs = crate::parse_str(lhs).unwrap();
+        let rhs = crate::parse_str(rhs).unwrap();
+        binary(op, lhs, rhs)
+            .map(|v| v.to_string())
+            .map_err(|e| e.to_string())
+    }
+}
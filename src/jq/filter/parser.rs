@@ -0,0 +1,1518 @@
+use super::builtins;
+use super::format;
+use super::lexer::{Segment, SpannedToken, Token};
+use super::{SyntaxError, SyntaxErrorKind};
+use crate::tokenizer::Span;
+use crate::Value;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    // `.`
+    Identity,
+    Literal(Value),
+    // `e[i]`, the index is evaluated against the same input as `e`;
+    // `.foo` is `.["foo"]`
+    Index(Box<Expr>, Box<Expr>),
+    // `e[start:end]`, either bound may be missing but not both
+    Slice(Box<Expr>, Option<Box<Expr>>, Option<Box<Expr>>),
+    // `e[]`, every element of an array or value of an object
+    Iterate(Box<Expr>),
+    // `[e]`, collecting every output of `e`; `[]` has no expression
+    Array(Option<Box<Expr>>),
+    // `{k: v, ...}` as key and value expressions, in order
+    Object(Vec<(Expr, Expr)>),
+    // `$name`
+    Var(String),
+    // unary `-`
+    Neg(Box<Expr>),
+    // `a + b` and the other operators evaluating both sides
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    // `if c then a else b end`, `elif` is a nested `if` in the else branch;
+    // without `else` the input is the output
+    If(Box<Expr>, Box<Expr>, Option<Box<Expr>>),
+    // `try e catch h`, without a handler errors are dropped; `e?` is `try e`
+    Try(Box<Expr>, Option<Box<Expr>>),
+    // `a // b`, the true outputs of `a`, or the outputs of `b` if there are none
+    Alternative(Box<Expr>, Box<Expr>),
+    // `path = value` and the other assignments, `path` must be a path
+    // expression
+    Assign(AssignOp, Box<Expr>, Box<Expr>),
+    // `source as $x | body`, with `?//` between alternative patterns
+    Bind(Box<Expr>, Vec<Pattern>, Box<Expr>),
+    // `reduce source as $x (init; update)`
+    Reduce(Box<Expr>, Vec<Pattern>, Box<Expr>, Box<Expr>),
+    // `foreach source as $x (init; update; extract)`, without an extract the
+    // states are the outputs
+    Foreach(
+        Box<Expr>,
+        Vec<Pattern>,
+        Box<Expr>,
+        Box<Expr>,
+        Option<Box<Expr>>,
+    ),
+    // `a and b`, `b` is only evaluated for true outputs of `a`
+    And(Box<Expr>, Box<Expr>),
+    // `a or b`, `b` is only evaluated for false outputs of `a`
+    Or(Box<Expr>, Box<Expr>),
+    // function with its arguments, e.g. `empty` or `map(.a)`: a definition
+    // in scope, a parameter, or a builtin
+    Call(String, Vec<Expr>),
+    // `def f: body; rest`, the function is visible in its body and in `rest`
+    Def(Box<FuncDef>, Box<Expr>),
+    // `a, b`, the outputs of `a` followed by those of `b`
+    Comma(Box<Expr>, Box<Expr>),
+    Pipe(Box<Expr>, Box<Expr>),
+    // `@name` alone, the input in that format
+    Format(String),
+    // `"a\(e)b"`, or `@name "a\(e)b"` where the format applies to the outputs
+    // of `e` but not to the literal parts
+    Interpolate(Option<String>, Vec<StringPart>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StringPart {
+    Literal(String),
+    Expr(Expr),
+}
+
+// destructuring pattern of `as`, e.g. `[$a, {b: $c, $d}]`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    Var(String),
+    Array(Vec<Pattern>),
+    // key expressions with the pattern of their value; `{$x: p}` is both
+    // `{x: $x}` and `{x: p}`
+    Object(Vec<(Expr, Pattern)>),
+}
+
+impl Pattern {
+    /// Names of the variables the pattern binds, in order.
+    pub fn variables(&self) -> Vec<&str> {
+        match self {
+            Pattern::Var(name) => vec![name],
+            Pattern::Array(elements) => elements.iter().flat_map(Pattern::variables).collect(),
+            Pattern::Object(entries) => entries
+                .iter()
+                .flat_map(|(_, pattern)| pattern.variables())
+                .collect(),
+        }
+    }
+}
+
+// `def name(params): body;`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FuncDef {
+    pub name: String,
+    pub params: Vec<Param>,
+    pub body: Expr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Param {
+    // `f`, called as a filter on the input of each call to `f`
+    Filter(String),
+    // `$x`, a value for each output of the argument; `x` is also a filter
+    Value(String),
+}
+
+impl Param {
+    pub fn name(&self) -> &str {
+        match self {
+            Param::Filter(name) | Param::Value(name) => name,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AssignOp {
+    // `=`, the right side runs on `.` and each output sets every path
+    Set,
+    // `|=`, the right side runs on the value at each path, and its first
+    // output replaces it, or no output deletes it
+    Update,
+    // `+=` and the others, `a op= b` is `b as $x | a |= . op $x`
+    Arithmetic(BinaryOp),
+    // `a //= b` is `b as $x | a |= . // $x`
+    Alternative,
+}
+
+// an empty filter is the identity, like in jq
+pub fn parse(tokens: &[SpannedToken], eof: Span) -> Result<Expr, SyntaxError> {
+    if tokens.is_empty() {
+        return Ok(Expr::Identity);
+    }
+
+    let mut parser = Parser::new(tokens, eof, builtins::prelude());
+    let expr = parser.parse_pipe()?;
+    if parser.peek().is_some() {
+        return Err(parser.unexpected());
+    }
+
+    Ok(expr)
+}
+
+/// Parses a sequence of definitions, like the prelude, each visible to the
+/// ones after it. Only native builtins are defined for them.
+pub fn parse_defs(tokens: &[SpannedToken], eof: Span) -> Result<Vec<FuncDef>, SyntaxError> {
+    let mut parser = Parser::new(tokens, eof, &[]);
+    let mut defs = Vec::new();
+    while parser.peek().is_some() {
+        let def = parser.parse_def()?;
+        parser.funcs.push((def.name.clone(), def.params.len()));
+        defs.push(def);
+    }
+
+    Ok(defs)
+}
+
+// variables bound for every filter
+const PREDEFINED_VARIABLES: &[&str] = &["ENV"];
+
+struct Parser<'a> {
+    tokens: &'a [SpannedToken],
+    pos: usize,
+    eof: Span,
+    // variables in scope, innermost last
+    vars: Vec<String>,
+    // names and arities of the functions and filter parameters in scope
+    funcs: Vec<(String, usize)>,
+    // definitions of the builtins written in jq
+    prelude: &'a [FuncDef],
+}
+
+impl<'a> Parser<'a> {
+    fn new(tokens: &'a [SpannedToken], eof: Span, prelude: &'a [FuncDef]) -> Parser<'a> {
+        Parser {
+            tokens,
+            pos: 0,
+            eof,
+            vars: PREDEFINED_VARIABLES.iter().map(|v| v.to_string()).collect(),
+            funcs: Vec::new(),
+            prelude,
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|t| &t.token)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<&Token> {
+        self.tokens.get(self.pos + offset).map(|t| &t.token)
+    }
+
+    fn next(&mut self) -> Option<&Token> {
+        let t = self.tokens.get(self.pos)?;
+        self.pos += 1;
+        Some(&t.token)
+    }
+
+    // consumes the next token if it is `token`
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.peek() == Some(token) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Ident(name)) if name == keyword) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), SyntaxError> {
+        if self.eat_keyword(keyword) {
+            return Ok(());
+        }
+        Err(self.unexpected())
+    }
+
+    fn expect(&mut self, token: &Token) -> Result<(), SyntaxError> {
+        if self.eat(token) {
+            return Ok(());
+        }
+        Err(self.unexpected())
+    }
+
+    // error for the next token, or for the end of the filter
+    fn unexpected(&self) -> SyntaxError {
+        match self.tokens.get(self.pos) {
+            Some(t) => SyntaxError::new(
+                t.span,
+                SyntaxErrorKind::UnexpectedToken(t.token.to_string()),
+            ),
+            None => SyntaxError::new(self.eof, SyntaxErrorKind::UnexpectedEndOfFilter),
+        }
+    }
+
+    // `a | b | c` is `a | (b | c)`, definitions extend as far right as
+    // possible: `def f: 1; f | g` is `def f: 1; (f | g)`
+    fn parse_pipe(&mut self) -> Result<Expr, SyntaxError> {
+        if matches!(self.peek(), Some(Token::Ident(name)) if name == "def") {
+            let def = self.parse_def()?;
+            self.funcs.push((def.name.clone(), def.params.len()));
+            let rest = self.parse_pipe();
+            self.funcs.pop();
+            return Ok(Expr::Def(Box::new(def), Box::new(rest?)));
+        }
+        let lhs = self.parse_comma()?;
+        if self.eat(&Token::Pipe) {
+            let rhs = self.parse_pipe()?;
+            return Ok(Expr::Pipe(Box::new(lhs), Box::new(rhs)));
+        }
+        Ok(lhs)
+    }
+
+    // binds tighter than `|`, `a, b | c` is `(a, b) | c`
+    fn parse_comma(&mut self) -> Result<Expr, SyntaxError> {
+        let mut lhs = self.parse_alternative()?;
+        while self.eat(&Token::Comma) {
+            let rhs = self.parse_alternative()?;
+            lhs = Expr::Comma(Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    // `a // b // c` is `a // (b // c)`
+    fn parse_alternative(&mut self) -> Result<Expr, SyntaxError> {
+        let lhs = self.parse_assignment()?;
+        if self.eat(&Token::DoubleSlash) {
+            let rhs = self.parse_alternative()?;
+            return Ok(Expr::Alternative(Box::new(lhs), Box::new(rhs)));
+        }
+        Ok(lhs)
+    }
+
+    // Assignments don't chain, and bind tighter than `//` but looser than
+    // `or`, as in jq: `.a = 1 // 2` is `(.a = 1) // 2`.
+    fn parse_assignment(&mut self) -> Result<Expr, SyntaxError> {
+        let lhs = self.parse_or()?;
+        let op = match self.peek() {
+            Some(Token::Assign) => AssignOp::Set,
+            Some(Token::PipeAssign) => AssignOp::Update,
+            Some(Token::PlusAssign) => AssignOp::Arithmetic(BinaryOp::Add),
+            Some(Token::MinusAssign) => AssignOp::Arithmetic(BinaryOp::Sub),
+            Some(Token::StarAssign) => AssignOp::Arithmetic(BinaryOp::Mul),
+            Some(Token::SlashAssign) => AssignOp::Arithmetic(BinaryOp::Div),
+            Some(Token::PercentAssign) => AssignOp::Arithmetic(BinaryOp::Mod),
+            Some(Token::DoubleSlashAssign) => AssignOp::Alternative,
+            _ => return Ok(lhs),
+        };
+        self.next();
+        let rhs = self.parse_or()?;
+        Ok(Expr::Assign(op, Box::new(lhs), Box::new(rhs)))
+    }
+
+    // `and` binds tighter than `or`, both are left associative
+    fn parse_or(&mut self) -> Result<Expr, SyntaxError> {
+        let mut lhs = self.parse_and()?;
+        while self.eat_keyword("or") {
+            let rhs = self.parse_and()?;
+            lhs = Expr::Or(Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, SyntaxError> {
+        let mut lhs = self.parse_comparison()?;
+        while self.eat_keyword("and") {
+            let rhs = self.parse_comparison()?;
+            lhs = Expr::And(Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    // comparisons don't chain, `1 < 2 < 3` is an error like in jq
+    fn parse_comparison(&mut self) -> Result<Expr, SyntaxError> {
+        let lhs = self.parse_additive()?;
+        let op = match self.peek() {
+            Some(Token::Eq) => BinaryOp::Eq,
+            Some(Token::Ne) => BinaryOp::Ne,
+            Some(Token::Lt) => BinaryOp::Lt,
+            Some(Token::Le) => BinaryOp::Le,
+            Some(Token::Gt) => BinaryOp::Gt,
+            Some(Token::Ge) => BinaryOp::Ge,
+            _ => return Ok(lhs),
+        };
+        self.next();
+        let rhs = self.parse_additive()?;
+        Ok(Expr::Binary(op, Box::new(lhs), Box::new(rhs)))
+    }
+
+    // `+` and `-` are left associative and bind looser than `*`, `/` and `%`
+    fn parse_additive(&mut self) -> Result<Expr, SyntaxError> {
+        let mut lhs = self.parse_multiplicative()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Plus) => BinaryOp::Add,
+                Some(Token::Minus) => BinaryOp::Sub,
+                _ => return Ok(lhs),
+            };
+            self.next();
+            let rhs = self.parse_multiplicative()?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn parse_multiplicative(&mut self) -> Result<Expr, SyntaxError> {
+        let mut lhs = self.parse_unary()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Star) => BinaryOp::Mul,
+                Some(Token::Slash) => BinaryOp::Div,
+                Some(Token::Percent) => BinaryOp::Mod,
+                _ => return Ok(lhs),
+            };
+            self.next();
+            let rhs = self.parse_unary()?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, SyntaxError> {
+        if self.eat(&Token::Minus) {
+            return Ok(Expr::Neg(Box::new(self.parse_unary()?)));
+        }
+        let term = self.parse_postfix()?;
+        if self.eat_keyword("as") {
+            return self.parse_binding(term);
+        }
+        Ok(term)
+    }
+
+    // after `source as`, the body extends as far right as possible:
+    // `1 + . as $x | $x * 2` is `1 + (. as $x | $x * 2)`
+    fn parse_binding(&mut self, source: Expr) -> Result<Expr, SyntaxError> {
+        let scope = self.vars.len();
+        let patterns = self.parse_patterns()?;
+        self.expect(&Token::Pipe)?;
+        let body = self.parse_pipe();
+        self.vars.truncate(scope);
+
+        Ok(Expr::Bind(Box::new(source), patterns, Box::new(body?)))
+    }
+
+    // one or more patterns separated by `?//`
+    fn parse_patterns(&mut self) -> Result<Vec<Pattern>, SyntaxError> {
+        let mut patterns = vec![self.parse_pattern()?];
+        // `?//` is not a token, so that `.a?//1` is still `.a? // 1`
+        while self.peek() == Some(&Token::Question) && self.peek_at(1) == Some(&Token::DoubleSlash)
+        {
+            self.pos += 2;
+            patterns.push(self.parse_pattern()?);
+        }
+        Ok(patterns)
+    }
+
+    // the variables are in scope as soon as they are bound, including for the
+    // keys of later entries: `{a: $x, ($x): $y}`
+    fn parse_pattern(&mut self) -> Result<Pattern, SyntaxError> {
+        match self.peek() {
+            Some(Token::Variable(name)) => {
+                let name = name.clone();
+                self.next();
+                self.vars.push(name.clone());
+                Ok(Pattern::Var(name))
+            }
+            Some(Token::BracketOpen) => {
+                self.next();
+                let mut elements = Vec::new();
+                loop {
+                    elements.push(self.parse_pattern()?);
+                    if self.eat(&Token::BracketClose) {
+                        return Ok(Pattern::Array(elements));
+                    }
+                    self.expect(&Token::Comma)?;
+                }
+            }
+            Some(Token::BraceOpen) => {
+                self.next();
+                let mut entries = Vec::new();
+                loop {
+                    self.parse_pattern_entry(&mut entries)?;
+                    if self.eat(&Token::BraceClose) {
+                        return Ok(Pattern::Object(entries));
+                    }
+                    self.expect(&Token::Comma)?;
+                }
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    // `$x`, `$x: p`, `key: p`, `"key": p`, `"\(e)": p` or `(expr): p`
+    fn parse_pattern_entry(
+        &mut self,
+        entries: &mut Vec<(Expr, Pattern)>,
+    ) -> Result<(), SyntaxError> {
+        let key = match self.peek() {
+            Some(Token::Variable(name)) => {
+                let key = Expr::Literal(Value::String(name.clone()));
+                let var = self.parse_pattern()?;
+                entries.push((key.clone(), var));
+                if !self.eat(&Token::Colon) {
+                    return Ok(());
+                }
+                entries.push((key, self.parse_pattern()?));
+                return Ok(());
+            }
+            Some(Token::Ident(name)) => {
+                let key = Expr::Literal(Value::String(name.clone()));
+                self.next();
+                key
+            }
+            Some(Token::String(_) | Token::InterpolatedString(_) | Token::Format(_)) => {
+                self.parse_string()?
+            }
+            Some(Token::ParenOpen) => {
+                self.next();
+                let key = self.parse_pipe()?;
+                self.expect(&Token::ParenClose)?;
+                key
+            }
+            _ => return Err(self.unexpected()),
+        };
+        self.expect(&Token::Colon)?;
+        entries.push((key, self.parse_pattern()?));
+        Ok(())
+    }
+
+    // a term followed by any number of `.foo`, `."foo"`, `[i]`, `.[i]`, `[]` or `?`
+    fn parse_postfix(&mut self) -> Result<Expr, SyntaxError> {
+        let mut expr = self.parse_term()?;
+        loop {
+            expr = match (self.peek(), self.peek_at(1)) {
+                (Some(Token::Field(name)), _) => {
+                    let expr = field(expr, name);
+                    self.next();
+                    expr
+                }
+                (Some(Token::Dot), Some(Token::String(name))) => {
+                    let expr = field(expr, name);
+                    self.pos += 2;
+                    expr
+                }
+                (Some(Token::Dot), Some(Token::InterpolatedString(_))) => {
+                    self.next();
+                    let name = self.parse_string()?;
+                    Expr::Index(Box::new(expr), Box::new(name))
+                }
+                (Some(Token::Dot), Some(Token::BracketOpen)) => {
+                    self.next();
+                    self.parse_brackets(expr)?
+                }
+                (Some(Token::BracketOpen), _) => self.parse_brackets(expr)?,
+                (Some(Token::Question), _) => {
+                    self.next();
+                    Expr::Try(Box::new(expr), None)
+                }
+                _ => return Ok(expr),
+            };
+        }
+    }
+
+    fn parse_term(&mut self) -> Result<Expr, SyntaxError> {
+        let expr = match self.peek() {
+            Some(Token::Dot) => {
+                // `."foo"` and `.[i]` are suffixes of the identity, they
+                // consume the `.` themselves
+                if matches!(
+                    self.peek_at(1),
+                    Some(Token::String(_) | Token::InterpolatedString(_) | Token::BracketOpen)
+                ) {
+                    return Ok(Expr::Identity);
+                }
+                Expr::Identity
+            }
+            Some(Token::Field(name)) => field(Expr::Identity, name),
+            // `..` is `recurse`, as in jq
+            Some(Token::DotDot) => Expr::Call("recurse".to_string(), vec![]),
+            Some(Token::Number(n)) => Expr::Literal(Value::Number(*n)),
+            Some(Token::String(_) | Token::InterpolatedString(_) | Token::Format(_)) => {
+                return self.parse_string()
+            }
+            Some(Token::Ident(name)) => match name.as_str() {
+                "true" => Expr::Literal(Value::Boolean(true)),
+                "false" => Expr::Literal(Value::Boolean(false)),
+                "null" => Expr::Literal(Value::Null),
+                "if" => return self.parse_if(),
+                "try" => return self.parse_try(),
+                "reduce" | "foreach" => return self.parse_fold(),
+                _ => return self.parse_call(),
+            },
+            Some(Token::ParenOpen) => {
+                self.next();
+                let expr = self.parse_pipe()?;
+                self.expect(&Token::ParenClose)?;
+                return Ok(expr);
+            }
+            Some(Token::BracketOpen) => {
+                self.next();
+                if self.eat(&Token::BracketClose) {
+                    return Ok(Expr::Array(None));
+                }
+                let expr = self.parse_pipe()?;
+                self.expect(&Token::BracketClose)?;
+                return Ok(Expr::Array(Some(Box::new(expr))));
+            }
+            Some(Token::BraceOpen) => return self.parse_object(),
+            Some(Token::Variable(_)) => return self.parse_variable(),
+            _ => return Err(self.unexpected()),
+        };
+        self.next();
+        Ok(expr)
+    }
+
+    // `if c then a (elif c then a)* (else b)? end`
+    fn parse_if(&mut self) -> Result<Expr, SyntaxError> {
+        self.expect_keyword("if")?;
+        self.parse_if_branches()
+    }
+
+    // everything after `if` or `elif`, a nested `elif` consumes the shared `end`
+    fn parse_if_branches(&mut self) -> Result<Expr, SyntaxError> {
+        let cond = self.parse_pipe()?;
+        self.expect_keyword("then")?;
+        let then = self.parse_pipe()?;
+        let otherwise = if self.eat_keyword("elif") {
+            let nested = self.parse_if_branches()?;
+            return Ok(Expr::If(
+                Box::new(cond),
+                Box::new(then),
+                Some(Box::new(nested)),
+            ));
+        } else if self.eat_keyword("else") {
+            Some(Box::new(self.parse_pipe()?))
+        } else {
+            None
+        };
+        self.expect_keyword("end")?;
+        Ok(Expr::If(Box::new(cond), Box::new(then), otherwise))
+    }
+
+    // `try body (catch handler)?`, both are postfix terms like in jq, so
+    // `try .a | .b` is `(try .a) | .b`
+    fn parse_try(&mut self) -> Result<Expr, SyntaxError> {
+        self.expect_keyword("try")?;
+        let body = self.parse_postfix()?;
+        let handler = match self.eat_keyword("catch") {
+            true => Some(Box::new(self.parse_postfix()?)),
+            false => None,
+        };
+        Ok(Expr::Try(Box::new(body), handler))
+    }
+
+    // `reduce source as $x (init; update)` or
+    // `foreach source as $x (init; update; extract)`, the source is a postfix
+    // term and the variables are visible in the update and the extract only
+    fn parse_fold(&mut self) -> Result<Expr, SyntaxError> {
+        let foreach = self.eat_keyword("foreach");
+        if !foreach {
+            self.expect_keyword("reduce")?;
+        }
+        let source = self.parse_postfix()?;
+        self.expect_keyword("as")?;
+        let scope = self.vars.len();
+        let patterns = self.parse_patterns()?;
+        let bound = self.vars.split_off(scope);
+
+        self.expect(&Token::ParenOpen)?;
+        let init = self.parse_pipe()?;
+        self.expect(&Token::Semicolon)?;
+        self.vars.extend(bound);
+        let update = self.parse_pipe()?;
+        let extract = match foreach && self.eat(&Token::Semicolon) {
+            true => Some(Box::new(self.parse_pipe()?)),
+            false => None,
+        };
+        self.expect(&Token::ParenClose)?;
+        self.vars.truncate(scope);
+
+        let (source, init, update) = (Box::new(source), Box::new(init), Box::new(update));
+        Ok(match foreach {
+            true => Expr::Foreach(source, patterns, init, update, extract),
+            false => Expr::Reduce(source, patterns, init, update),
+        })
+    }
+
+    // `def name: body;` or `def name(f; $x): body;`, the function and its
+    // parameters are in scope in the body
+    fn parse_def(&mut self) -> Result<FuncDef, SyntaxError> {
+        self.expect_keyword("def")?;
+        let name = match self.peek() {
+            Some(Token::Ident(name)) => name.clone(),
+            _ => return Err(self.unexpected()),
+        };
+        self.next();
+        let mut params = Vec::new();
+        if self.eat(&Token::ParenOpen) {
+            loop {
+                params.push(match self.peek() {
+                    Some(Token::Ident(name)) => Param::Filter(name.clone()),
+                    Some(Token::Variable(name)) => Param::Value(name.clone()),
+                    _ => return Err(self.unexpected()),
+                });
+                self.next();
+                if self.eat(&Token::ParenClose) {
+                    break;
+                }
+                self.expect(&Token::Semicolon)?;
+            }
+        }
+        self.expect(&Token::Colon)?;
+
+        let (funcs, vars) = (self.funcs.len(), self.vars.len());
+        self.funcs.push((name.clone(), params.len()));
+        for param in &params {
+            self.funcs.push((param.name().to_string(), 0));
+            if let Param::Value(name) = param {
+                self.vars.push(name.clone());
+            }
+        }
+        let body = self.parse_pipe();
+        self.funcs.truncate(funcs);
+        self.vars.truncate(vars);
+        let body = body?;
+        self.expect(&Token::Semicolon)?;
+
+        Ok(FuncDef { name, params, body })
+    }
+
+    // `name` or `name(a; b)`, the arguments are unevaluated filters
+    fn parse_call(&mut self) -> Result<Expr, SyntaxError> {
+        let t = &self.tokens[self.pos];
+        let Token::Ident(name) = &t.token else {
+            return Err(self.unexpected());
+        };
+        self.next();
+        let mut args = Vec::new();
+        if self.eat(&Token::ParenOpen) {
+            loop {
+                args.push(self.parse_pipe()?);
+                if self.eat(&Token::ParenClose) {
+                    break;
+                }
+                self.expect(&Token::Semicolon)?;
+            }
+        }
+        if !self.is_defined(name, args.len()) {
+            return Err(SyntaxError::new(
+                t.span,
+                SyntaxErrorKind::UndefinedFunction(name.clone(), args.len()),
+            ));
+        }
+        Ok(Expr::Call(name.clone(), args))
+    }
+
+    // a definition or parameter in scope, or a builtin
+    fn is_defined(&self, name: &str, arity: usize) -> bool {
+        self.funcs.iter().any(|(n, a)| n == name && *a == arity)
+            || self
+                .prelude
+                .iter()
+                .any(|def| def.name == name && def.params.len() == arity)
+            || builtins::lookup(name, arity).is_some()
+    }
+
+    fn parse_variable(&mut self) -> Result<Expr, SyntaxError> {
+        let t = &self.tokens[self.pos];
+        let Token::Variable(name) = &t.token else {
+            return Err(self.unexpected());
+        };
+        if !self.vars.contains(name) {
+            return Err(SyntaxError::new(
+                t.span,
+                SyntaxErrorKind::UndefinedVariable(name.clone()),
+            ));
+        }
+        self.next();
+        Ok(Expr::Var(name.clone()))
+    }
+
+    // `{a, "b": .x, "c-\(.n)": 1, @base64: .y, (.k): .v, $x, $y: 1}`
+    fn parse_object(&mut self) -> Result<Expr, SyntaxError> {
+        self.expect(&Token::BraceOpen)?;
+        let mut entries = Vec::new();
+        if self.eat(&Token::BraceClose) {
+            return Ok(Expr::Object(entries));
+        }
+        loop {
+            entries.push(self.parse_object_entry()?);
+            if self.eat(&Token::BraceClose) {
+                return Ok(Expr::Object(entries));
+            }
+            self.expect(&Token::Comma)?;
+        }
+    }
+
+    fn parse_object_entry(&mut self) -> Result<(Expr, Expr), SyntaxError> {
+        // the key, and the value when there is no `: value`
+        let (key, shorthand) = match self.peek() {
+            Some(Token::Ident(name) | Token::String(name)) => {
+                let key = Expr::Literal(Value::String(name.clone()));
+                let shorthand = field(Expr::Identity, name);
+                self.next();
+                (key, Some(shorthand))
+            }
+            // `{@base64: v}` uses the input in that format as key
+            Some(Token::Format(name)) if self.peek_at(1) == Some(&Token::Colon) => {
+                let name = name.clone();
+                self.check_format(&name)?;
+                self.next();
+                (Expr::Format(name), None)
+            }
+            // `{"\(.k)"}` is `{"\(.k)": .["\(.k)"]}`
+            Some(Token::InterpolatedString(_) | Token::Format(_)) => {
+                let key = self.parse_string()?;
+                let shorthand = Expr::Index(Box::new(Expr::Identity), Box::new(key.clone()));
+                (key, Some(shorthand))
+            }
+            // `{$x}` is `{x: $x}`, while `{$x: v}` uses the value of `$x` as key
+            Some(Token::Variable(name)) => {
+                let name = Expr::Literal(Value::String(name.clone()));
+                let var = self.parse_variable()?;
+                match self.peek() {
+                    Some(Token::Colon) => (var, None),
+                    _ => (name, Some(var)),
+                }
+            }
+            Some(Token::ParenOpen) => {
+                self.next();
+                let key = self.parse_pipe()?;
+                self.expect(&Token::ParenClose)?;
+                (key, None)
+            }
+            _ => return Err(self.unexpected()),
+        };
+
+        if self.eat(&Token::Colon) {
+            return Ok((key, self.parse_object_value()?));
+        }
+        match shorthand {
+            Some(value) => Ok((key, value)),
+            None => Err(self.unexpected()),
+        }
+    }
+
+    // values are terms, optionally negated or piped, as in jq: `{a: .b | .c}`
+    // but `{a: (1, 2)}`
+    fn parse_object_value(&mut self) -> Result<Expr, SyntaxError> {
+        let value = if self.eat(&Token::Minus) {
+            Expr::Neg(Box::new(self.parse_object_value()?))
+        } else {
+            self.parse_postfix()?
+        };
+        if self.eat(&Token::Pipe) {
+            let rhs = self.parse_object_value()?;
+            return Ok(Expr::Pipe(Box::new(value), Box::new(rhs)));
+        }
+        Ok(value)
+    }
+
+    // `"s"`, `"a\(e)b"`, `@name` or `@name "a\(e)b"`
+    fn parse_string(&mut self) -> Result<Expr, SyntaxError> {
+        let tokens = self.tokens;
+        let format = match tokens.get(self.pos).map(|t| &t.token) {
+            Some(Token::Format(name)) => {
+                self.check_format(name)?;
+                self.next();
+                if !matches!(
+                    self.peek(),
+                    Some(Token::String(_) | Token::InterpolatedString(_))
+                ) {
+                    return Ok(Expr::Format(name.clone()));
+                }
+                Some(name.clone())
+            }
+            _ => None,
+        };
+        let parts = match tokens.get(self.pos).map(|t| &t.token) {
+            // the format doesn't apply to literal parts
+            Some(Token::String(s)) if format.is_none() => {
+                self.next();
+                return Ok(Expr::Literal(Value::String(s.clone())));
+            }
+            Some(Token::String(s)) => vec![StringPart::Literal(s.clone())],
+            Some(Token::InterpolatedString(segments)) => self.parse_segments(segments)?,
+            _ => return Err(self.unexpected()),
+        };
+        self.next();
+        Ok(Expr::Interpolate(format, parts))
+    }
+
+    // each interpolation is parsed on its own, in the current scope
+    fn parse_segments(&mut self, segments: &'a [Segment]) -> Result<Vec<StringPart>, SyntaxError> {
+        let mut parts = Vec::new();
+        for segment in segments {
+            match segment {
+                Segment::Literal(s) if s.is_empty() => {}
+                Segment::Literal(s) => parts.push(StringPart::Literal(s.clone())),
+                Segment::Interpolation(tokens, close) => {
+                    let saved = (self.tokens, self.pos, self.eof);
+                    (self.tokens, self.pos, self.eof) = (tokens, 0, *close);
+                    let expr = self.parse_pipe().and_then(|expr| match self.peek() {
+                        Some(_) => Err(self.unexpected()),
+                        None => Ok(expr),
+                    });
+                    (self.tokens, self.pos, self.eof) = saved;
+                    parts.push(StringPart::Expr(expr?));
+                }
+            }
+        }
+        Ok(parts)
+    }
+
+    fn check_format(&self, name: &str) -> Result<(), SyntaxError> {
+        if format::lookup(name).is_none() {
+            return Err(SyntaxError::new(
+                self.tokens[self.pos].span,
+                SyntaxErrorKind::UnknownFormat(name.to_string()),
+            ));
+        }
+        Ok(())
+    }
+
+    fn parse_brackets(&mut self, expr: Expr) -> Result<Expr, SyntaxError> {
+        self.expect(&Token::BracketOpen)?;
+        if self.eat(&Token::BracketClose) {
+            return Ok(Expr::Iterate(Box::new(expr)));
+        }
+        // `[:end]`
+        if self.eat(&Token::Colon) {
+            let end = self.parse_pipe()?;
+            self.expect(&Token::BracketClose)?;
+            return Ok(Expr::Slice(Box::new(expr), None, Some(Box::new(end))));
+        }
+        let index = self.parse_pipe()?;
+        if self.eat(&Token::Colon) {
+            let end = match self.eat(&Token::BracketClose) {
+                true => None,
+                false => {
+                    let end = self.parse_pipe()?;
+                    self.expect(&Token::BracketClose)?;
+                    Some(Box::new(end))
+                }
+            };
+            return Ok(Expr::Slice(Box::new(expr), Some(Box::new(index)), end));
+        }
+        self.expect(&Token::BracketClose)?;
+        Ok(Expr::Index(Box::new(expr), Box::new(index)))
+    }
+}
+
+fn field(expr: Expr, name: &str) -> Expr {
+    Expr::Index(
+        Box::new(expr),
+        Box::new(Expr::Literal(Value::String(name.to_string()))),
+    )
+}
+
+#[cfg(test)]
+mod parser {
+    use super::*;
+    use crate::filter::lexer::{end_of_input, tokenize};
+
+    #[test]
+    fn test_parse_identity() {
+        assert_eq!(parse_str("."), Ok(Expr::Identity));
+        assert_eq!(parse_str(""), Ok(Expr::Identity));
+        assert_eq!(parse_str("(.)"), Ok(Expr::Identity));
+    }
+
+    #[test]
+    fn test_parse_fields() {
+        let expected = field(field(Expr::Identity, "a"), "b c");
+        assert_eq!(parse_str(r#".a."b c""#), Ok(expected.clone()));
+        assert_eq!(parse_str(r#".["a"]["b c"]"#), Ok(expected.clone()));
+        assert_eq!(parse_str(r#"."a".["b c"]"#), Ok(expected));
+    }
+
+    #[test]
+    fn test_parse_index_and_pipe() {
+        assert_eq!(
+            parse_str(".[-1] | .x"),
+            Ok(Expr::Pipe(
+                Box::new(Expr::Index(
+                    Box::new(Expr::Identity),
+                    Box::new(Expr::Neg(Box::new(Expr::Literal(Value::Number(1.0))))),
+                )),
+                Box::new(field(Expr::Identity, "x")),
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_generators() {
+        assert_eq!(
+            parse_str(".[], .a[] | [.[]]"),
+            Ok(Expr::Pipe(
+                Box::new(Expr::Comma(
+                    Box::new(Expr::Iterate(Box::new(Expr::Identity))),
+                    Box::new(Expr::Iterate(Box::new(field(Expr::Identity, "a")))),
+                )),
+                Box::new(Expr::Array(Some(Box::new(Expr::Iterate(Box::new(
+                    Expr::Identity
+                )))))),
+            ))
+        );
+        assert_eq!(
+            parse_str("..|.a"),
+            Ok(Expr::Pipe(
+                Box::new(Expr::Call("recurse".to_string(), vec![])),
+                Box::new(field(Expr::Identity, "a")),
+            ))
+        );
+        assert_eq!(
+            parse_str("[], empty"),
+            Ok(Expr::Comma(
+                Box::new(Expr::Array(None)),
+                Box::new(Expr::Call("empty".to_string(), vec![])),
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_arithmetic() {
+        let number = |n: f64| Box::new(Expr::Literal(Value::Number(n)));
+        let binary = |op, lhs, rhs| Box::new(Expr::Binary(op, lhs, rhs));
+        assert_eq!(
+            parse_str("1 + 2 * 3 - -4 % 5"),
+            Ok(*binary(
+                BinaryOp::Sub,
+                binary(
+                    BinaryOp::Add,
+                    number(1.0),
+                    binary(BinaryOp::Mul, number(2.0), number(3.0))
+                ),
+                binary(BinaryOp::Mod, Box::new(Expr::Neg(number(4.0))), number(5.0)),
+            ))
+        );
+        assert_eq!(
+            parse_str("(1, 2) / 3, 4"),
+            Ok(Expr::Comma(
+                binary(
+                    BinaryOp::Div,
+                    Box::new(Expr::Comma(number(1.0), number(2.0))),
+                    number(3.0)
+                ),
+                number(4.0),
+            ))
+        );
+
+        let err = parse_str("1 +").unwrap_err();
+        assert_eq!(err.kind(), &SyntaxErrorKind::UnexpectedEndOfFilter);
+        let err = parse_str("1 * * 2").unwrap_err();
+        assert_eq!(err.to_string(), "1:5: Unexpected `*`");
+    }
+
+    #[test]
+    fn test_parse_logic() {
+        let field = |name| Box::new(field(Expr::Identity, name));
+        assert_eq!(
+            parse_str(".a or .b and .c == .d + 1, not"),
+            Ok(Expr::Comma(
+                Box::new(Expr::Or(
+                    field("a"),
+                    Box::new(Expr::And(
+                        field("b"),
+                        Box::new(Expr::Binary(
+                            BinaryOp::Eq,
+                            field("c"),
+                            Box::new(Expr::Binary(
+                                BinaryOp::Add,
+                                field("d"),
+                                Box::new(Expr::Literal(Value::Number(1.0)))
+                            ))
+                        ))
+                    ))
+                )),
+                Box::new(Expr::Call("not".to_string(), vec![])),
+            ))
+        );
+
+        assert_eq!(
+            parse_str("group_by(.a; 1)").unwrap_err().to_string(),
+            "1:1: group_by/2 is not defined"
+        );
+        assert_eq!(
+            parse_str("group_by(.a, .b | .c)"),
+            Ok(Expr::Call(
+                "group_by".to_string(),
+                vec![Expr::Pipe(
+                    Box::new(Expr::Comma(field("a"), field("b"))),
+                    field("c")
+                )]
+            ))
+        );
+
+        let err = parse_str("1 < 2 < 3").unwrap_err();
+        assert_eq!(err.to_string(), "1:7: Unexpected `<`");
+        let err = parse_str(".a and").unwrap_err();
+        assert_eq!(err.kind(), &SyntaxErrorKind::UnexpectedEndOfFilter);
+    }
+
+    #[test]
+    fn test_parse_if() {
+        let field = |name| Box::new(field(Expr::Identity, name));
+        assert_eq!(
+            parse_str("if .a then .b elif .c then .d else .e end"),
+            Ok(Expr::If(
+                field("a"),
+                field("b"),
+                Some(Box::new(Expr::If(field("c"), field("d"), Some(field("e")))))
+            ))
+        );
+        assert_eq!(
+            parse_str("if .a then .b end.c"),
+            Ok(Expr::Index(
+                Box::new(Expr::If(field("a"), field("b"), None)),
+                Box::new(Expr::Literal(Value::String("c".to_string())))
+            ))
+        );
+
+        let err = parse_str("if .a then .b").unwrap_err();
+        assert_eq!(err.kind(), &SyntaxErrorKind::UnexpectedEndOfFilter);
+        let err = parse_str("if .a else .b end").unwrap_err();
+        assert_eq!(err.to_string(), "1:7: Unexpected `else`");
+    }
+
+    #[test]
+    fn test_parse_error_handling() {
+        let field = |name| Box::new(field(Expr::Identity, name));
+        assert_eq!(
+            parse_str(".a? // .b[]? // 1"),
+            Ok(Expr::Alternative(
+                Box::new(Expr::Try(field("a"), None)),
+                Box::new(Expr::Alternative(
+                    Box::new(Expr::Try(Box::new(Expr::Iterate(field("b"))), None)),
+                    Box::new(Expr::Literal(Value::Number(1.0)))
+                ))
+            ))
+        );
+        assert_eq!(
+            parse_str("try .a catch . | .b"),
+            Ok(Expr::Pipe(
+                Box::new(Expr::Try(field("a"), Some(Box::new(Expr::Identity)))),
+                field("b")
+            ))
+        );
+        assert_eq!(
+            parse_str(".a // .b, .c"),
+            Ok(Expr::Comma(
+                Box::new(Expr::Alternative(field("a"), field("b"))),
+                field("c")
+            ))
+        );
+
+        let err = parse_str("try").unwrap_err();
+        assert_eq!(err.kind(), &SyntaxErrorKind::UnexpectedEndOfFilter);
+        let err = parse_str(". catch .").unwrap_err();
+        assert_eq!(err.to_string(), "1:3: Unexpected `catch`");
+    }
+
+    #[test]
+    fn test_parse_assignments() {
+        let field = |name| Box::new(field(Expr::Identity, name));
+        let one = || Box::new(Expr::Literal(Value::Number(1.0)));
+        assert_eq!(
+            parse_str(".a = .b or .c // 1"),
+            Ok(Expr::Alternative(
+                Box::new(Expr::Assign(
+                    AssignOp::Set,
+                    field("a"),
+                    Box::new(Expr::Or(field("b"), field("c")))
+                )),
+                one()
+            ))
+        );
+        assert_eq!(
+            parse_str(".a |= . + 1, .b -= 1 | .c //= 1"),
+            Ok(Expr::Pipe(
+                Box::new(Expr::Comma(
+                    Box::new(Expr::Assign(
+                        AssignOp::Update,
+                        field("a"),
+                        Box::new(Expr::Binary(BinaryOp::Add, Box::new(Expr::Identity), one()))
+                    )),
+                    Box::new(Expr::Assign(
+                        AssignOp::Arithmetic(BinaryOp::Sub),
+                        field("b"),
+                        one()
+                    ))
+                )),
+                Box::new(Expr::Assign(AssignOp::Alternative, field("c"), one()))
+            ))
+        );
+
+        let err = parse_str(".a = .b = 1").unwrap_err();
+        assert_eq!(err.to_string(), "1:9: Unexpected `=`");
+        let err = parse_str(".a +=").unwrap_err();
+        assert_eq!(err.kind(), &SyntaxErrorKind::UnexpectedEndOfFilter);
+    }
+
+    #[test]
+    fn test_parse_bindings() {
+        let var = |name: &str| Expr::Var(name.to_string());
+        let string = |s: &str| Expr::Literal(Value::String(s.to_string()));
+        assert_eq!(
+            parse_str(".a as $x | $x, 1"),
+            Ok(Expr::Bind(
+                Box::new(field(Expr::Identity, "a")),
+                vec![Pattern::Var("x".to_string())],
+                Box::new(Expr::Comma(
+                    Box::new(var("x")),
+                    Box::new(Expr::Literal(Value::Number(1.0)))
+                ))
+            ))
+        );
+        assert_eq!(
+            parse_str(r#". as [$a, {b: $c, $d, "e": [$f], $g: {$h}, ($a): $i}] ?// $j | $j"#),
+            Ok(Expr::Bind(
+                Box::new(Expr::Identity),
+                vec![
+                    Pattern::Array(vec![
+                        Pattern::Var("a".to_string()),
+                        Pattern::Object(vec![
+                            (string("b"), Pattern::Var("c".to_string())),
+                            (string("d"), Pattern::Var("d".to_string())),
+                            (
+                                string("e"),
+                                Pattern::Array(vec![Pattern::Var("f".to_string())])
+                            ),
+                            (string("g"), Pattern::Var("g".to_string())),
+                            (
+                                string("g"),
+                                Pattern::Object(vec![(string("h"), Pattern::Var("h".to_string()))])
+                            ),
+                            (var("a"), Pattern::Var("i".to_string())),
+                        ]),
+                    ]),
+                    Pattern::Var("j".to_string()),
+                ],
+                Box::new(var("j"))
+            ))
+        );
+        assert_eq!(
+            parse_str(".a?//1"),
+            Ok(Expr::Alternative(
+                Box::new(Expr::Try(Box::new(field(Expr::Identity, "a")), None)),
+                Box::new(Expr::Literal(Value::Number(1.0)))
+            ))
+        );
+
+        // variables are only visible in the body
+        let err = parse_str("(1 as $x | $x), $x").unwrap_err();
+        assert_eq!(err.to_string(), "1:17: $x is not defined");
+        let err = parse_str(". as {$a, ($b): $c} | 1").unwrap_err();
+        assert_eq!(err.to_string(), "1:12: $b is not defined");
+        let err = parse_str(". as [] | 1").unwrap_err();
+        assert_eq!(err.to_string(), "1:7: Unexpected `]`");
+        let err = parse_str(". as {a} | 1").unwrap_err();
+        assert_eq!(err.to_string(), "1:8: Unexpected `}`");
+        let err = parse_str(". as $x").unwrap_err();
+        assert_eq!(err.kind(), &SyntaxErrorKind::UnexpectedEndOfFilter);
+    }
+
+    #[test]
+    fn test_parse_reduce_and_foreach() {
+        let var = |name: &str| Box::new(Expr::Var(name.to_string()));
+        let number = |n: f64| Box::new(Expr::Literal(Value::Number(n)));
+        let iterate = || Box::new(Expr::Iterate(Box::new(Expr::Identity)));
+        assert_eq!(
+            parse_str("reduce .[] as $x (0; . + $x) | -."),
+            Ok(Expr::Pipe(
+                Box::new(Expr::Reduce(
+                    iterate(),
+                    vec![Pattern::Var("x".to_string())],
+                    number(0.0),
+                    Box::new(Expr::Binary(
+                        BinaryOp::Add,
+                        Box::new(Expr::Identity),
+                        var("x")
+                    ))
+                )),
+                Box::new(Expr::Neg(Box::new(Expr::Identity)))
+            ))
+        );
+        assert_eq!(
+            parse_str("foreach .[] as [$a] ?// $a (0; $a; [$a, .])"),
+            Ok(Expr::Foreach(
+                iterate(),
+                vec![
+                    Pattern::Array(vec![Pattern::Var("a".to_string())]),
+                    Pattern::Var("a".to_string())
+                ],
+                number(0.0),
+                var("a"),
+                Some(Box::new(Expr::Array(Some(Box::new(Expr::Comma(
+                    var("a"),
+                    Box::new(Expr::Identity)
+                ))))))
+            ))
+        );
+        assert_eq!(
+            parse_str("foreach 1 as $x (0; 1)"),
+            Ok(Expr::Foreach(
+                number(1.0),
+                vec![Pattern::Var("x".to_string())],
+                number(0.0),
+                number(1.0),
+                None
+            ))
+        );
+
+        // the variables are not visible in the init, nor after the parentheses
+        let err = parse_str("reduce .[] as $x ($x; .)").unwrap_err();
+        assert_eq!(err.to_string(), "1:19: $x is not defined");
+        let err = parse_str("reduce .[] as $x (0; .) | $x").unwrap_err();
+        assert_eq!(err.to_string(), "1:27: $x is not defined");
+        let err = parse_str("reduce .[] as $x (0; .; .)").unwrap_err();
+        assert_eq!(err.to_string(), "1:23: Unexpected `;`");
+        let err = parse_str("foreach .[] as $x (0)").unwrap_err();
+        assert_eq!(err.to_string(), "1:21: Unexpected `)`");
+    }
+
+    #[test]
+    fn test_parse_definitions() {
+        let call = |name: &str| Expr::Call(name.to_string(), vec![]);
+        assert_eq!(
+            parse_str("def f(g; $x): g | $x; f(.; 1) | f(.; 2)"),
+            Ok(Expr::Def(
+                Box::new(FuncDef {
+                    name: "f".to_string(),
+                    params: vec![
+                        Param::Filter("g".to_string()),
+                        Param::Value("x".to_string())
+                    ],
+                    body: Expr::Pipe(Box::new(call("g")), Box::new(Expr::Var("x".to_string()))),
+                }),
+                Box::new(Expr::Pipe(
+                    Box::new(Expr::Call(
+                        "f".to_string(),
+                        vec![Expr::Identity, Expr::Literal(Value::Number(1.0))]
+                    )),
+                    Box::new(Expr::Call(
+                        "f".to_string(),
+                        vec![Expr::Identity, Expr::Literal(Value::Number(2.0))]
+                    ))
+                ))
+            ))
+        );
+        // recursive, nested, and shadowing a builtin
+        assert!(parse_str("def f: f; f").is_ok());
+        assert!(parse_str("def f: def g: 1; g; f").is_ok());
+        assert!(parse_str("1 as $x | def f($y): $x + $y + y; f(2)").is_ok());
+        assert!(parse_str("def empty: 1; def map: map(.); . | def f: 1; f").is_ok());
+
+        let err = parse_str("(def f: 1; f), f").unwrap_err();
+        assert_eq!(err.to_string(), "1:16: f/0 is not defined");
+        let err = parse_str("def f: def g: 1; g; g").unwrap_err();
+        assert_eq!(err.to_string(), "1:21: g/0 is not defined");
+        let err = parse_str("def f(g): g; g").unwrap_err();
+        assert_eq!(err.to_string(), "1:14: g/0 is not defined");
+        let err = parse_str("def f(g): g(1); 1").unwrap_err();
+        assert_eq!(err.to_string(), "1:11: g/1 is not defined");
+        let err = parse_str("def f($x): 1; $x").unwrap_err();
+        assert_eq!(err.to_string(), "1:15: $x is not defined");
+        let err = parse_str("def f(.): 1; 1").unwrap_err();
+        assert_eq!(err.to_string(), "1:7: Unexpected `.`");
+        let err = parse_str("def f: 1").unwrap_err();
+        assert_eq!(err.kind(), &SyntaxErrorKind::UnexpectedEndOfFilter);
+    }
+
+    #[test]
+    fn test_parse_slices() {
+        let number = |n: f64| Some(Box::new(Expr::Literal(Value::Number(n))));
+        let slice = |start, end| Ok(Expr::Slice(Box::new(Expr::Identity), start, end));
+        assert_eq!(parse_str(".[2:5]"), slice(number(2.0), number(5.0)));
+        assert_eq!(parse_str(".[3:]"), slice(number(3.0), None));
+        assert_eq!(
+            parse_str(".[:-1]"),
+            slice(None, Some(Box::new(Expr::Neg(number(1.0).unwrap()))))
+        );
+        assert_eq!(
+            parse_str(".a[1:]"),
+            Ok(Expr::Slice(
+                Box::new(field(Expr::Identity, "a")),
+                number(1.0),
+                None
+            ))
+        );
+
+        let err = parse_str(".[:]").unwrap_err();
+        assert_eq!(err.to_string(), "1:4: Unexpected `]`");
+        let err = parse_str(".[1:2:3]").unwrap_err();
+        assert_eq!(err.to_string(), "1:6: Unexpected `:`");
+    }
+
+    #[test]
+    fn test_parse_object() {
+        let string = |s: &str| Expr::Literal(Value::String(s.to_string()));
+        assert_eq!(
+            parse_str(r#"{a, "b c", if: .x | .y, (.k): -1, $ENV, $ENV: 2}"#),
+            Ok(Expr::Object(vec![
+                (string("a"), field(Expr::Identity, "a")),
+                (string("b c"), field(Expr::Identity, "b c")),
+                (
+                    string("if"),
+                    Expr::Pipe(
+                        Box::new(field(Expr::Identity, "x")),
+                        Box::new(field(Expr::Identity, "y")),
+                    )
+                ),
+                (
+                    field(Expr::Identity, "k"),
+                    Expr::Neg(Box::new(Expr::Literal(Value::Number(1.0))))
+                ),
+                (string("ENV"), Expr::Var("ENV".to_string())),
+                (
+                    Expr::Var("ENV".to_string()),
+                    Expr::Literal(Value::Number(2.0))
+                ),
+            ]))
+        );
+        assert_eq!(parse_str("{}"), Ok(Expr::Object(vec![])));
+
+        let err = parse_str("{a: 1, b: 2 + 3}").unwrap_err();
+        assert_eq!(err.to_string(), "1:13: Unexpected `+`");
+        let err = parse_str("{a: 1, b: 2, 3}").unwrap_err();
+        assert_eq!(err.to_string(), "1:14: Unexpected `3`");
+        let err = parse_str("{(.a)}").unwrap_err();
+        assert_eq!(err.to_string(), "1:6: Unexpected `}`");
+        let err = parse_str("{a: 1, b: 2").unwrap_err();
+        assert_eq!(err.kind(), &SyntaxErrorKind::UnexpectedEndOfFilter);
+        let err = parse_str("{$x}").unwrap_err();
+        assert_eq!(err.to_string(), "1:2: $x is not defined");
+    }
+
+    #[test]
+    fn test_parse_strings() {
+        let string = |s: &str| Expr::Literal(Value::String(s.to_string()));
+        let literal = |s: &str| StringPart::Literal(s.to_string());
+        let name = Expr::Interpolate(
+            None,
+            vec![
+                literal("Hello "),
+                StringPart::Expr(field(Expr::Identity, "name")),
+            ],
+        );
+        assert_eq!(parse_str(r#""Hello \(.name)""#), Ok(name.clone()));
+        assert_eq!(
+            parse_str(r#"@csv "\(.a),\(.b)""#),
+            Ok(Expr::Interpolate(
+                Some("csv".to_string()),
+                vec![
+                    StringPart::Expr(field(Expr::Identity, "a")),
+                    literal(","),
+                    StringPart::Expr(field(Expr::Identity, "b")),
+                ]
+            ))
+        );
+        assert_eq!(
+            parse_str(r#"@sh "ls""#),
+            Ok(Expr::Interpolate(
+                Some("sh".to_string()),
+                vec![literal("ls")]
+            ))
+        );
+        assert_eq!(
+            parse_str("@base64 | @base64d"),
+            Ok(Expr::Pipe(
+                Box::new(Expr::Format("base64".to_string())),
+                Box::new(Expr::Format("base64d".to_string())),
+            ))
+        );
+        // interpolations see the variables in scope
+        assert!(parse_str(r#". as $x | "\("\($x)")""#).is_ok());
+        assert_eq!(
+            parse_str(r#"."x\(1)""#),
+            Ok(Expr::Index(
+                Box::new(Expr::Identity),
+                Box::new(Expr::Interpolate(
+                    None,
+                    vec![
+                        literal("x"),
+                        StringPart::Expr(Expr::Literal(Value::Number(1.0)))
+                    ]
+                ))
+            ))
+        );
+        assert_eq!(
+            parse_str(r#"{"Hello \(.name)", @json: 1, @uri "u": 2}"#),
+            Ok(Expr::Object(vec![
+                (
+                    name.clone(),
+                    Expr::Index(Box::new(Expr::Identity), Box::new(name))
+                ),
+                (
+                    Expr::Format("json".to_string()),
+                    Expr::Literal(Value::Number(1.0))
+                ),
+                (
+                    Expr::Interpolate(Some("uri".to_string()), vec![literal("u")]),
+                    Expr::Literal(Value::Number(2.0))
+                ),
+            ]))
+        );
+        assert!(matches!(
+            parse_str(r#". as {"a\(1)": $x} | $x"#),
+            Ok(Expr::Bind(_, _, _))
+        ));
+        assert_eq!(parse_str(r#""\\(x)""#), Ok(string("\\(x)")));
+
+        let err = parse_str(r#"@foo "x""#).unwrap_err();
+        assert_eq!(err.to_string(), "1:1: @foo is not a valid format");
+        let err = parse_str(r#""a\(.b c)""#).unwrap_err();
+        assert_eq!(err.to_string(), "1:8: Unexpected `c`");
+        let err = parse_str(r#""a\()""#).unwrap_err();
+        assert_eq!(err.to_string(), "1:5: Unexpected end of filter");
+        let err = parse_str(r#""\($x)""#).unwrap_err();
+        assert_eq!(err.to_string(), "1:4: $x is not defined");
+        let err = parse_str("@json:").unwrap_err();
+        assert_eq!(err.to_string(), "1:6: Unexpected `:`");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let err = parse_str(".a |").unwrap_err();
+        assert_eq!(err.kind(), &SyntaxErrorKind::UnexpectedEndOfFilter);
+        assert_eq!(err.span().column, 5);
+
+        let err = parse_str(".a )").unwrap_err();
+        assert_eq!(
+            err.kind(),
+            &SyntaxErrorKind::UnexpectedToken(")".to_string())
+        );
+
+        let err = parse_str(".[0").unwrap_err();
+        assert_eq!(err.kind(), &SyntaxErrorKind::UnexpectedEndOfFilter);
+
+        let err = parse_str(". ]").unwrap_err();
+        assert_eq!(err.to_string(), "1:3: Unexpected `]`");
+
+        let err = parse_str(".a, nope").unwrap_err();
+        assert_eq!(err.to_string(), "1:5: nope/0 is not defined");
+    }
+
+    fn parse_str(source: &str) -> Result<Expr, SyntaxError> {
+        parse(&tokenize(source)?, end_of_input(source))
+    }
+}
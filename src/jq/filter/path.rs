@@ -0,0 +1,806 @@
+use std::collections::HashSet;
+use std::iter;
+use std::mem;
+use std::sync::Arc;
+
+use super::env::{Env, Function};
+use super::eval::{self, Outputs, RuntimeError};
+use super::parser::{FuncDef, Param, Pattern};
+use super::{builtins, Expr};
+use crate::Value;
+
+/// Where a value is in the input of a path expression: the keys, indices and
+/// `{"start": s, "end": e}` slices leading to it.
+pub type Path = Vec<Value>;
+
+/// A value with its path, or `None` for values that don't come from the input,
+/// like literals. Those are only an error once used as a path.
+pub type Tracked = (Option<Path>, Value);
+
+// Largest index an array can be grown to by an update, jq's `INT_MAX >> 2`.
+const MAX_ARRAY_INDEX: usize = (i32::MAX >> 2) as usize;
+
+/// Lazy stream of the outputs of an expression evaluated as a path expression.
+pub type PathOutputs<'a> = Box<dyn Iterator<Item = Result<Tracked, RuntimeError>> + 'a>;
+
+/// The path of each output of `expr` within `input`, as in `path(expr)`.
+pub fn paths<'a>(
+    expr: &'a Expr,
+    env: &Env<'a>,
+    input: Value,
+) -> Box<dyn Iterator<Item = Result<Path, RuntimeError>> + 'a> {
+    Box::new(
+        eval_paths(expr, env, (Some(Vec::new()), input)).map(|output| {
+            let (path, value) = output?;
+            path.ok_or_else(|| {
+                RuntimeError::msg(format!("Invalid path expression with result {}", value))
+            })
+        }),
+    )
+}
+
+// Evaluates `expr` like `eval::eval`, following the path of each value through
+// the expressions that select parts of their input. Any other expression gives
+// values without a path.
+pub fn eval_paths<'a>(expr: &'a Expr, env: &Env<'a>, input: Tracked) -> PathOutputs<'a> {
+    match expr {
+        Expr::Identity => single(Ok(input)),
+        Expr::Index(target, index) => {
+            let (env, target_input) = (env.clone(), input.clone());
+            with_values(eval::eval(index, &env, input.1), move |index| {
+                Box::new(
+                    eval_paths(target, &env, target_input.clone())
+                        .map(move |output| output.and_then(|output| step(output, &index))),
+                )
+            })
+        }
+        Expr::Slice(target, start, end) => {
+            let (env, end_input, target_input) = (env.clone(), input.1.clone(), input.clone());
+            with_values(eval::eval_bound(start, &env, input.1), move |start| {
+                let (target_env, target_input) = (env.clone(), target_input.clone());
+                with_values(eval::eval_bound(end, &env, end_input.clone()), move |end| {
+                    let slice = Value::Object(
+                        vec![
+                            ("start".to_string(), start.clone()),
+                            ("end".to_string(), end),
+                        ]
+                        .into(),
+                    );
+                    Box::new(
+                        eval_paths(target, &target_env, target_input.clone())
+                            .map(move |output| output.and_then(|output| step(output, &slice))),
+                    )
+                })
+            })
+        }
+        Expr::Iterate(expr) => then(eval_paths(expr, env, input), iterate),
+        Expr::If(cond, then_branch, else_branch) => {
+            let (env, branch_input) = (env.clone(), input.clone());
+            with_values(eval::eval(cond, &env, input.1), move |cond| {
+                match (cond.is_truthy(), else_branch) {
+                    (true, _) => eval_paths(then_branch, &env, branch_input.clone()),
+                    (false, Some(else_branch)) => {
+                        eval_paths(else_branch, &env, branch_input.clone())
+                    }
+                    (false, None) => single(Ok(branch_input.clone())),
+                }
+            })
+        }
+        Expr::Try(body, handler) => {
+            let mut body = eval_paths(body, env, input);
+            let mut failed = false;
+            let outputs = iter::from_fn(move || {
+                if failed {
+                    return None;
+                }
+                let output = body.next()?;
+                failed = output.is_err();
+                Some(output)
+            });
+            let env = env.clone();
+            Box::new(outputs.flat_map(move |output| match (output, handler) {
+                (Ok(output), _) => single(Ok(output)),
+                (Err(e), Some(handler)) => untracked(eval::eval(handler, &env, e.into_value())),
+                (Err(_), None) => Box::new(iter::empty()),
+            }))
+        }
+        Expr::Alternative(lhs, rhs) => alternative(lhs, rhs, env.clone(), input),
+        // unlike in `eval::eval`, an error in the body doesn't move on to the
+        // next pattern of `?//`
+        Expr::Bind(source, patterns, body) => {
+            let scope = eval::pattern_scope(patterns, env);
+            let body_input = input.clone();
+            with_values(
+                eval::eval(source, env, input.1),
+                move |value| match eval::first_match(patterns, &scope, &value, &body_input.1) {
+                    Ok(scopes) => {
+                        let body_input = body_input.clone();
+                        Box::new(
+                            scopes.into_iter().flat_map(move |scope| {
+                                eval_paths(body, &scope, body_input.clone())
+                            }),
+                        )
+                    }
+                    Err(e) => single(Err(e)),
+                },
+            )
+        }
+        Expr::Reduce(source, patterns, init, update) => {
+            let (env, source_input) = (env.clone(), input.1.clone());
+            then(eval_paths(init, &env, input), move |init| {
+                single(reduce(
+                    source,
+                    patterns,
+                    update,
+                    &env,
+                    source_input.clone(),
+                    init,
+                ))
+            })
+        }
+        Expr::Call(name, args) => call(expr, name, args, env, input),
+        Expr::Def(def, rest) => {
+            let env = env.bind_function(&def.name, Function::Def(def, env.clone()));
+            eval_paths(rest, &env, input)
+        }
+        Expr::Comma(lhs, rhs) => {
+            Box::new(eval_paths(lhs, env, input.clone()).chain(eval_paths(rhs, env, input)))
+        }
+        Expr::Pipe(lhs, rhs) => {
+            let env = env.clone();
+            then(eval_paths(lhs, &env, input), move |output| {
+                eval_paths(rhs, &env, output)
+            })
+        }
+        _ => untracked(eval::eval(expr, env, input.1)),
+    }
+}
+
+// Functions are resolved as in `eval::eval`. The native builtins that select
+// parts of their input have their own path versions.
+fn call<'a>(
+    expr: &'a Expr,
+    name: &'a str,
+    args: &'a [Expr],
+    env: &Env<'a>,
+    input: Tracked,
+) -> PathOutputs<'a> {
+    match env.function(name, args.len()) {
+        Some(Function::Def(def, scope)) => return apply(def, scope, args, env, input),
+        Some(Function::Arg(arg, scope)) => return eval_paths(arg, scope, input),
+        None => {}
+    }
+    if let Some(def) = builtins::definition(name, args.len()) {
+        return apply(def, &Env::default(), args, env, input);
+    }
+    match builtins::lookup_path(name, args.len()) {
+        Some(builtin) => builtin(args, env, input),
+        None => untracked(eval::eval(expr, env, input.1)),
+    }
+}
+
+fn apply<'a>(
+    def: &'a FuncDef,
+    scope: &Env<'a>,
+    args: &'a [Expr],
+    env: &Env<'a>,
+    input: Tracked,
+) -> PathOutputs<'a> {
+    let mut body_scope = scope.bind_function(&def.name, Function::Def(def, scope.clone()));
+    for (param, arg) in def.params.iter().zip(args) {
+        body_scope = body_scope.bind_function(param.name(), Function::Arg(arg, env.clone()));
+    }
+    bind_values(&def.params, args, env.clone(), body_scope, &def.body, input)
+}
+
+// value arguments are evaluated against the input value, as in `eval::eval`
+fn bind_values<'a>(
+    params: &'a [Param],
+    args: &'a [Expr],
+    env: Env<'a>,
+    scope: Env<'a>,
+    body: &'a Expr,
+    input: Tracked,
+) -> PathOutputs<'a> {
+    let Some(i) = params.iter().position(|p| matches!(p, Param::Value(_))) else {
+        return eval_paths(body, &scope, input);
+    };
+    let (name, rest, rest_args) = (params[i].name(), &params[i + 1..], &args[i + 1..]);
+    let body_input = input.clone();
+    with_values(eval::eval(&args[i], &env, input.1), move |value| {
+        bind_values(
+            rest,
+            rest_args,
+            env.clone(),
+            scope.bind(name, value),
+            body,
+            body_input.clone(),
+        )
+    })
+}
+
+// `a // b`, with the same outputs as `eval::eval`
+fn alternative<'a>(lhs: &'a Expr, rhs: &'a Expr, env: Env<'a>, input: Tracked) -> PathOutputs<'a> {
+    let mut lhs_outputs = Some(eval_paths(lhs, &env, input.clone()));
+    let mut rhs_outputs: Option<PathOutputs<'a>> = None;
+    let mut found = false;
+    Box::new(iter::from_fn(move || {
+        if let Some(outputs) = &mut lhs_outputs {
+            for output in outputs.by_ref() {
+                match output {
+                    Ok(output) if output.1.is_truthy() => {
+                        found = true;
+                        return Some(Ok(output));
+                    }
+                    _ => {}
+                }
+            }
+            lhs_outputs = None;
+            if !found {
+                rhs_outputs = Some(eval_paths(rhs, &env, input.clone()));
+            }
+        }
+        rhs_outputs.as_mut()?.next()
+    }))
+}
+
+// `reduce` with the accumulator tracked, so that `reduce .[] as $k (.; .[$k])`
+// is a path
+fn reduce<'a>(
+    source: &'a Expr,
+    patterns: &'a [Pattern],
+    update: &'a Expr,
+    env: &Env<'a>,
+    input: Value,
+    init: Tracked,
+) -> Result<Tracked, RuntimeError> {
+    let outer = eval::pattern_scope(patterns, env);
+    let mut acc = init;
+    for value in eval::eval(source, env, input.clone()) {
+        for scope in eval::first_match(patterns, &outer, &value?, &input)? {
+            let mut next = (None, Value::Null);
+            for output in eval_paths(update, &scope, acc) {
+                next = output?;
+            }
+            acc = next;
+        }
+    }
+    Ok(acc)
+}
+
+/// The elements of an array or the values of an object, with their paths.
+/// Other values and values without a path have none.
+pub fn children<'a>((path, value): Tracked) -> PathOutputs<'a> {
+    let Some(path) = path else {
+        return Box::new(iter::empty());
+    };
+    let child = move |key: Value, value: Value| {
+        let mut path = path.clone();
+        path.push(key);
+        Ok((Some(path), value))
+    };
+    match value {
+        Value::Array(elements) => Box::new(
+            (0..elements.len()).map(move |i| child(Value::Number(i as f64), elements[i].clone())),
+        ),
+        Value::Object(fields) => Box::new((0..fields.len()).map(move |i| {
+            let (key, value) = &fields[i];
+            child(Value::String(key.clone()), value.clone())
+        })),
+        _ => Box::new(iter::empty()),
+    }
+}
+
+// `.[]` of a tracked value
+fn iterate<'a>(input: Tracked) -> PathOutputs<'a> {
+    match input {
+        (None, value) => single(Err(RuntimeError::msg(format!(
+            "Invalid path expression near attempt to iterate through {}",
+            value
+        )))),
+        (_, Value::Array(_) | Value::Object(_)) => children(input),
+        (_, value) => untracked(eval::iterate(value)),
+    }
+}
+
+// `.[key]` of a tracked value, with `key` appended to its path
+fn step((path, value): Tracked, key: &Value) -> Result<Tracked, RuntimeError> {
+    let Some(mut path) = path else {
+        return Err(RuntimeError::msg(format!(
+            "Invalid path expression near attempt to access element {} of {}",
+            key, value
+        )));
+    };
+    let child = eval::index_value(&value, key)?;
+    path.push(key.clone());
+    Ok((Some(path), child))
+}
+
+fn single<'a>(output: Result<Tracked, RuntimeError>) -> PathOutputs<'a> {
+    Box::new(iter::once(output))
+}
+
+// feeds each output to `f`, passing errors through
+fn then<'a>(
+    outputs: PathOutputs<'a>,
+    f: impl Fn(Tracked) -> PathOutputs<'a> + 'a,
+) -> PathOutputs<'a> {
+    Box::new(outputs.flat_map(move |output| match output {
+        Ok(output) => f(output),
+        Err(e) => single(Err(e)),
+    }))
+}
+
+// feeds each output of an expression that isn't a path to `f`
+fn with_values<'a>(
+    outputs: Outputs<'a>,
+    f: impl Fn(Value) -> PathOutputs<'a> + 'a,
+) -> PathOutputs<'a> {
+    Box::new(outputs.flat_map(move |output| match output {
+        Ok(value) => f(value),
+        Err(e) => single(Err(e)),
+    }))
+}
+
+// outputs of an expression that isn't a path
+fn untracked<'a>(outputs: Outputs<'a>) -> PathOutputs<'a> {
+    Box::new(outputs.map(|output| output.map(|value| (None, value))))
+}
+
+/// `getpath(path)`: the value at `path`, or `null` if part of it is missing.
+pub fn get_path(value: &Value, path: &[Value]) -> Result<Value, RuntimeError> {
+    let mut value = value.clone();
+    for key in path {
+        value = eval::index_value(&value, key)?;
+    }
+    Ok(value)
+}
+
+/// `setpath(path; value)`: `root` with `value` at `path`, creating the objects
+/// and arrays leading to it. Arrays and objects that nothing else holds are
+/// changed in place.
+pub fn set_path(root: Value, path: &[Value], value: Value) -> Result<Value, RuntimeError> {
+    update_path(root, path, |_| Ok(value))
+}
+
+/// `root` with the value at `path` replaced by `f` of it, `null` if it is
+/// missing. The value is moved out of `root` for `f`, so that `f` can change it
+/// in place too.
+pub fn update_path(
+    root: Value,
+    path: &[Value],
+    f: impl FnOnce(Value) -> Result<Value, RuntimeError>,
+) -> Result<Value, RuntimeError> {
+    check_length(path)?;
+    match path.split_first() {
+        None => f(root),
+        Some((key, rest)) => update(root, key, |child| update_path(child, rest, f)),
+    }
+}
+
+/// `delpaths(paths)`: `root` without the values at `paths`. As in jq, the
+/// paths are resolved against `root` first, so that an element reached by
+/// several of them, like `.[-1]` and `.[2]` of three, is deleted once, and the
+/// elements of an array are removed together, so that none shifts another.
+pub fn delete_paths(root: Value, paths: Vec<Path>) -> Result<Value, RuntimeError> {
+    if paths.iter().any(|path| path.is_empty()) {
+        return Ok(Value::Null);
+    }
+    for path in &paths {
+        check_length(path)?;
+    }
+    let mut deleted = Vec::new();
+    let mut nested = Vec::new();
+    for mut path in paths {
+        let rest = path.split_off(1);
+        let key = path.pop().expect("paths aren't empty");
+        if rest.is_empty() {
+            deleted.extend(deleted_keys(&root, &key)?);
+        } else if let Value::Null = eval::index_value(&root, &key)? {
+            // nothing to delete below a missing value
+        } else {
+            nested.push((resolve(&root, key)?, rest));
+        }
+    }
+    deleted.sort();
+    deleted.dedup();
+    nested.sort_by(|(a, _), (b, _)| a.start().cmp(&b.start()).then(a.cmp(b)));
+    let mut groups: Vec<(Key, Vec<Path>)> = Vec::new();
+    for (key, rest) in nested {
+        match groups.last_mut() {
+            Some((k, rests)) if *k == key => rests.push(rest),
+            _ => groups.push((key, vec![rest])),
+        }
+    }
+    // last first, removing the elements after each key before it, which
+    // doesn't move it
+    let mut root = root;
+    for (key, rests) in groups.into_iter().rev() {
+        let after = deleted.partition_point(|k| k.start() <= key.start());
+        root = remove(root, deleted.split_off(after));
+        // nothing to delete below a value deleted as a whole
+        if deleted.binary_search(&key).is_err() {
+            root = update(root, &key.to_value(), |child| delete_paths(child, rests))?;
+        }
+    }
+    Ok(remove(root, deleted))
+}
+
+// Paths are updated and deleted a level of recursion per key, so they can't
+// be longer than parsed values can be deep.
+fn check_length(path: &[Value]) -> Result<(), RuntimeError> {
+    if path.len() > crate::parser::MAX_DEPTH {
+        return Err(RuntimeError::msg("Exceeds depth limit for paths"));
+    }
+    Ok(())
+}
+
+// A path component resolved against the value it indexes: the field of an
+// object, the index of an array counted from the start, or the range of a
+// slice. Other components are kept for their errors.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum Key {
+    Field(String),
+    Index(usize),
+    Slice(usize, usize),
+    Other(Value),
+}
+
+impl Key {
+    // where the key starts in an array
+    fn start(&self) -> usize {
+        match self {
+            Key::Index(start) | Key::Slice(start, _) => *start,
+            Key::Field(_) | Key::Other(_) => 0,
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        match self {
+            Key::Field(key) => Value::String(key.clone()),
+            Key::Index(i) => Value::Number(*i as f64),
+            Key::Slice(start, end) => Value::Object(Arc::new(vec![
+                ("start".to_string(), Value::Number(*start as f64)),
+                ("end".to_string(), Value::Number(*end as f64)),
+            ])),
+            Key::Other(key) => key.clone(),
+        }
+    }
+}
+
+fn resolve(root: &Value, key: Value) -> Result<Key, RuntimeError> {
+    Ok(match (root, key) {
+        (Value::Array(elements), Value::Number(n)) => Key::Index(array_index(n, elements.len())?),
+        (Value::Array(elements), Value::Object(bounds)) => {
+            let (start, end) = eval::slice_bounds(&bounds);
+            let (start, end) = eval::slice_range(elements.len(), &start, &end)?;
+            Key::Slice(start, end)
+        }
+        (Value::Object(_), Value::String(key)) => Key::Field(key),
+        (_, key) => Key::Other(key),
+    })
+}
+
+// the keys of the children of `root` that deleting `key` removes
+fn deleted_keys(root: &Value, key: &Value) -> Result<Vec<Key>, RuntimeError> {
+    match (root, key) {
+        (Value::Null, _) => Ok(Vec::new()),
+        (Value::Object(_), Value::String(key)) => Ok(vec![Key::Field(key.clone())]),
+        (Value::Array(elements), Value::Number(n)) => {
+            let i = array_index(*n, elements.len())?;
+            Ok(if i < elements.len() {
+                vec![Key::Index(i)]
+            } else {
+                Vec::new()
+            })
+        }
+        (Value::Array(elements), Value::Object(bounds)) => {
+            let (start, end) = eval::slice_bounds(bounds);
+            let (start, end) = eval::slice_range(elements.len(), &start, &end)?;
+            Ok((start..end).map(Key::Index).collect())
+        }
+        (root, key) => Err(RuntimeError::msg(format!(
+            "Cannot delete field at {} index of {}",
+            key.type_name(),
+            root.type_name()
+        ))),
+    }
+}
+
+// `root` without its children at the sorted `keys`
+fn remove(root: Value, keys: Vec<Key>) -> Value {
+    if keys.is_empty() {
+        return root;
+    }
+    match root {
+        Value::Object(mut fields) => {
+            let keys = keys
+                .iter()
+                .filter_map(|key| match key {
+                    Key::Field(key) => Some(key.as_str()),
+                    _ => None,
+                })
+                .collect::<HashSet<_>>();
+            Arc::make_mut(&mut fields).retain(|(k, _)| !keys.contains(k.as_str()));
+            Value::Object(fields)
+        }
+        Value::Array(mut elements) => {
+            let mut i = 0;
+            Arc::make_mut(&mut elements).retain(|_| {
+                i += 1;
+                keys.binary_search(&Key::Index(i - 1)).is_err()
+            });
+            Value::Array(elements)
+        }
+        root => root,
+    }
+}
+
+// Replaces the child of `root` at `key` with `f` of it, `null` being an empty
+// object or array. The child is moved out of `root` first, so that `f` can
+// change it in place.
+fn update(
+    root: Value,
+    key: &Value,
+    f: impl FnOnce(Value) -> Result<Value, RuntimeError>,
+) -> Result<Value, RuntimeError> {
+    match (root, key) {
+        (Value::Null, Value::String(_)) => update(Value::Object(Arc::default()), key, f),
+        (Value::Null, Value::Number(_) | Value::Object(_)) => {
+            update(Value::Array(Arc::default()), key, f)
+        }
+        (Value::Object(mut fields), Value::String(key)) => {
+            let fields_mut = Arc::make_mut(&mut fields);
+            match fields_mut.iter_mut().find(|(k, _)| k == key) {
+                Some((_, value)) => *value = f(mem::replace(value, Value::Null))?,
+                None => fields_mut.push((key.clone(), f(Value::Null)?)),
+            }
+            Ok(Value::Object(fields))
+        }
+        (Value::Array(mut elements), Value::Number(n)) => {
+            let i = array_index(*n, elements.len())?;
+            if i > MAX_ARRAY_INDEX {
+                return Err(RuntimeError::msg("Array index too large"));
+            }
+            let elements_mut = Arc::make_mut(&mut elements);
+            if i >= elements_mut.len() {
+                elements_mut.resize(i + 1, Value::Null);
+            }
+            elements_mut[i] = f(mem::replace(&mut elements_mut[i], Value::Null))?;
+            Ok(Value::Array(elements))
+        }
+        (Value::Array(mut elements), Value::Object(bounds)) => {
+            let (start, end) = eval::slice_bounds(bounds);
+            let (start, end) = eval::slice_range(elements.len(), &start, &end)?;
+            let elements_mut = Arc::make_mut(&mut elements);
+            let slice = elements_mut.drain(start..end).collect::<Vec<_>>();
+            match f(Value::Array(slice.into()))? {
+                Value::Array(slice) => {
+                    elements_mut.splice(start..start, Arc::unwrap_or_clone(slice));
+                }
+                _ => {
+                    return Err(RuntimeError::msg(
+                        "A slice of an array can only be assigned another array",
+                    ))
+                }
+            }
+            Ok(Value::Array(elements))
+        }
+        (root, key) => {
+            // the same error as getting it, if there is one
+            eval::index_value(&root, key)?;
+            Err(RuntimeError::msg(format!(
+                "Cannot update field at object index of {}",
+                root.type_name()
+            )))
+        }
+    }
+}
+
+// position of index `n` in an array of `len`, counting from the end if negative
+fn array_index(n: f64, len: usize) -> Result<usize, RuntimeError> {
+    let i = n.floor();
+    let i = if i < 0.0 { i + len as f64 } else { i };
+    if i < 0.0 {
+        return Err(RuntimeError::msg("Out of bounds negative array index"));
+    }
+    Ok(i as usize)
+}
+
+#[cfg(test)]
+mod path {
+    use super::*;
+    use crate::filter::Filter;
+
+    #[test]
+    fn test_path_expressions() {
+        let input = r#"{"a": [{"b": 1}, {"b": null}], "c": "x"}"#;
+        assert_eq!(paths(".a[1].b", input), Ok(r#"[["a",1,"b"]]"#.to_string()));
+        assert_eq!(
+            paths(".a[].b, .c", input),
+            Ok(r#"[["a",0,"b"],["a",1,"b"],["c"]]"#.to_string())
+        );
+        assert_eq!(
+            paths(".a[-1:]", input),
+            Ok(r#"[["a",{"start":-1,"end":null}]]"#.to_string())
+        );
+        assert_eq!(
+            paths(".a[] | select(.b) | .b", input),
+            Ok(r#"[["a",0,"b"]]"#.to_string())
+        );
+        assert_eq!(
+            paths(".a[] | .b // .missing", input),
+            Ok(r#"[["a",0,"b"],["a",1,"missing"]]"#.to_string())
+        );
+        assert_eq!(
+            paths(r#"if .c == "x" then .c else empty end, .a[5]?"#, input),
+            Ok(r#"[["c"],["a",5]]"#.to_string())
+        );
+        assert_eq!(
+            paths(r#""a" as $k | .[$k][0] | try error catch empty"#, input),
+            Ok("[]".to_string())
+        );
+        assert_eq!(
+            paths(r#"def f(g): .a[] | g; f(.b), getpath(["c"])"#, input),
+            Ok(r#"[["a",0,"b"],["a",1,"b"],["c"]]"#.to_string())
+        );
+        assert_eq!(
+            paths(r#"reduce ("a", 0) as $k (.; .[$k]) | .."#, input),
+            Ok(r#"[["a",0],["a",0,"b"]]"#.to_string())
+        );
+        assert_eq!(
+            paths("1", input),
+            Err("Invalid path expression with result 1".to_string())
+        );
+        assert_eq!(
+            paths(".a | .[0] + {} | .b", input),
+            Err(
+                r#"Invalid path expression near attempt to access element "b" of {"b":1}"#
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            paths("[.a[].b] | .[]", input),
+            Err("Invalid path expression near attempt to iterate through [1,null]".to_string())
+        );
+        // values that don't come from the input are fine if they aren't used
+        assert_eq!(paths("1 | empty", input), Ok("[]".to_string()));
+    }
+
+    #[test]
+    fn test_set_path() {
+        let set = |root: &str, path: &str, value: &str| {
+            set_path(parse(root), &keys(path), parse(value))
+                .map(|v| v.to_string())
+                .map_err(|e| e.to_string())
+        };
+        assert_eq!(set("null", "[]", "1"), Ok("1".to_string()));
+        assert_eq!(
+            set(r#"{"a": 1}"#, r#"["b", "c"]"#, "2"),
+            Ok(r#"{"a":1,"b":{"c":2}}"#.to_string())
+        );
+        assert_eq!(set("[1]", "[2]", "3"), Ok("[1,null,3]".to_string()));
+        assert_eq!(set("[1, 2]", "[-1]", "3"), Ok("[1,3]".to_string()));
+        assert_eq!(
+            set("[1, 2, 3]", r#"[{"start": 1, "end": null}]"#, "[4]"),
+            Ok("[1,4]".to_string())
+        );
+        assert_eq!(
+            set("[1]", "[-2]", "3"),
+            Err("Out of bounds negative array index".to_string())
+        );
+        assert_eq!(
+            set("null", "[1e18]", "1"),
+            Err("Array index too large".to_string())
+        );
+        assert_eq!(
+            set("[[1]]", "[0, 536870912]", "1"),
+            Err("Array index too large".to_string())
+        );
+        assert_eq!(
+            set("[1]", r#"["a"]"#, "3"),
+            Err(r#"Cannot index array with "a""#.to_string())
+        );
+        assert_eq!(
+            set("[1]", r#"[{"start": 0}]"#, "3"),
+            Err("A slice of an array can only be assigned another array".to_string())
+        );
+        assert_eq!(
+            set(r#""abc""#, r#"[{"start": 0}]"#, "3"),
+            Err("Cannot update field at object index of string".to_string())
+        );
+        let long = vec![Value::Number(0.0); crate::parser::MAX_DEPTH + 1];
+        assert_eq!(
+            set_path(Value::Null, &long, Value::Null).map_err(|e| e.to_string()),
+            Err("Exceeds depth limit for paths".to_string())
+        );
+        assert_eq!(
+            delete_paths(Value::Null, vec![long]).map_err(|e| e.to_string()),
+            Err("Exceeds depth limit for paths".to_string())
+        );
+
+        // an array that nothing else holds is changed in place
+        let root = parse("[[1, 2], 3]");
+        let Value::Array(outer) = &root else { panic!() };
+        let Value::Array(inner) = &outer[0] else {
+            panic!()
+        };
+        let inner = Arc::as_ptr(inner);
+        let root = set_path(root, &keys("[0, 1]"), Value::Null).unwrap();
+        let Value::Array(outer) = &root else { panic!() };
+        let Value::Array(updated) = &outer[0] else {
+            panic!()
+        };
+        assert_eq!(Arc::as_ptr(updated), inner);
+    }
+
+    #[test]
+    fn test_delete_paths() {
+        let delete = |root: &str, paths: &str| {
+            let Value::Array(paths) = parse(paths) else {
+                panic!()
+            };
+            let paths = paths.iter().map(|p| keys(&p.to_string())).collect();
+            delete_paths(parse(root), paths)
+                .map(|v| v.to_string())
+                .map_err(|e| e.to_string())
+        };
+        assert_eq!(
+            delete("[0, 1, 2, 3]", "[[0], [2], [7]]"),
+            Ok("[1,3]".to_string())
+        );
+        assert_eq!(
+            delete(
+                r#"{"a": {"b": 1, "c": 2}, "d": [1, 2]}"#,
+                r#"[["a", "b"], ["d", -1], ["x", "y"]]"#
+            ),
+            Ok(r#"{"a":{"c":2},"d":[1]}"#.to_string())
+        );
+        assert_eq!(
+            delete(
+                "[0, 1, 2, 3]",
+                r#"[[{"start": 1, "end": 3}], [{"start": 0, "end": 1}, 0]]"#
+            ),
+            Ok("[3]".to_string())
+        );
+        // duplicate, negative and overlapping paths delete an element once
+        assert_eq!(
+            delete("[0, 1, 2, 3]", "[[1], [1], [-3], [3], [-1]]"),
+            Ok("[0,2]".to_string())
+        );
+        assert_eq!(
+            delete(
+                "[0, 1, 2, 3, 4]",
+                r#"[[{"start": 1, "end": 3}], [{"start": 2, "end": null}], [3]]"#
+            ),
+            Ok("[0]".to_string())
+        );
+        assert_eq!(
+            delete(r#"[{"a": 1, "b": 2}, 5]"#, r#"[[0, "a"], [-2, "b"], [1]]"#),
+            Ok("[{}]".to_string())
+        );
+        assert_eq!(delete("[1]", "[[]]"), Ok("null".to_string()));
+        assert_eq!(
+            delete("{}", "[[0]]"),
+            Err("Cannot delete field at number index of object".to_string())
+        );
+    }
+
+    // `[path(filter)]`
+    fn paths(filter: &str, input: &str) -> Result<String, String> {
+        let filter = Filter::parse(&format!("[path({})]", filter)).unwrap();
+        let output = filter.run(parse(input)).next().unwrap();
+        output.map(|v| v.to_string()).map_err(|e| e.to_string())
+    }
+
+    fn keys(path: &str) -> Path {
+        match parse(path) {
+            Value::Array(keys) => keys.to_vec(),
+            _ => panic!("not a path: {}", path),
+        }
+    }
+
+    fn parse(json: &str) -> Value {
+        crate::parse_str(json).unwrap()
+    }
+}
@@ -1,29 +1,285 @@
+// unit test modules are named after the module they test
+#![allow(clippy::module_inception)]
+
+use anyhow::Context;
+use diagnostics::{Diagnostic, Diagnostics};
 use log::debug;
+use std::error::Error;
+use std::fmt::Display;
+use std::sync::Arc;
 use std::{fs::File, io::Read};
-use tokenizer::Tokenizer;
+use tokenizer::{Span, SpannedToken, Tokenizer};
 
+pub mod diagnostics;
+pub mod filter;
 pub mod parser;
 pub mod tokenizer;
+mod value;
+
+/// Owned JSON document tree returned by [`parse_str`].
+pub use parser::ASTNode as Value;
+pub use parser::{RecordError, Records};
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ParseOptions {
+    // accept several top-level values, e.g. `{} {}`
+    pub multi: bool,
+    // report every syntax error instead of stopping at the first one
+    pub all_errors: bool,
+}
 
 pub fn process_file(filename: &str) -> anyhow::Result<()> {
-    let mut file = File::open(filename)?;
+    process_file_with(filename, ParseOptions::default())
+}
 
-    let mut contents = String::new();
-    file.read_to_string(&mut contents)?;
+pub fn process_file_with(filename: &str, options: ParseOptions) -> anyhow::Result<()> {
+    let file = File::open(filename).with_context(|| format!("Could not open {}", filename))?;
+
+    process_reader_with(file, filename, options)
+}
+
+// `name` identifies the input in error messages, e.g. `<stdin>`
+pub fn process_reader<R: Read>(reader: R, name: &str) -> anyhow::Result<()> {
+    process_reader_with(reader, name, ParseOptions::default())
+}
 
-    process_str(&contents)?;
+pub fn process_reader_with<R: Read>(
+    reader: R,
+    name: &str,
+    options: ParseOptions,
+) -> anyhow::Result<()> {
+    parse_reader_with(reader, name, options)?;
 
     Ok(())
 }
 
 pub fn process_str(contents: &str) -> anyhow::Result<()> {
+    process_str_with(contents, ParseOptions::default())
+}
+
+pub fn process_str_with(contents: &str, options: ParseOptions) -> anyhow::Result<()> {
+    parse_str_with(contents, options)?;
+
+    Ok(())
+}
+
+/// Collects every syntax error in `contents`, in input order.
+pub fn diagnose_str(contents: &str, options: ParseOptions) -> Vec<Diagnostic> {
+    parse_recovering(contents, options).1
+}
+
+fn parse_recovering(contents: &str, options: ParseOptions) -> (Vec<Value>, Vec<Diagnostic>) {
+    let (tokens, token_errors) = Tokenizer::new(contents).collect_recovering();
+
+    let tokens = &mut tokens.iter().peekable();
+    let (values, parse_errors) = if options.multi {
+        parser::parse_multi_recovering(tokens)
+    } else {
+        let (value, errors) = parser::parse_recovering(tokens);
+        (value.into_iter().collect(), errors)
+    };
+
+    let mut diagnostics: Vec<Diagnostic> = token_errors
+        .iter()
+        .map(Diagnostic::from)
+        .chain(parse_errors.iter().map(Diagnostic::from))
+        .collect();
+    diagnostics.sort_by_key(|d| d.span.offset);
+    (values, diagnostics)
+}
+
+/// Parses the top-level values in `contents` according to `options`. Empty
+/// input has no values.
+pub fn parse_str_with(contents: &str, options: ParseOptions) -> anyhow::Result<Vec<Value>> {
+    if options.all_errors {
+        let (values, diagnostics) = parse_recovering(contents, options);
+        if !diagnostics.is_empty() {
+            return Err(Diagnostics(diagnostics).into());
+        }
+        return Ok(values);
+    }
+
+    let tokens = tokenize(contents)?;
+    let tokens = &mut tokens.iter().peekable();
+
+    if options.multi {
+        Ok(parser::parse_multi(tokens)?)
+    } else {
+        Ok(parser::parse(tokens)?.into_iter().collect())
+    }
+}
+
+/// Like `parse_str_with`, but also gives the line each value ends on, which
+/// jq reports with runtime errors.
+pub fn parse_str_with_lines(
+    contents: &str,
+    options: ParseOptions,
+) -> anyhow::Result<Vec<(Value, usize)>> {
+    let values = parse_str_with(contents, options)?;
+    if !options.multi {
+        // a single document ends on the last line with content
+        let line = contents.trim_end().lines().count();
+        return Ok(values.into_iter().map(|value| (value, line)).collect());
+    }
+
+    // the input is valid, so its records are exactly its values
+    Ok(parse_many(contents)
+        .with_lines()
+        .collect::<Result<_, _>>()?)
+}
+
+pub fn parse_reader_with<R: Read>(
+    reader: R,
+    name: &str,
+    options: ParseOptions,
+) -> anyhow::Result<Vec<Value>> {
+    let contents = read_contents(reader, name)?;
+
+    parse_str_with(&contents, options).map_err(|e| FileError::wrap(name, contents.into(), e))
+}
+
+pub fn parse_file_with(filename: &str, options: ParseOptions) -> anyhow::Result<Vec<Value>> {
+    let file = File::open(filename).with_context(|| format!("Could not open {}", filename))?;
+
+    parse_reader_with(file, filename, options)
+}
+
+pub fn parse_reader_with_lines<R: Read>(
+    reader: R,
+    name: &str,
+    options: ParseOptions,
+) -> anyhow::Result<Vec<(Value, usize)>> {
+    let contents = read_contents(reader, name)?;
+
+    parse_str_with_lines(&contents, options).map_err(|e| FileError::wrap(name, contents.into(), e))
+}
+
+pub fn parse_file_with_lines(
+    filename: &str,
+    options: ParseOptions,
+) -> anyhow::Result<Vec<(Value, usize)>> {
+    let file = File::open(filename).with_context(|| format!("Could not open {}", filename))?;
+
+    parse_reader_with_lines(file, filename, options)
+}
+
+/// Parses `contents` into a [`Value`], failing on empty input.
+pub fn parse_str(contents: &str) -> anyhow::Result<Value> {
+    let tokens = tokenize(contents)?;
+
+    let value = parser::parse(&mut tokens.iter().peekable())?
+        .ok_or(parser::ParseError::UnexpectedEndOfInput(Span::default()))?;
+
+    Ok(value)
+}
+
+/// Yields each top-level value of a stream such as NDJSON in turn. A bad
+/// record yields a [`RecordError`] and parsing resumes at the next record.
+pub fn parse_many(contents: &str) -> Records {
+    debug!("Content: {}", contents);
+
+    let (tokens, token_errors) = Tokenizer::new(contents).collect_recovering();
+    debug!("Tokens: {:?}", tokens);
+
+    Records::new(tokens, token_errors)
+}
+
+/// Like [`parse_many`] for a named input, e.g. `<stdin>`. Errors are wrapped in
+/// a [`FileError`] so that diagnostics can point into the input.
+pub fn parse_many_reader<R: Read>(
+    reader: R,
+    name: &str,
+) -> anyhow::Result<impl Iterator<Item = anyhow::Result<Value>>> {
+    let records = parse_many_reader_lines(reader, name)?;
+
+    Ok(records.map(|record| record.map(|(value, _)| value)))
+}
+
+pub fn parse_many_file(
+    filename: &str,
+) -> anyhow::Result<impl Iterator<Item = anyhow::Result<Value>>> {
+    let file = File::open(filename).with_context(|| format!("Could not open {}", filename))?;
+
+    parse_many_reader(file, filename)
+}
+
+/// Like `parse_many_reader`, but also gives the line each record ends on.
+pub fn parse_many_reader_lines<R: Read>(
+    reader: R,
+    name: &str,
+) -> anyhow::Result<impl Iterator<Item = anyhow::Result<(Value, usize)>>> {
+    let contents: Arc<str> = read_contents(reader, name)?.into();
+    let name = name.to_string();
+
+    let records = parse_many(&contents).with_lines();
+    Ok(records
+        .map(move |record| record.map_err(|e| FileError::wrap(&name, contents.clone(), e.into()))))
+}
+
+pub fn parse_many_file_lines(
+    filename: &str,
+) -> anyhow::Result<impl Iterator<Item = anyhow::Result<(Value, usize)>>> {
+    let file = File::open(filename).with_context(|| format!("Could not open {}", filename))?;
+
+    parse_many_reader_lines(file, filename)
+}
+
+fn read_contents<R: Read>(mut reader: R, name: &str) -> anyhow::Result<String> {
+    let mut contents = String::new();
+    reader
+        .read_to_string(&mut contents)
+        .with_context(|| format!("Could not read {}", name))?;
+
+    Ok(contents)
+}
+
+fn tokenize(contents: &str) -> anyhow::Result<Vec<SpannedToken>> {
     debug!("Content: {}", contents);
 
     let tokenizer = Tokenizer::new(contents);
     let tokens = Tokenizer::try_collect(tokenizer)?;
     debug!("Tokens: {:?}", tokens);
 
-    parser::parse(&mut tokens.iter().peekable())?;
+    Ok(tokens)
+}
 
-    Ok(())
+/// Error in the contents of a named input, displayed as `file:line:col: message`.
+/// Keeps the contents around so that diagnostics can show the offending line.
+#[derive(Debug)]
+pub struct FileError {
+    filename: String,
+    // shared by every error of a stream
+    contents: Arc<str>,
+    error: anyhow::Error,
+}
+
+impl FileError {
+    fn wrap(filename: &str, contents: Arc<str>, error: anyhow::Error) -> anyhow::Error {
+        FileError {
+            filename: filename.to_string(),
+            contents,
+            error,
+        }
+        .into()
+    }
+
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    pub fn contents(&self) -> &str {
+        &self.contents
+    }
+}
+
+impl Display for FileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.filename, self.error)
+    }
+}
+
+impl Error for FileError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.error.as_ref())
+    }
 }
@@ -2,183 +2,559 @@ use std::error::Error;
 use std::fmt::{Debug, Display};
 use std::iter::Peekable;
 use std::slice::Iter;
+use std::sync::Arc;
 
-use super::tokenizer::Token;
+use super::tokenizer::{Span, SpannedToken, Token, TokenError};
 
-#[derive(Debug, PartialEq)]
-pub enum ASTNode<'a> {
-    Object(Vec<(&'a str, ASTNode<'a>)>),
-    Array(Vec<ASTNode<'a>>),
-    String(&'a str),
+/// How deeply objects and arrays may nest, as in jq. Parsing, printing and
+/// dropping values are recursive, so deeper input fails rather than
+/// overflowing the stack.
+pub const MAX_DEPTH: usize = 10_000;
+
+// Containers are shared so that values can be cloned cheaply while filtering,
+// `Arc::make_mut` copies them on write. Equality and ordering are jq's, see
+// `value.rs`.
+#[derive(Debug, Clone)]
+pub enum ASTNode {
+    Object(Arc<Vec<(String, ASTNode)>>),
+    Array(Arc<Vec<ASTNode>>),
+    String(String),
     Number(f64),
     Boolean(bool),
     Null,
 }
 
-pub fn parse<'a>(tokens: &mut Peekable<Iter<'a, Token>>) -> Result<(), ParseError> {
+// returns `None` if there are no tokens at all
+pub fn parse(tokens: &mut Peekable<Iter<'_, SpannedToken>>) -> Result<Option<ASTNode>, ParseError> {
     if tokens.len() == 0 {
-        return Ok(());
+        return Ok(None);
+    }
+
+    let mut ctx = Context::strict(end_of_input(tokens));
+    let node = parse_value(tokens, &mut ctx)?;
+
+    // a single document must not be followed by anything else
+    if let Some(t) = tokens.next() {
+        return Err(ParseError::UnexpectedTrailingToken(t.token.clone(), t.span));
+    }
+
+    Ok(Some(node))
+}
+
+// parses every top-level value in the token stream, e.g. `{} {}`
+pub fn parse_multi(
+    tokens: &mut Peekable<Iter<'_, SpannedToken>>,
+) -> Result<Vec<ASTNode>, ParseError> {
+    let mut ctx = Context::strict(end_of_input(tokens));
+    let mut nodes = Vec::new();
+    while tokens.peek().is_some() {
+        nodes.push(parse_value(tokens, &mut ctx)?);
+    }
+
+    Ok(nodes)
+}
+
+// Like `parse`, but keeps going after errors and collects all of them. The
+// returned value may be partial when there are errors.
+pub fn parse_recovering(
+    tokens: &mut Peekable<Iter<'_, SpannedToken>>,
+) -> (Option<ASTNode>, Vec<ParseError>) {
+    let (mut nodes, errors) = parse_values_recovering(tokens, false);
+    (nodes.pop(), errors)
+}
+
+// Like `parse_multi`, but keeps going after errors and collects all of them.
+pub fn parse_multi_recovering(
+    tokens: &mut Peekable<Iter<'_, SpannedToken>>,
+) -> (Vec<ASTNode>, Vec<ParseError>) {
+    parse_values_recovering(tokens, true)
+}
+
+fn parse_values_recovering(
+    tokens: &mut Peekable<Iter<'_, SpannedToken>>,
+    multi: bool,
+) -> (Vec<ASTNode>, Vec<ParseError>) {
+    let mut ctx = Context::recovering(end_of_input(tokens));
+    let mut nodes = Vec::new();
+    let mut errors = Vec::new();
+
+    while let Some(t) = tokens.peek() {
+        if !multi && !nodes.is_empty() {
+            errors.push(ParseError::UnexpectedTrailingToken(t.token.clone(), t.span));
+            break;
+        }
+        match parse_value(tokens, &mut ctx) {
+            Ok(node) => nodes.push(node),
+            Err(e) if e.is_fatal() => {
+                errors.push(e);
+                break;
+            }
+            Err(e) => errors.push(e),
+        }
     }
 
-    parse_value(tokens)?;
+    // errors inside containers come before the top-level ones
+    let mut all = ctx.errors.unwrap_or_default();
+    all.append(&mut errors);
+    all.sort_by_key(|e| e.span().offset);
+    (nodes, all)
+}
+
+// end of input errors point at the last token
+fn end_of_input(tokens: &Peekable<Iter<'_, SpannedToken>>) -> Span {
+    tokens.clone().last().map(|t| t.span).unwrap_or_default()
+}
+
+struct Context {
+    eof: Span,
+    // collected errors when recovering, `None` in strict mode
+    errors: Option<Vec<ParseError>>,
+    // objects and arrays the parser is in
+    depth: usize,
+}
+
+impl Context {
+    fn strict(eof: Span) -> Self {
+        Context {
+            eof,
+            errors: None,
+            depth: 0,
+        }
+    }
 
-    Ok(())
+    fn recovering(eof: Span) -> Self {
+        Context {
+            eof,
+            errors: Some(Vec::new()),
+            depth: 0,
+        }
+    }
+
+    // records the error when recovering, otherwise returns it
+    fn report(&mut self, err: ParseError) -> Result<(), ParseError> {
+        match &mut self.errors {
+            Some(errors) if !err.is_fatal() => {
+                errors.push(err);
+                Ok(())
+            }
+            _ => Err(err),
+        }
+    }
+}
+
+// skips tokens up to the next `,`, `}` or `]` of the current container
+fn synchronize(tokens: &mut Peekable<Iter<'_, SpannedToken>>) {
+    let mut depth = 0;
+    while let Some(t) = tokens.peek() {
+        match t.token {
+            Token::BraceOpen | Token::BracketOpen => depth += 1,
+            Token::BraceClose | Token::BracketClose if depth > 0 => depth -= 1,
+            Token::Comma | Token::BraceClose | Token::BracketClose if depth == 0 => break,
+            _ => {}
+        }
+        tokens.next();
+    }
 }
 
-fn parse_value<'a>(tokens: &mut Peekable<Iter<'a, Token>>) -> Result<ASTNode<'a>, ParseError> {
+fn parse_value(
+    tokens: &mut Peekable<Iter<'_, SpannedToken>>,
+    ctx: &mut Context,
+) -> Result<ASTNode, ParseError> {
     // consume first token here
-    let token = tokens.next().ok_or(ParseError::UnexpectedEndOfInput)?;
-    match &token {
-        Token::String(s) => Ok(ASTNode::String(s)),
+    let t = tokens
+        .next()
+        .ok_or(ParseError::UnexpectedEndOfInput(ctx.eof))?;
+    match &t.token {
+        Token::String(s) => Ok(ASTNode::String(s.clone())),
         Token::Number(n) => Ok(ASTNode::Number(*n)),
         Token::True => Ok(ASTNode::Boolean(true)),
         Token::False => Ok(ASTNode::Boolean(false)),
         Token::Null => Ok(ASTNode::Null),
-        Token::BraceOpen => parse_object(tokens),
-        Token::BracketOpen => parse_array(tokens),
-        _ => Err(ParseError::UnexpectedToken),
+        Token::BraceOpen | Token::BracketOpen if ctx.depth >= MAX_DEPTH => {
+            Err(ParseError::ExceedsDepthLimit(t.span))
+        }
+        Token::BraceOpen | Token::BracketOpen => {
+            ctx.depth += 1;
+            let node = match t.token {
+                Token::BraceOpen => parse_object(tokens, ctx),
+                _ => parse_array(tokens, ctx),
+            };
+            ctx.depth -= 1;
+            node
+        }
+        // already reported by the tokenizer
+        Token::Invalid => Ok(ASTNode::Null),
+        _ => Err(ParseError::UnexpectedToken(t.span)),
     }
 }
 
-fn parse_object<'a>(tokens: &mut Peekable<Iter<'a, Token>>) -> Result<ASTNode<'a>, ParseError> {
-    let mut node = ASTNode::Object(Vec::new());
+fn parse_object(
+    tokens: &mut Peekable<Iter<'_, SpannedToken>>,
+    ctx: &mut Context,
+) -> Result<ASTNode, ParseError> {
+    let mut fields = Vec::new();
     let mut is_first = true;
-    let mut expect_next_value = false;
+    // span of the comma after the last value, if any
+    let mut expect_next_value: Option<Span> = None;
 
     loop {
-        let token = tokens.next().ok_or(ParseError::UnexpectedEndOfInput)?;
-        match token {
+        let t = tokens
+            .next()
+            .ok_or(ParseError::UnexpectedEndOfInput(ctx.eof))?;
+        match &t.token {
             // end of object
             Token::BraceClose => {
-                if expect_next_value {
-                    return Err(ParseError::UnexpectedCommaBeforeEndOfObject);
+                if let Some(comma) = expect_next_value {
+                    ctx.report(ParseError::UnexpectedCommaBeforeEndOfObject(comma))?;
                 }
                 break;
             }
             // object key
             Token::String(s) => {
                 // if not first key, expect comma before next key
-                if !is_first && !expect_next_value {
-                    return Err(ParseError::MissingComma);
+                if !is_first && expect_next_value.is_none() {
+                    ctx.report(ParseError::MissingComma(t.span))?;
                 }
                 is_first = false;
 
-                let token = tokens.next();
-                if let Some(Token::Colon) = token {
-                    // get the value of this key recursively
-                    let value = parse_value(tokens);
-                    match value {
-                        // if value is parsed successfully, add it to the object with the key
-                        Ok(v) => match &mut node {
-                            ASTNode::Object(obj) => {
-                                obj.push((s, v));
-                                // if comma is after value, skip it and expect next value
-                                if let Some(Token::Comma) = tokens.peek() {
-                                    tokens.next();
-                                    expect_next_value = true;
-                                } else {
-                                    expect_next_value = false;
-                                }
+                match tokens.peek() {
+                    Some(SpannedToken {
+                        token: Token::Colon,
+                        ..
+                    }) => {
+                        tokens.next();
+                        // get the value of this key recursively
+                        match parse_value(tokens, ctx) {
+                            // if value is parsed successfully, add it to the object with the key
+                            Ok(v) => fields.push((s.clone(), v)),
+                            Err(e) => {
+                                ctx.report(e)?;
+                                synchronize(tokens);
                             }
-                            _ => panic!("Should never happen!"),
-                        },
-                        Err(e) => return Err(e),
+                        }
+                    }
+                    Some(t) => {
+                        ctx.report(ParseError::ExpectedColonAfterStringKey(t.span))?;
+                        synchronize(tokens);
                     }
-                } else {
-                    return Err(ParseError::ExpectedColonAfterStringKey);
+                    None => return Err(ParseError::ExpectedColonAfterStringKey(ctx.eof)),
                 }
+                // if comma is after value, skip it and expect next value
+                expect_next_value = next_comma(tokens);
+            }
+            // already reported by the tokenizer
+            Token::Invalid => {
+                synchronize(tokens);
+                expect_next_value = next_comma(tokens);
+            }
+            _ => {
+                ctx.report(ParseError::UnexpectedObjectKey(t.span))?;
+                synchronize(tokens);
+                expect_next_value = next_comma(tokens);
             }
-            _ => return Err(ParseError::UnexpectedObjectKey),
         }
     }
 
-    Ok(node)
+    Ok(ASTNode::Object(fields.into()))
 }
 
-fn parse_array<'a>(tokens: &mut Peekable<Iter<'a, Token>>) -> Result<ASTNode<'a>, ParseError> {
-    let mut node = ASTNode::Array(Vec::new());
+fn parse_array(
+    tokens: &mut Peekable<Iter<'_, SpannedToken>>,
+    ctx: &mut Context,
+) -> Result<ASTNode, ParseError> {
+    let mut elements = Vec::new();
     let mut is_first = true;
-    let mut expect_next_value = false;
+    // span of the comma after the last value, if any
+    let mut expect_next_value: Option<Span> = None;
 
     loop {
-        let token = tokens.peek().ok_or(ParseError::UnexpectedEndOfInput)?;
-        match token {
+        let t = tokens
+            .peek()
+            .ok_or(ParseError::UnexpectedEndOfInput(ctx.eof))?;
+        match &t.token {
             // end of array
             Token::BracketClose => {
-                if expect_next_value {
-                    return Err(ParseError::UnexpectedCommaBeforeEndOfArray);
+                if let Some(comma) = expect_next_value {
+                    ctx.report(ParseError::UnexpectedCommaBeforeEndOfArray(comma))?;
                 }
                 tokens.next();
                 break;
             }
             _ => {
                 // if not first value, expect comma before next value
-                if !is_first && !expect_next_value {
-                    return Err(ParseError::MissingComma);
+                if !is_first && expect_next_value.is_none() {
+                    ctx.report(ParseError::MissingCommaInArray(t.span))?;
                 }
-                is_first = true;
+                is_first = false;
 
                 // get the value of this array element recursively
-                let value = parse_value(tokens);
-                match value {
+                match parse_value(tokens, ctx) {
                     // if value is parsed successfully, add it to the array
-                    Ok(v) => match &mut node {
-                        ASTNode::Array(arr) => {
-                            arr.push(v);
-                            // if there is a comma after value, skip it and expect next value
-                            if let Some(Token::Comma) = tokens.peek() {
-                                tokens.next();
-                                expect_next_value = true;
-                            } else {
-                                expect_next_value = false;
-                            }
-                        }
-                        _ => panic!("Should never happen!"),
-                    },
-                    Err(e) => return Err(e),
+                    Ok(v) => elements.push(v),
+                    Err(e) => {
+                        ctx.report(e)?;
+                        synchronize(tokens);
+                    }
                 }
+                // if there is a comma after value, skip it and expect next value
+                expect_next_value = next_comma(tokens);
+            }
+        }
+    }
+
+    Ok(ASTNode::Array(elements.into()))
+}
+
+// consumes the next token if it is a comma and returns its span
+fn next_comma(tokens: &mut Peekable<Iter<'_, SpannedToken>>) -> Option<Span> {
+    tokens.next_if(|t| t.token == Token::Comma).map(|t| t.span)
+}
+
+// Iterator over the top-level values of a stream, e.g. concatenated JSON or
+// NDJSON. A bad record yields a `RecordError` and the stream resumes at the
+// next record, so callers decide whether to stop or skip it.
+pub struct Records {
+    tokens: Vec<SpannedToken>,
+    // reported by `collect_recovering`, one per `Token::Invalid`
+    token_errors: std::vec::IntoIter<TokenError>,
+    pos: usize,
+    index: usize,
+    eof: Span,
+}
+
+impl Records {
+    pub fn new(tokens: Vec<SpannedToken>, token_errors: Vec<TokenError>) -> Self {
+        let eof = tokens.last().map(|t| t.span).unwrap_or_default();
+        Records {
+            tokens,
+            token_errors: token_errors.into_iter(),
+            pos: 0,
+            index: 0,
+            eof,
+        }
+    }
+
+    /// Line the last record ended on, 0 before the first record.
+    pub fn line(&self) -> usize {
+        self.pos
+            .checked_sub(1)
+            .map_or(0, |last| self.tokens[last].span.line)
+    }
+
+    /// Yields each record with the line it ends on.
+    pub fn with_lines(mut self) -> impl Iterator<Item = Result<(ASTNode, usize), RecordError>> {
+        std::iter::from_fn(move || {
+            let record = self.next()?;
+            Some(record.map(|node| (node, self.line())))
+        })
+    }
+
+    // index of the first token after the bad record starting at `start`
+    fn resynchronize(&self, start: usize, err: &ParseError) -> usize {
+        // the record ends where its brackets balance
+        let mut depth = 0;
+        for (i, t) in self.tokens.iter().enumerate().skip(start) {
+            match t.token {
+                Token::BraceOpen | Token::BracketOpen => depth += 1,
+                Token::BraceClose | Token::BracketClose => depth -= 1,
+                _ => {}
+            }
+            if depth <= 0 {
+                return i + 1;
             }
         }
+
+        // an unclosed record ends at the first line starting after the error,
+        // as in NDJSON
+        let line = self.tokens[start].span.line;
+        (start + 1..self.tokens.len())
+            .find(|&i| {
+                let t = &self.tokens[i];
+                t.span.line > line
+                    && t.span.offset >= err.span().offset
+                    && self.tokens[i - 1].span.line < t.span.line
+            })
+            .unwrap_or(self.tokens.len())
     }
+}
+
+impl Iterator for Records {
+    type Item = Result<ASTNode, RecordError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.pos;
+        let line = self.tokens.get(start)?.span.line;
+        let index = self.index;
+        self.index += 1;
 
-    Ok(node)
+        let mut tokens = self.tokens[start..].iter().peekable();
+        let result = parse_value(&mut tokens, &mut Context::strict(self.eof));
+        let end = match &result {
+            Ok(_) => self.tokens.len() - tokens.len(),
+            Err(e) => self.resynchronize(start, e),
+        };
+        self.pos = end;
+
+        // the parser only saw placeholders for unreadable tokens
+        let invalid = self.tokens[start..end]
+            .iter()
+            .filter(|t| t.token == Token::Invalid)
+            .count();
+        let mut token_errors = self.token_errors.by_ref().take(invalid);
+        let token_error = token_errors.next();
+        token_errors.for_each(drop);
+
+        // report whichever error comes first in the record
+        let error: anyhow::Error = match (result, token_error) {
+            (Ok(node), None) => return Some(Ok(node)),
+            (Ok(_), Some(t)) => t.into(),
+            (Err(p), Some(t)) if t.span().offset < p.span().offset => t.into(),
+            (Err(p), _) => p.into(),
+        };
+        Some(Err(RecordError { index, line, error }))
+    }
+}
+
+// A record of a stream that could not be parsed.
+#[derive(Debug)]
+pub struct RecordError {
+    // 0-based position of the record in the stream
+    pub index: usize,
+    // line the record starts on
+    pub line: usize,
+    pub error: anyhow::Error,
+}
+
+impl Display for RecordError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} (in record #{} starting on line {})",
+            self.error,
+            self.index + 1,
+            self.line
+        )
+    }
+}
+
+impl Error for RecordError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.error.as_ref())
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum ParseError {
-    UnexpectedEndOfInput,
-    UnexpectedToken,
-    UnexpectedCommaBeforeEndOfObject,
-    UnexpectedCommaBeforeEndOfArray,
-    MissingComma,
-    ExpectedColonAfterStringKey,
-    UnexpectedObjectKey,
+    UnexpectedEndOfInput(Span),
+    UnexpectedToken(Span),
+    UnexpectedCommaBeforeEndOfObject(Span),
+    UnexpectedCommaBeforeEndOfArray(Span),
+    MissingComma(Span),
+    MissingCommaInArray(Span),
+    ExpectedColonAfterStringKey(Span),
+    UnexpectedObjectKey(Span),
+    UnexpectedTrailingToken(Token, Span),
+    ExceedsDepthLimit(Span),
 }
 
-impl Display for ParseError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl ParseError {
+    pub fn span(&self) -> Span {
+        match self {
+            ParseError::UnexpectedEndOfInput(span)
+            | ParseError::UnexpectedToken(span)
+            | ParseError::UnexpectedCommaBeforeEndOfObject(span)
+            | ParseError::UnexpectedCommaBeforeEndOfArray(span)
+            | ParseError::MissingComma(span)
+            | ParseError::MissingCommaInArray(span)
+            | ParseError::ExpectedColonAfterStringKey(span)
+            | ParseError::UnexpectedObjectKey(span)
+            | ParseError::UnexpectedTrailingToken(_, span)
+            | ParseError::ExceedsDepthLimit(span) => *span,
+        }
+    }
+
+    // Errors that parsing can't recover from: running out of tokens, or
+    // nesting too deep to find where the container ends.
+    fn is_fatal(&self) -> bool {
+        matches!(
+            self,
+            ParseError::UnexpectedEndOfInput(_) | ParseError::ExceedsDepthLimit(_)
+        )
+    }
+}
+
+impl ParseError {
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            ParseError::UnexpectedEndOfInput(_) => {
+                Some("input ended before all objects and arrays were closed")
+            }
+            ParseError::UnexpectedToken(_) => {
+                Some("expected a value: object, array, string, number, `true`, `false` or `null`")
+            }
+            ParseError::UnexpectedCommaBeforeEndOfObject(_) => {
+                Some("remove the trailing `,` before `}`")
+            }
+            ParseError::UnexpectedCommaBeforeEndOfArray(_) => {
+                Some("remove the trailing `,` before `]`")
+            }
+            ParseError::MissingComma(_) => Some("expected `,` or `}` after object value"),
+            ParseError::MissingCommaInArray(_) => Some("expected `,` or `]` after array element"),
+            ParseError::ExpectedColonAfterStringKey(_) => {
+                Some("expected `:` between object key and value")
+            }
+            ParseError::UnexpectedObjectKey(_) => Some("object keys must be double-quoted strings"),
+            ParseError::UnexpectedTrailingToken(_, _) => {
+                Some("only a single top-level value is allowed, use `--multi` to accept several")
+            }
+            ParseError::ExceedsDepthLimit(_) => {
+                Some("objects and arrays can be nested at most 10000 levels deep")
+            }
+        }
+    }
+
+    // the error message without its location
+    pub fn message(&self) -> String {
         match self {
-            ParseError::UnexpectedEndOfInput => write!(f, "Unexpected end of input"),
-            ParseError::UnexpectedToken => write!(f, "Unexpected token"),
-            ParseError::UnexpectedCommaBeforeEndOfObject => {
-                write!(f, "Unexpected comma before end of object")
+            ParseError::UnexpectedEndOfInput(_) => "Unexpected end of input".to_string(),
+            ParseError::UnexpectedToken(_) => "Unexpected token".to_string(),
+            ParseError::UnexpectedCommaBeforeEndOfObject(_) => {
+                "Unexpected comma before end of object".to_string()
+            }
+            ParseError::UnexpectedCommaBeforeEndOfArray(_) => {
+                "Unexpected comma before end of array".to_string()
             }
-            ParseError::UnexpectedCommaBeforeEndOfArray => {
-                write!(f, "Unexpected comma before end of array")
+            ParseError::MissingComma(_) | ParseError::MissingCommaInArray(_) => {
+                "Missing comma".to_string()
             }
-            ParseError::MissingComma => write!(f, "Missing comma"),
-            ParseError::ExpectedColonAfterStringKey => write!(f, "Expected colon after string key"),
-            ParseError::UnexpectedObjectKey => write!(f, "Unexpected object key"),
+            ParseError::ExpectedColonAfterStringKey(_) => {
+                "Expected colon after string key".to_string()
+            }
+            ParseError::UnexpectedObjectKey(_) => "Unexpected object key".to_string(),
+            ParseError::UnexpectedTrailingToken(token, _) => {
+                format!(
+                    "Unexpected trailing token `{}` after top-level value",
+                    token
+                )
+            }
+            ParseError::ExceedsDepthLimit(_) => "Exceeds depth limit for parsing".to_string(),
         }
     }
 }
 
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.span(), self.message())
+    }
+}
+
 impl Error for ParseError {}
 
 #[cfg(test)]
 mod parser {
     use super::Token;
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn test_parse_simple_json() {
@@ -234,6 +610,28 @@ mod parser {
         ];
         let result = parse_tokens(tokens);
         assert!(result.is_ok());
+        assert_eq!(
+            result.unwrap(),
+            Some(ASTNode::Object(
+                vec![(
+                    "key".to_string(),
+                    ASTNode::Object(
+                        vec![(
+                            "inner_key".to_string(),
+                            ASTNode::Array(vec![ASTNode::Number(1.0), ASTNode::Number(2.0)].into())
+                        )]
+                        .into()
+                    )
+                )]
+                .into()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_no_tokens() {
+        let result = parse_tokens(vec![]);
+        assert_eq!(result, Ok(None));
     }
 
     #[test]
@@ -252,7 +650,7 @@ mod parser {
 
     #[test]
     fn test_parse_object() {
-        let tokens = vec![
+        let tokens = [
             // parse_object fn requires the first token (Token::BraceOpen) to be consumed
             // Token::BraceOpen,
             Token::String("key".to_string()),
@@ -260,28 +658,62 @@ mod parser {
             Token::String("value".to_string()),
             Token::BraceClose,
         ];
-        let result = parse_object(&mut tokens.iter().peekable());
+        let result = parse_object(
+            &mut spanned(&tokens).iter().peekable(),
+            &mut Context::strict(Span::default()),
+        );
         assert!(result.is_ok());
         assert_eq!(
             result.unwrap(),
-            ASTNode::Object(vec![("key", ASTNode::String("value"))])
+            ASTNode::Object(vec![("key".to_string(), ASTNode::String("value".to_string()))].into())
         );
     }
 
     #[test]
     fn test_parse_array() {
-        let tokens = vec![
+        let tokens = [
             // parse_array fn requires the first token (Token::BracketOpen) to be consumed
             // Token::BracketOpen,
             Token::Number(1.0),
+            Token::Comma,
             Token::Number(2.0),
             Token::BracketClose,
         ];
-        let result = parse_array(&mut tokens.iter().peekable());
+        let result = parse_array(
+            &mut spanned(&tokens).iter().peekable(),
+            &mut Context::strict(Span::default()),
+        );
         assert!(result.is_ok());
         assert_eq!(
             result.unwrap(),
-            ASTNode::Array(vec![ASTNode::Number(1.0), ASTNode::Number(2.0)])
+            ASTNode::Array(vec![ASTNode::Number(1.0), ASTNode::Number(2.0)].into())
+        );
+    }
+
+    #[test]
+    fn test_parse_array_missing_comma() {
+        let tokens = [Token::Number(1.0), Token::Number(2.0), Token::BracketClose];
+        let result = parse_array(
+            &mut spanned(&tokens).iter().peekable(),
+            &mut Context::strict(Span::default()),
+        );
+        assert_eq!(
+            result.unwrap_err(),
+            ParseError::MissingCommaInArray(span(1))
+        );
+
+        let tokens = vec![
+            Token::BracketOpen,
+            Token::Number(1.0),
+            Token::Comma,
+            Token::Number(2.0),
+            Token::Number(3.0),
+            Token::BracketClose,
+        ];
+        let result = parse_tokens(tokens);
+        assert_eq!(
+            result.unwrap_err(),
+            ParseError::MissingCommaInArray(span(4))
         );
     }
 
@@ -297,7 +729,7 @@ mod parser {
         ];
         let result = parse_tokens(tokens);
         assert!(result.is_err());
-        assert!(result.unwrap_err() == ParseError::UnexpectedCommaBeforeEndOfObject);
+        assert!(result.unwrap_err() == ParseError::UnexpectedCommaBeforeEndOfObject(span(4)));
 
         let tokens = vec![
             Token::BracketOpen,
@@ -309,7 +741,7 @@ mod parser {
         ];
         let result = parse_tokens(tokens);
         assert!(result.is_err());
-        assert!(result.unwrap_err() == ParseError::UnexpectedCommaBeforeEndOfArray);
+        assert!(result.unwrap_err() == ParseError::UnexpectedCommaBeforeEndOfArray(span(4)));
     }
 
     #[test]
@@ -323,7 +755,7 @@ mod parser {
         ];
         let result = parse_tokens(tokens);
         assert!(result.is_err());
-        assert!(result.unwrap_err() == ParseError::UnexpectedObjectKey);
+        assert!(result.unwrap_err() == ParseError::UnexpectedObjectKey(span(1)));
 
         let tokens = vec![
             Token::BraceOpen,
@@ -334,7 +766,7 @@ mod parser {
         ];
         let result = parse_tokens(tokens);
         assert!(result.is_err());
-        assert!(result.unwrap_err() == ParseError::UnexpectedObjectKey);
+        assert!(result.unwrap_err() == ParseError::UnexpectedObjectKey(span(1)));
     }
 
     #[test]
@@ -347,7 +779,7 @@ mod parser {
         ];
         let result = parse_tokens(tokens);
         assert!(result.is_err());
-        assert!(result.unwrap_err() == ParseError::ExpectedColonAfterStringKey);
+        assert!(result.unwrap_err() == ParseError::ExpectedColonAfterStringKey(span(2)));
     }
 
     #[test]
@@ -355,10 +787,523 @@ mod parser {
         let tokens = vec![Token::BraceOpen];
         let result = parse_tokens(tokens);
         assert!(result.is_err());
-        assert!(result.unwrap_err() == ParseError::UnexpectedEndOfInput);
+        assert!(result.unwrap_err() == ParseError::UnexpectedEndOfInput(span(0)));
+    }
+
+    #[test]
+    fn test_parse_depth_limit() {
+        let nested = |depth: usize| {
+            let mut tokens = vec![Token::BracketOpen; depth];
+            tokens.extend(vec![Token::BracketClose; depth]);
+            spanned(&tokens)
+        };
+        // the deepest input takes more than the default stack of a test
+        let deepest = std::thread::Builder::new()
+            .stack_size(1 << 28)
+            .spawn(move || {
+                let tokens = nested(MAX_DEPTH);
+                assert!(parse(&mut tokens.iter().peekable()).is_ok());
+            })
+            .unwrap();
+        deepest.join().unwrap();
+
+        let too_deep = std::thread::Builder::new()
+            .stack_size(1 << 28)
+            .spawn(move || {
+                let tokens = nested(MAX_DEPTH + 1);
+                assert_eq!(
+                    parse(&mut tokens.iter().peekable()).unwrap_err(),
+                    ParseError::ExceedsDepthLimit(span(MAX_DEPTH))
+                );
+                // far deeper than the stack allows, reported once when recovering
+                let tokens = nested(1_000_000);
+                let (node, errors) = parse_recovering(&mut tokens.iter().peekable());
+                assert_eq!(errors, vec![ParseError::ExceedsDepthLimit(span(MAX_DEPTH))]);
+                assert_eq!(node, None);
+
+                // a stream resumes after the record
+                let mut tokens = nested(1_000_000);
+                tokens.extend(spanned(&[Token::Null]));
+                let mut records = Records::new(tokens, Vec::new());
+                let error = records.next().unwrap().unwrap_err();
+                assert_eq!(
+                    error.error.downcast_ref::<ParseError>(),
+                    Some(&ParseError::ExceedsDepthLimit(span(MAX_DEPTH)))
+                );
+                assert!(matches!(records.next(), Some(Ok(ASTNode::Null))));
+            })
+            .unwrap();
+        too_deep.join().unwrap();
+    }
+
+    #[test]
+    fn test_parse_trailing_tokens() {
+        let tokens = vec![
+            Token::BraceOpen,
+            Token::BraceClose,
+            Token::BraceOpen,
+            Token::BraceClose,
+        ];
+        let result = parse_tokens(tokens);
+        assert_eq!(
+            result.unwrap_err(),
+            ParseError::UnexpectedTrailingToken(Token::BraceOpen, span(2))
+        );
+
+        let tokens = vec![Token::Number(1.0), Token::Number(2.0), Token::BracketClose];
+        let result = parse_tokens(tokens);
+        assert_eq!(
+            result.unwrap_err(),
+            ParseError::UnexpectedTrailingToken(Token::Number(2.0), span(1))
+        );
+    }
+
+    #[test]
+    fn test_parse_multi() {
+        let tokens = [
+            Token::BraceOpen,
+            Token::BraceClose,
+            Token::Number(1.0),
+            Token::String("a".to_string()),
+        ];
+        let result = parse_multi(&mut spanned(&tokens).iter().peekable());
+        assert_eq!(
+            result.unwrap(),
+            vec![
+                ASTNode::Object(vec![].into()),
+                ASTNode::Number(1.0),
+                ASTNode::String("a".to_string())
+            ]
+        );
+
+        let tokens = [Token::Number(1.0), Token::Number(2.0), Token::BracketClose];
+        let result = parse_multi(&mut spanned(&tokens).iter().peekable());
+        assert_eq!(result.unwrap_err(), ParseError::UnexpectedToken(span(2)));
+    }
+
+    // arbitrary token soup, biased towards structural tokens so that valid
+    // arrays and objects come up regularly
+    fn token_strategy() -> impl Strategy<Value = Token> {
+        prop_oneof![
+            3 => Just(Token::BraceOpen),
+            3 => Just(Token::BraceClose),
+            3 => Just(Token::BracketOpen),
+            3 => Just(Token::BracketClose),
+            3 => Just(Token::Colon),
+            4 => Just(Token::Comma),
+            3 => (0u8..3).prop_map(|n| Token::String(format!("k{}", n))),
+            2 => (-10i32..10).prop_map(|n| Token::Number(n as f64)),
+            1 => Just(Token::True),
+            1 => Just(Token::False),
+            1 => Just(Token::Null),
+        ]
+    }
+
+    fn node_strategy() -> impl Strategy<Value = ASTNode> {
+        let leaf = prop_oneof![
+            (0u8..3).prop_map(|n| ASTNode::String(format!("k{}", n))),
+            (-10i32..10).prop_map(|n| ASTNode::Number(n as f64)),
+            any::<bool>().prop_map(ASTNode::Boolean),
+            Just(ASTNode::Null),
+        ];
+        leaf.prop_recursive(4, 32, 4, |inner| {
+            prop_oneof![
+                prop::collection::vec(inner.clone(), 0..4).prop_map(|v| ASTNode::Array(v.into())),
+                prop::collection::vec(((0u8..3).prop_map(|n| format!("k{}", n)), inner), 0..4)
+                    .prop_map(|v| ASTNode::Object(v.into())),
+            ]
+        })
+    }
+
+    fn node_to_tokens(node: &ASTNode, tokens: &mut Vec<Token>) {
+        match node {
+            ASTNode::Object(obj) => {
+                tokens.push(Token::BraceOpen);
+                for (i, (key, value)) in obj.iter().enumerate() {
+                    if i > 0 {
+                        tokens.push(Token::Comma);
+                    }
+                    tokens.push(Token::String(key.clone()));
+                    tokens.push(Token::Colon);
+                    node_to_tokens(value, tokens);
+                }
+                tokens.push(Token::BraceClose);
+            }
+            ASTNode::Array(arr) => {
+                tokens.push(Token::BracketOpen);
+                for (i, value) in arr.iter().enumerate() {
+                    if i > 0 {
+                        tokens.push(Token::Comma);
+                    }
+                    node_to_tokens(value, tokens);
+                }
+                tokens.push(Token::BracketClose);
+            }
+            ASTNode::String(s) => tokens.push(Token::String(s.clone())),
+            ASTNode::Number(n) => tokens.push(Token::Number(*n)),
+            ASTNode::Boolean(true) => tokens.push(Token::True),
+            ASTNode::Boolean(false) => tokens.push(Token::False),
+            ASTNode::Null => tokens.push(Token::Null),
+        }
+    }
+
+    // Reference recognizer written directly from the RFC 8259 grammar:
+    //   value  = object / array / string / number / true / false / null
+    //   object = "{" [ member *( "," member ) ] "}"
+    //   member = string ":" value
+    //   array  = "[" [ value *( "," value ) ] "]"
+    // Each function returns the number of tokens consumed on success.
+    mod grammar {
+        use super::Token;
+
+        pub fn value(tokens: &[Token]) -> Option<usize> {
+            match tokens.first()? {
+                Token::BraceOpen => object_rest(&tokens[1..]).map(|n| n + 1),
+                Token::BracketOpen => array_rest(&tokens[1..]).map(|n| n + 1),
+                Token::String(_) | Token::Number(_) | Token::True | Token::False | Token::Null => {
+                    Some(1)
+                }
+                _ => None,
+            }
+        }
+
+        // object after the opening brace
+        pub fn object_rest(tokens: &[Token]) -> Option<usize> {
+            if tokens.first()? == &Token::BraceClose {
+                return Some(1);
+            }
+            let mut pos = 0;
+            loop {
+                match tokens.get(pos)? {
+                    Token::String(_) => pos += 1,
+                    _ => return None,
+                }
+                if tokens.get(pos)? != &Token::Colon {
+                    return None;
+                }
+                pos += 1;
+                pos += value(&tokens[pos..])?;
+                match tokens.get(pos)? {
+                    Token::Comma => pos += 1,
+                    Token::BraceClose => return Some(pos + 1),
+                    _ => return None,
+                }
+            }
+        }
+
+        // array after the opening bracket
+        pub fn array_rest(tokens: &[Token]) -> Option<usize> {
+            if tokens.first()? == &Token::BracketClose {
+                return Some(1);
+            }
+            let mut pos = 0;
+            loop {
+                pos += value(&tokens[pos..])?;
+                match tokens.get(pos)? {
+                    Token::Comma => pos += 1,
+                    Token::BracketClose => return Some(pos + 1),
+                    _ => return None,
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_recovering() {
+        // {"a" 1, "b": [1 2,], , "c": }
+        let tokens = vec![
+            Token::BraceOpen,
+            Token::String("a".to_string()),
+            Token::Number(1.0),
+            Token::Comma,
+            Token::String("b".to_string()),
+            Token::Colon,
+            Token::BracketOpen,
+            Token::Number(1.0),
+            Token::Number(2.0),
+            Token::Comma,
+            Token::BracketClose,
+            Token::Comma,
+            Token::Comma,
+            Token::String("c".to_string()),
+            Token::Colon,
+            Token::BraceClose,
+        ];
+        let tokens = spanned(&tokens);
+        let (node, errors) = parse_recovering(&mut tokens.iter().peekable());
+        assert_eq!(
+            errors,
+            vec![
+                ParseError::ExpectedColonAfterStringKey(span(2)),
+                ParseError::MissingCommaInArray(span(8)),
+                ParseError::UnexpectedCommaBeforeEndOfArray(span(9)),
+                // `"c":` is skipped while resynchronizing on `}`
+                ParseError::UnexpectedObjectKey(span(12)),
+            ]
+        );
+        assert_eq!(
+            node,
+            Some(ASTNode::Object(
+                vec![(
+                    "b".to_string(),
+                    ASTNode::Array(vec![ASTNode::Number(1.0), ASTNode::Number(2.0)].into())
+                )]
+                .into()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_recovering_invalid_tokens() {
+        // [<invalid>, {<invalid>, "a": 1}, 2 3]
+        let tokens = vec![
+            Token::BracketOpen,
+            Token::Invalid,
+            Token::Comma,
+            Token::BraceOpen,
+            Token::Invalid,
+            Token::Comma,
+            Token::String("a".to_string()),
+            Token::Colon,
+            Token::Number(1.0),
+            Token::BraceClose,
+            Token::Comma,
+            Token::Number(2.0),
+            Token::Number(3.0),
+            Token::BracketClose,
+        ];
+        let tokens = spanned(&tokens);
+        let (node, errors) = parse_recovering(&mut tokens.iter().peekable());
+        // invalid tokens are reported by the tokenizer, not again here
+        assert_eq!(errors, vec![ParseError::MissingCommaInArray(span(12))]);
+        assert!(node.is_some());
+
+        let tokens = spanned(&[Token::BracketOpen, Token::Number(1.0)]);
+        let (node, errors) = parse_recovering(&mut tokens.iter().peekable());
+        assert_eq!(errors, vec![ParseError::UnexpectedEndOfInput(span(1))]);
+        assert_eq!(node, None);
+    }
+
+    #[test]
+    fn test_parse_multi_recovering() {
+        let tokens = spanned(&[
+            Token::BracketOpen,
+            Token::Comma,
+            Token::BracketClose,
+            Token::Number(1.0),
+            Token::Colon,
+            Token::BraceOpen,
+            Token::BraceClose,
+        ]);
+        let (nodes, errors) = parse_multi_recovering(&mut tokens.iter().peekable());
+        assert_eq!(
+            errors,
+            vec![
+                ParseError::UnexpectedToken(span(1)),
+                ParseError::UnexpectedToken(span(4)),
+            ]
+        );
+        assert_eq!(
+            nodes,
+            vec![
+                ASTNode::Array(vec![].into()),
+                ASTNode::Number(1.0),
+                ASTNode::Object(vec![].into())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_records_with_lines() {
+        let contents = "1\n{\n  \"a\": [2,\n3]} 4\n\n[5";
+        let (tokens, token_errors) =
+            crate::tokenizer::Tokenizer::new(contents).collect_recovering();
+        let lines: Vec<_> = Records::new(tokens, token_errors)
+            .with_lines()
+            .map(|r| r.map(|(_, line)| line).map_err(|e| e.line))
+            .collect();
+        assert_eq!(lines, vec![Ok(1), Ok(4), Ok(4), Err(6)]);
+    }
+
+    #[test]
+    fn test_records() {
+        let contents = "{\"a\": 1}\n{\"a\": 2 \"b\": 3}\n[tru, 4]\n\"x\n[5\n6";
+        let (tokens, token_errors) =
+            crate::tokenizer::Tokenizer::new(contents).collect_recovering();
+        let records: Vec<_> = Records::new(tokens, token_errors).collect();
+        assert_eq!(records.len(), 6);
+
+        let values: Vec<_> = records.iter().filter_map(|r| r.as_ref().ok()).collect();
+        assert_eq!(
+            values,
+            vec![
+                &ASTNode::Object(vec![("a".to_string(), ASTNode::Number(1.0))].into()),
+                &ASTNode::Number(6.0),
+            ]
+        );
+
+        let errors: Vec<_> = records
+            .iter()
+            .filter_map(|r| r.as_ref().err())
+            .map(|e| (e.index, e.line, e.error.to_string()))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (1, 2, "2:9: Missing comma".to_string()),
+                (2, 3, "3:2: Unexpected token".to_string()),
+                (3, 4, "4:1: Unexpected token".to_string()),
+                (4, 5, "6:1: Missing comma".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_record_error_display() {
+        let err = RecordError {
+            index: 2,
+            line: 3,
+            error: ParseError::MissingComma(span(4)).into(),
+        };
+        assert_eq!(
+            err.to_string(),
+            "1:5: Missing comma (in record #3 starting on line 3)"
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn prop_parse_recovering_agrees_with_parse(tokens in prop::collection::vec(token_strategy(), 1..16)) {
+            let spanned_tokens = spanned(&tokens);
+            let result = parse(&mut spanned_tokens.iter().peekable());
+            let (_, errors) = parse_recovering(&mut spanned_tokens.iter().peekable());
+            match result {
+                Ok(_) => prop_assert!(errors.is_empty(), "tokens: {:?}", tokens),
+                Err(e) => prop_assert_eq!(errors.first(), Some(&e), "tokens: {:?}", tokens),
+            }
+        }
+
+        #[test]
+        fn prop_parse_array_matches_grammar(tokens in prop::collection::vec(token_strategy(), 0..16)) {
+            let spanned_tokens = spanned(&tokens);
+            let mut iter = spanned_tokens.iter().peekable();
+            let result = parse_array(&mut iter, &mut Context::strict(Span::default()));
+            let expected = grammar::array_rest(&tokens);
+            prop_assert_eq!(result.is_ok(), expected.is_some(), "tokens: {:?}", tokens);
+            if let Some(consumed) = expected {
+                prop_assert_eq!(iter.len(), tokens.len() - consumed);
+            }
+        }
+
+        #[test]
+        fn prop_parse_object_matches_grammar(tokens in prop::collection::vec(token_strategy(), 0..16)) {
+            let spanned_tokens = spanned(&tokens);
+            let mut iter = spanned_tokens.iter().peekable();
+            let result = parse_object(&mut iter, &mut Context::strict(Span::default()));
+            let expected = grammar::object_rest(&tokens);
+            prop_assert_eq!(result.is_ok(), expected.is_some(), "tokens: {:?}", tokens);
+            if let Some(consumed) = expected {
+                prop_assert_eq!(iter.len(), tokens.len() - consumed);
+            }
+        }
+
+        #[test]
+        fn prop_parse_matches_grammar(tokens in prop::collection::vec(token_strategy(), 1..16)) {
+            let result = parse(&mut spanned(&tokens).iter().peekable());
+            let expected = grammar::value(&tokens) == Some(tokens.len());
+            prop_assert_eq!(result.is_ok(), expected, "tokens: {:?}", tokens);
+        }
+
+        #[test]
+        fn prop_parse_roundtrip(node in node_strategy()) {
+            let mut tokens = Vec::new();
+            node_to_tokens(&node, &mut tokens);
+            prop_assert_eq!(grammar::value(&tokens), Some(tokens.len()));
+            let result = parse(&mut spanned(&tokens).iter().peekable());
+            prop_assert_eq!(result, Ok(Some(node)));
+        }
+
+        #[test]
+        fn prop_parse_mutated_matches_grammar(
+            node in node_strategy(),
+            index in any::<prop::sample::Index>(),
+            replacement in prop::option::of(token_strategy()),
+        ) {
+            // dropping or replacing a single token of a valid document
+            let mut tokens = Vec::new();
+            node_to_tokens(&node, &mut tokens);
+            let i = index.index(tokens.len());
+            match replacement {
+                Some(token) => tokens[i] = token,
+                None => {
+                    tokens.remove(i);
+                }
+            }
+            let result = parse(&mut spanned(&tokens).iter().peekable());
+            let expected = tokens.is_empty() || grammar::value(&tokens) == Some(tokens.len());
+            prop_assert_eq!(result.is_ok(), expected, "tokens: {:?}", tokens);
+        }
+    }
+
+    #[test]
+    fn test_parse_error_display() {
+        let tokens = [
+            SpannedToken {
+                token: Token::BracketOpen,
+                span: Span {
+                    offset: 0,
+                    len: 1,
+                    line: 1,
+                    column: 1,
+                },
+            },
+            SpannedToken {
+                token: Token::Number(1.0),
+                span: Span {
+                    offset: 4,
+                    len: 1,
+                    line: 2,
+                    column: 3,
+                },
+            },
+            SpannedToken {
+                token: Token::Number(2.0),
+                span: Span {
+                    offset: 8,
+                    len: 1,
+                    line: 3,
+                    column: 3,
+                },
+            },
+        ];
+        let err = parse(&mut tokens.iter().peekable()).unwrap_err();
+        assert_eq!(err.span().line, 3);
+        assert_eq!(err.to_string(), "3:3: Missing comma");
+    }
+
+    // gives the i-th token the span of the i-th character of a single line
+    fn span(i: usize) -> Span {
+        Span {
+            offset: i,
+            len: 1,
+            line: 1,
+            column: i + 1,
+        }
+    }
+
+    fn spanned(tokens: &[Token]) -> Vec<SpannedToken> {
+        tokens
+            .iter()
+            .enumerate()
+            .map(|(i, token)| SpannedToken {
+                token: token.clone(),
+                span: span(i),
+            })
+            .collect()
     }
 
-    fn parse_tokens(tokens: Vec<Token>) -> Result<(), ParseError> {
+    fn parse_tokens(tokens: Vec<Token>) -> Result<Option<ASTNode>, ParseError> {
+        let tokens = spanned(&tokens);
         let mut tokens_iter = tokens.iter().peekable();
         parse(&mut tokens_iter)
     }
@@ -1,7 +1,7 @@
 use std::error::Error;
 use std::fmt::{Debug, Display};
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     BraceOpen,
     BraceClose,
@@ -14,91 +14,286 @@ pub enum Token {
     True,
     False,
     Null,
+    // placeholder for unreadable input, only produced by `collect_recovering`
+    Invalid,
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::BraceOpen => write!(f, "{{"),
+            Token::BraceClose => write!(f, "}}"),
+            Token::BracketOpen => write!(f, "["),
+            Token::BracketClose => write!(f, "]"),
+            Token::Colon => write!(f, ":"),
+            Token::Comma => write!(f, ","),
+            Token::String(s) => write!(f, "{:?}", s),
+            Token::Number(n) => write!(f, "{}", n),
+            Token::True => write!(f, "true"),
+            Token::False => write!(f, "false"),
+            Token::Null => write!(f, "null"),
+            Token::Invalid => write!(f, "<invalid>"),
+        }
+    }
+}
+
+// Location of a token or error in the input. `line` and `column` are 1-based,
+// `column` counts characters rather than bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub offset: usize,
+    pub len: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Default for Span {
+    fn default() -> Self {
+        Span {
+            offset: 0,
+            len: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
 }
 
 pub struct Tokenizer<'a> {
     contents: &'a str,
+    // byte offset into `contents`, always on a char boundary
     pos: usize,
+    // byte offset of the first character of the token being read
+    token_start: usize,
+    line: usize,
+    line_start: usize,
+    // `column` is the column of byte offset `column_pos`, both only move forward
+    // within a line so that computing spans stays linear
+    column: usize,
+    column_pos: usize,
     error: Option<TokenError>,
 }
 
 impl<'a> Tokenizer<'a> {
-    pub fn new(contents: &str) -> Tokenizer {
+    pub fn new(contents: &str) -> Tokenizer<'_> {
         Tokenizer {
             contents,
             pos: 0,
+            token_start: 0,
+            line: 1,
+            line_start: 0,
+            column: 1,
+            column_pos: 0,
             error: None,
         }
     }
 
-    fn peek_nth_char(&self, n: usize) -> Option<char> {
-        self.contents.chars().nth(n)
+    // `pos` is a byte offset, so this is O(1) unlike `chars().nth(n)`
+    fn peek_char_at(&self, pos: usize) -> Option<char> {
+        self.contents.get(pos..)?.chars().next()
     }
 
     fn peek_char(&self) -> Option<char> {
-        self.peek_nth_char(self.pos)
+        self.peek_char_at(self.pos)
     }
 
     fn next_char(&mut self) -> Option<char> {
-        self.peek_char().map(|c| {
-            self.pos += 1;
-            c
+        self.peek_char().inspect(|c| {
+            self.pos += c.len_utf8();
+            if *c == '\n' {
+                self.line += 1;
+                self.line_start = self.pos;
+                self.column = 1;
+                self.column_pos = self.pos;
+            }
         })
     }
 
-    fn next_token(&mut self) -> Option<Token> {
-        loop {
-            let c = self.next_char()?;
-            if c.is_whitespace() || c.is_control() {
-                continue;
+    // `pos` must be on the current line
+    fn span_at(&mut self, pos: usize) -> Span {
+        if pos < self.column_pos {
+            self.column = 1;
+            self.column_pos = self.line_start;
+        }
+        self.column += self.contents[self.column_pos..pos].chars().count();
+        self.column_pos = pos;
+        Span {
+            offset: pos,
+            len: 0,
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek_char() {
+            if !c.is_whitespace() && !c.is_control() {
+                break;
             }
+            self.next_char();
+        }
+    }
 
-            return match c {
-                '{' => Some(Token::BraceOpen),
-                '}' => Some(Token::BraceClose),
-                '[' => Some(Token::BracketOpen),
-                ']' => Some(Token::BracketClose),
-                ':' => Some(Token::Colon),
-                ',' => Some(Token::Comma),
-                '"' => self.read_string(),
-                't' => self.read_bool_true(),
-                'f' => self.read_bool_false(),
-                'n' => self.read_null(),
-                '0'..='9' | '-' => self.read_number(c),
-                _ => {
-                    self.set_error();
-                    None
-                }
-            };
+    fn next_token(&mut self) -> Option<SpannedToken> {
+        self.skip_whitespace();
+        self.token_start = self.pos;
+        let span = self.span_at(self.pos);
+
+        let token = self.read_token()?;
+        Some(SpannedToken {
+            token,
+            span: Span {
+                len: self.pos - span.offset,
+                ..span
+            },
+        })
+    }
+
+    fn read_token(&mut self) -> Option<Token> {
+        let c = self.next_char()?;
+        match c {
+            '{' => Some(Token::BraceOpen),
+            '}' => Some(Token::BraceClose),
+            '[' => Some(Token::BracketOpen),
+            ']' => Some(Token::BracketClose),
+            ':' => Some(Token::Colon),
+            ',' => Some(Token::Comma),
+            '"' => self.read_string(),
+            't' => self.read_bool_true(),
+            'f' => self.read_bool_false(),
+            'n' => self.read_null(),
+            '0'..='9' | '-' => self.read_number(c),
+            _ => {
+                self.set_error();
+                None
+            }
         }
     }
 
     fn read_string(&mut self) -> Option<Token> {
+        let start_pos = self.token_start;
         let mut s = String::new();
-        let mut peek_pos = self.pos;
         loop {
-            let c = self.peek_nth_char(peek_pos);
-            match c {
+            match self.peek_char() {
                 // no multiline strings allowed
                 Some('\n') | Some('\r') | None => {
-                    self.set_error();
+                    self.set_error_at(start_pos, TokenErrorKind::UnexpectedToken);
+                    return None;
+                }
+                Some('"') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some('\\') => {
+                    let c = self.read_escape(start_pos)?;
+                    s.push(c);
+                }
+                // control characters must be escaped
+                Some(c) if c < '\u{20}' => {
+                    self.set_error_at(self.pos, TokenErrorKind::ControlCharacterInString);
                     return None;
                 }
-                Some('"') => break,
                 Some(c) => {
                     s.push(c);
-                    peek_pos += 1;
+                    self.pos += c.len_utf8();
                 }
             }
         }
-        self.pos = peek_pos + 1;
         Some(Token::String(s))
     }
 
+    fn read_escape(&mut self, string_start_pos: usize) -> Option<char> {
+        let escape_pos = self.pos;
+        // skip the backslash
+        self.pos += 1;
+        // a line break is left unread, so that the error stays on the line of
+        // the escape
+        if self.peek_char().is_some_and(|c| c < '\u{20}') {
+            self.set_error_at(escape_pos, TokenErrorKind::ControlCharacterInString);
+            return None;
+        }
+        let c = match self.next_char() {
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some('/') => '/',
+            Some('b') => '\u{8}',
+            Some('f') => '\u{c}',
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('u') => return self.read_unicode_escape(escape_pos),
+            Some(c) => {
+                self.set_error_at(escape_pos, TokenErrorKind::InvalidEscape(c));
+                return None;
+            }
+            None => {
+                self.set_error_at(string_start_pos, TokenErrorKind::UnexpectedToken);
+                return None;
+            }
+        };
+        Some(c)
+    }
+
+    // reads the part of `\uXXXX` after `\u`, including the low half of a surrogate pair
+    fn read_unicode_escape(&mut self, escape_pos: usize) -> Option<char> {
+        let high = self.read_hex4(escape_pos)?;
+        let code = match high {
+            0xD800..=0xDBFF => {
+                if self.peek_char() != Some('\\') || self.peek_char_at(self.pos + 1) != Some('u') {
+                    self.set_error_at(escape_pos, TokenErrorKind::UnpairedSurrogate);
+                    return None;
+                }
+                let low_pos = self.pos;
+                self.pos += 2;
+                let low = self.read_hex4(low_pos)?;
+                if !(0xDC00..=0xDFFF).contains(&low) {
+                    self.set_error_at(escape_pos, TokenErrorKind::UnpairedSurrogate);
+                    return None;
+                }
+                0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+            }
+            0xDC00..=0xDFFF => {
+                self.set_error_at(escape_pos, TokenErrorKind::UnpairedSurrogate);
+                return None;
+            }
+            _ => high,
+        };
+        // surrogates are excluded above, so this is always a valid scalar value
+        char::from_u32(code)
+    }
+
+    fn read_hex4(&mut self, escape_pos: usize) -> Option<u32> {
+        let mut code = 0;
+        for _ in 0..4 {
+            match self.peek_char().and_then(|c| c.to_digit(16)) {
+                Some(digit) => {
+                    code = code * 16 + digit;
+                    self.pos += 1;
+                }
+                None => {
+                    self.set_error_at(escape_pos, TokenErrorKind::InvalidUnicodeEscape);
+                    return None;
+                }
+            }
+        }
+        Some(code)
+    }
+
     fn read_bool_true(&mut self) -> Option<Token> {
         if self.peek_char() == Some('r')
-            && self.peek_nth_char(self.pos + 1) == Some('u')
-            && self.peek_nth_char(self.pos + 2) == Some('e')
+            && self.peek_char_at(self.pos + 1) == Some('u')
+            && self.peek_char_at(self.pos + 2) == Some('e')
         {
             self.pos += 3;
             Some(Token::True)
@@ -110,9 +305,9 @@ impl<'a> Tokenizer<'a> {
 
     fn read_bool_false(&mut self) -> Option<Token> {
         if self.peek_char() == Some('a')
-            && self.peek_nth_char(self.pos + 1) == Some('l')
-            && self.peek_nth_char(self.pos + 2) == Some('s')
-            && self.peek_nth_char(self.pos + 3) == Some('e')
+            && self.peek_char_at(self.pos + 1) == Some('l')
+            && self.peek_char_at(self.pos + 2) == Some('s')
+            && self.peek_char_at(self.pos + 3) == Some('e')
         {
             self.pos += 4;
             Some(Token::False)
@@ -124,8 +319,8 @@ impl<'a> Tokenizer<'a> {
 
     fn read_null(&mut self) -> Option<Token> {
         if self.peek_char() == Some('u')
-            && self.peek_nth_char(self.pos + 1) == Some('l')
-            && self.peek_nth_char(self.pos + 2) == Some('l')
+            && self.peek_char_at(self.pos + 1) == Some('l')
+            && self.peek_char_at(self.pos + 2) == Some('l')
         {
             self.pos += 3;
             Some(Token::Null)
@@ -139,7 +334,7 @@ impl<'a> Tokenizer<'a> {
         let mut s = first.to_string();
         let mut peek_pos = self.pos;
         loop {
-            let c = self.peek_nth_char(peek_pos);
+            let c = self.peek_char_at(peek_pos);
             match c {
                 Some(c) => match c {
                     '0'..='9' | '.' | 'e' | 'E' | '+' | '-' => {
@@ -171,15 +366,10 @@ impl<'a> Tokenizer<'a> {
         }
     }
 
-    pub fn try_collect(mut self) -> Result<Vec<Token>, TokenError> {
+    pub fn try_collect(mut self) -> Result<Vec<SpannedToken>, TokenError> {
         let mut tokens = Vec::new();
-        loop {
-            match self.next_token() {
-                Some(token) => {
-                    tokens.push(token);
-                }
-                None => break,
-            }
+        while let Some(token) = self.next_token() {
+            tokens.push(token);
         }
 
         if let Some(error) = self.error {
@@ -189,34 +379,139 @@ impl<'a> Tokenizer<'a> {
         }
     }
 
+    // Collects tokens and keeps going after errors. Unreadable input up to the
+    // next `,`, `}`, `]` or line break becomes a single `Token::Invalid`, so the
+    // parser can carry on without reporting the same problem again.
+    pub fn collect_recovering(mut self) -> (Vec<SpannedToken>, Vec<TokenError>) {
+        let mut tokens = Vec::new();
+        let mut errors = Vec::new();
+        loop {
+            if let Some(token) = self.next_token() {
+                tokens.push(token);
+                continue;
+            }
+            let Some(error) = self.error.take() else {
+                break;
+            };
+            errors.push(error);
+
+            let span = self.span_at(self.token_start);
+            self.skip_to_sync_point();
+            tokens.push(SpannedToken {
+                token: Token::Invalid,
+                span: Span {
+                    len: self.pos - self.token_start,
+                    ..span
+                },
+            });
+        }
+
+        (tokens, errors)
+    }
+
+    fn skip_to_sync_point(&mut self) {
+        while let Some(c) = self.peek_char() {
+            if matches!(c, ',' | '}' | ']' | '\n') {
+                break;
+            }
+            self.next_char();
+        }
+    }
+
     fn set_error(&mut self) {
-        // first character is always consumed, so we need to subtract 1
-        self.error = Some(TokenError::new(self.pos - 1));
+        self.set_error_at(self.token_start, TokenErrorKind::UnexpectedToken);
+    }
+
+    fn set_error_at(&mut self, pos: usize, kind: TokenErrorKind) {
+        // cover everything read so far, or at least the offending character
+        let char_len = self.peek_char_at(pos).map_or(0, |c| c.len_utf8());
+        let len = (self.pos - pos).max(char_len);
+        let span = Span {
+            len,
+            ..self.span_at(pos)
+        };
+        self.error = Some(TokenError::new(span, kind));
     }
 }
 
 impl<'a> Iterator for Tokenizer<'a> {
-    type Item = Token;
+    type Item = SpannedToken;
 
     fn next(&mut self) -> Option<Self::Item> {
         self.next_token()
     }
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenErrorKind {
+    UnexpectedToken,
+    InvalidEscape(char),
+    InvalidUnicodeEscape,
+    UnpairedSurrogate,
+    ControlCharacterInString,
+}
+
+impl TokenErrorKind {
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            TokenErrorKind::UnexpectedToken => {
+                Some("expected `{`, `[`, `]`, `}`, `:`, `,`, a string, a number, `true`, `false` or `null`")
+            }
+            TokenErrorKind::InvalidEscape(_) => {
+                Some("valid escapes are `\\\"`, `\\\\`, `\\/`, `\\b`, `\\f`, `\\n`, `\\r`, `\\t` and `\\uXXXX`")
+            }
+            TokenErrorKind::InvalidUnicodeEscape => {
+                Some("`\\u` must be followed by exactly 4 hex digits")
+            }
+            TokenErrorKind::UnpairedSurrogate => {
+                Some("`\\uD800`-`\\uDBFF` must be followed by a low surrogate `\\uDC00`-`\\uDFFF`")
+            }
+            TokenErrorKind::ControlCharacterInString => {
+                Some("control characters must be escaped, e.g. `\\t` or `\\u0001`")
+            }
+        }
+    }
+}
+
+impl Display for TokenErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TokenErrorKind::UnexpectedToken => write!(f, "Unexpected token"),
+            TokenErrorKind::InvalidEscape(c) => write!(f, "Invalid escape sequence `\\{}`", c),
+            TokenErrorKind::InvalidUnicodeEscape => {
+                write!(f, "Invalid unicode escape, expected 4 hex digits")
+            }
+            TokenErrorKind::UnpairedSurrogate => write!(f, "Unpaired UTF-16 surrogate"),
+            TokenErrorKind::ControlCharacterInString => {
+                write!(f, "Unescaped control character in string")
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct TokenError {
-    start_pos: usize,
+    span: Span,
+    kind: TokenErrorKind,
 }
 
 impl TokenError {
-    pub fn new(start_pos: usize) -> TokenError {
-        TokenError { start_pos }
+    pub fn new(span: Span, kind: TokenErrorKind) -> TokenError {
+        TokenError { span, kind }
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn kind(&self) -> &TokenErrorKind {
+        &self.kind
     }
 }
 
 impl Display for TokenError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Unexpected token at position {}", self.start_pos)
+        write!(f, "{}: {}", self.span, self.kind)
     }
 }
 
@@ -313,50 +608,218 @@ mod tokenizer {
             }
         "#;
         let err = must_parse_with_error(contents);
-        assert_eq!(err.start_pos, contents.find("extra").unwrap());
+        assert_eq!(err.span.offset, contents.find("extra").unwrap());
     }
 
     #[test]
     fn test_invalid_bool() {
         let contents = "tru";
         let err = must_parse_with_error(contents);
-        assert_eq!(err.start_pos, 0);
+        assert_eq!(err.span.offset, 0);
 
         let contents = "fals";
         let err = must_parse_with_error(contents);
-        assert_eq!(err.start_pos, 0);
+        assert_eq!(err.span.offset, 0);
     }
 
     #[test]
     fn test_invalid_str() {
         let contents = r#""abc"#;
         let err = must_parse_with_error(contents);
-        assert_eq!(err.start_pos, 0);
+        assert_eq!(err.span.offset, 0);
 
         let contents = r#""abc
         ""#;
         let err = must_parse_with_error(contents);
-        assert_eq!(err.start_pos, 0);
+        assert_eq!(err.span.offset, 0);
     }
 
     #[test]
     fn test_invalid_number() {
         let contents = "1.";
         let err = must_parse_with_error(contents);
-        assert_eq!(err.start_pos, 0);
+        assert_eq!(err.span.offset, 0);
 
         let contents = "1.1e";
         let err = must_parse_with_error(contents);
-        assert_eq!(err.start_pos, 0);
+        assert_eq!(err.span.offset, 0);
 
         let contents = "01";
         let err = must_parse_with_error(contents);
-        assert_eq!(err.start_pos, 0);
+        assert_eq!(err.span.offset, 0);
+    }
+
+    #[test]
+    fn test_error_position_is_byte_offset() {
+        let contents = r#"["é😀", é]"#;
+        let err = must_parse_with_error(contents);
+        assert_eq!(err.span.offset, contents.rfind('é').unwrap());
+
+        let contents = r#"["é", tru]"#;
+        let err = must_parse_with_error(contents);
+        assert_eq!(err.span.offset, contents.find("tru").unwrap());
+    }
+
+    #[test]
+    fn test_string_escapes() {
+        let contents = r#""a\"b\\c\/d\be\ff\ng\rh\ti""#;
+        let tokens = must_parse_tokens(contents);
+        assert_eq!(
+            tokens,
+            vec![Token::String("a\"b\\c/d\u{8}e\u{c}f\ng\rh\ti".to_string())]
+        );
+
+        let contents = r#""\u0041\u00e9\u20AC""#;
+        let tokens = must_parse_tokens(contents);
+        assert_eq!(tokens, vec![Token::String("Aé€".to_string())]);
+
+        // surrogate pair for U+1F600
+        let contents = r#"["\ud83d\ude00", 1]"#;
+        let tokens = must_parse_tokens(contents);
+        assert_eq!(
+            tokens,
+            vec![
+                Token::BracketOpen,
+                Token::String("😀".to_string()),
+                Token::Comma,
+                Token::Number(1.0),
+                Token::BracketClose,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_escapes() {
+        let contents = r#"["ab\xcd"]"#;
+        let err = must_parse_with_error(contents);
+        assert_eq!(err.span.offset, 4);
+        assert_eq!(err.kind, TokenErrorKind::InvalidEscape('x'));
+
+        let contents = r#""\u12""#;
+        let err = must_parse_with_error(contents);
+        assert_eq!(err.span.offset, 1);
+        assert_eq!(err.kind, TokenErrorKind::InvalidUnicodeEscape);
+
+        let contents = r#""a\ud83d""#;
+        let err = must_parse_with_error(contents);
+        assert_eq!(err.span.offset, 2);
+        assert_eq!(err.kind, TokenErrorKind::UnpairedSurrogate);
+
+        let contents = r#""\ud83d\u0041""#;
+        let err = must_parse_with_error(contents);
+        assert_eq!(err.span.offset, 1);
+        assert_eq!(err.kind, TokenErrorKind::UnpairedSurrogate);
+
+        let contents = r#""\ude00""#;
+        let err = must_parse_with_error(contents);
+        assert_eq!(err.span.offset, 1);
+        assert_eq!(err.kind, TokenErrorKind::UnpairedSurrogate);
+
+        let contents = r#""abc\"#;
+        let err = must_parse_with_error(contents);
+        assert_eq!(err.span.offset, 0);
+        assert_eq!(err.kind, TokenErrorKind::UnexpectedToken);
+    }
+
+    #[test]
+    fn test_control_character_in_string() {
+        let contents = "\"a\tb\"";
+        let err = must_parse_with_error(contents);
+        assert_eq!(err.span.offset, 2);
+        assert_eq!(err.kind, TokenErrorKind::ControlCharacterInString);
+
+        let contents = "\"\u{1}\"";
+        let err = must_parse_with_error(contents);
+        assert_eq!(err.span.offset, 1);
+        assert_eq!(err.kind, TokenErrorKind::ControlCharacterInString);
+        // escaped line breaks are reported on the line of the backslash
+        for contents in ["\"a\\\n\"", "\"a\\\r\n\"", "[\n\"a\\\n\"]"] {
+            let err = must_parse_with_error(contents);
+            assert_eq!(err.kind, TokenErrorKind::ControlCharacterInString);
+            assert_eq!(err.span.line, contents.matches('[').count() + 1);
+            assert_eq!(err.span.column, 3);
+        }
+        let (_, errors) = Tokenizer::new("[\"a\\\n1]").collect_recovering();
+        assert_eq!(errors.len(), 1);
+        assert_eq!((errors[0].span.line, errors[0].span.column), (1, 4));
+    }
+
+    #[test]
+    fn test_token_spans() {
+        let contents = "{\n  \"ké\": [1,\n\ttrue]\n}";
+        let tokenizer = Tokenizer::new(contents);
+        let spans = tokenizer
+            .map(|t| (t.span.offset, t.span.len, t.span.line, t.span.column))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            spans,
+            vec![
+                (0, 1, 1, 1),
+                (4, 5, 2, 3),
+                (9, 1, 2, 7),
+                (11, 1, 2, 9),
+                (12, 1, 2, 10),
+                (13, 1, 2, 11),
+                (16, 4, 3, 2),
+                (20, 1, 3, 6),
+                (22, 1, 4, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_error_line_and_column() {
+        let contents = "[\n  \"é\", \"a\\qb\"\n]";
+        let err = must_parse_with_error(contents);
+        assert_eq!(err.span.line, 2);
+        assert_eq!(err.span.column, 10);
+        assert_eq!(err.to_string(), "2:10: Invalid escape sequence `\\q`");
+    }
+
+    #[test]
+    fn test_collect_recovering() {
+        let contents = "{\"a\": tru, \"b\": \"x\\qy\"]\n  \"c\": 1 @}";
+        let (tokens, errors) = Tokenizer::new(contents).collect_recovering();
+        assert_eq!(
+            tokens.iter().map(|t| t.token.clone()).collect::<Vec<_>>(),
+            vec![
+                Token::BraceOpen,
+                Token::String("a".to_string()),
+                Token::Colon,
+                Token::Invalid,
+                Token::Comma,
+                Token::String("b".to_string()),
+                Token::Colon,
+                Token::Invalid,
+                Token::BracketClose,
+                Token::String("c".to_string()),
+                Token::Colon,
+                Token::Number(1.0),
+                Token::Invalid,
+                Token::BraceClose,
+            ]
+        );
+        assert_eq!(tokens[3].span.len, 3);
+        assert_eq!(
+            errors.iter().map(|e| e.kind.clone()).collect::<Vec<_>>(),
+            vec![
+                TokenErrorKind::UnexpectedToken,
+                TokenErrorKind::InvalidEscape('q'),
+                TokenErrorKind::UnexpectedToken,
+            ]
+        );
+        assert_eq!(errors[2].span.line, 2);
+        assert_eq!(errors[2].span.column, 10);
     }
 
     fn must_parse_tokens(contents: &str) -> Vec<Token> {
         let tokenizer = Tokenizer::new(contents);
-        tokenizer.try_collect().expect("Failed to collect tokens")
+        tokenizer
+            .try_collect()
+            .expect("Failed to collect tokens")
+            .into_iter()
+            .map(|t| t.token)
+            .collect()
     }
 
     fn must_parse_with_error(contents: &str) -> TokenError {
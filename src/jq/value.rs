@@ -0,0 +1,308 @@
+use std::cmp::Ordering;
+use std::fmt::{Display, Write};
+
+use super::Value;
+
+const INDENT: usize = 2;
+
+impl Value {
+    // type name as reported by jq, e.g. in `Cannot index number with "foo"`
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Object(_) => "object",
+            Value::Array(_) => "array",
+            Value::String(_) => "string",
+            Value::Number(_) => "number",
+            Value::Boolean(_) => "boolean",
+            Value::Null => "null",
+        }
+    }
+
+    /// `false` and `null` are false, every other value is true.
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Boolean(false) | Value::Null)
+    }
+
+    // position of the type in jq's ordering
+    fn type_rank(&self) -> u8 {
+        match self {
+            Value::Null => 0,
+            Value::Boolean(false) => 1,
+            Value::Boolean(true) => 2,
+            Value::Number(_) => 3,
+            Value::String(_) => 4,
+            Value::Array(_) => 5,
+            Value::Object(_) => 6,
+        }
+    }
+
+    /// Serializes the value as indented JSON, the way jq prints its results.
+    pub fn to_json_pretty(&self) -> String {
+        let mut out = String::new();
+        let _ = write_json(&mut out, self, Some(0));
+        out
+    }
+}
+
+// compact JSON, as printed by `jq -c`
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write_json(f, self, None)
+    }
+}
+
+// Values are ordered like in jq: null < false < true < numbers < strings <
+// arrays < objects. Strings compare by their bytes, arrays element by element,
+// and objects by their sorted keys, then by the values of those keys. Objects
+// with the same fields in a different order are equal.
+impl Ord for Value {
+    fn cmp(&self, other: &Value) -> Ordering {
+        match (self, other) {
+            (Value::Number(a), Value::Number(b)) => compare_numbers(*a, *b),
+            (Value::String(a), Value::String(b)) => a.cmp(b),
+            (Value::Array(a), Value::Array(b)) => a.iter().cmp(b.iter()),
+            (Value::Object(a), Value::Object(b)) => {
+                let (a, b) = (sorted_fields(a), sorted_fields(b));
+                a.iter()
+                    .map(|(k, _)| k)
+                    .cmp(b.iter().map(|(k, _)| k))
+                    .then_with(|| a.iter().map(|(_, v)| v).cmp(b.iter().map(|(_, v)| v)))
+            }
+            (a, b) => a.type_rank().cmp(&b.type_rank()),
+        }
+    }
+}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Value) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Value {}
+
+// NaN sorts below every number and equals itself, so the order stays total
+fn compare_numbers(a: f64, b: f64) -> Ordering {
+    a.partial_cmp(&b)
+        .unwrap_or_else(|| b.is_nan().cmp(&a.is_nan()))
+}
+
+fn sorted_fields(fields: &[(String, Value)]) -> Vec<&(String, Value)> {
+    let mut sorted: Vec<_> = fields.iter().collect();
+    sorted.sort_by(|(a, _), (b, _)| a.cmp(b));
+    sorted
+}
+
+// `indent` is the current nesting level, `None` for compact output
+fn write_json<W: Write>(out: &mut W, value: &Value, indent: Option<usize>) -> std::fmt::Result {
+    let newline = |out: &mut W, level: usize| match indent {
+        Some(_) => write!(out, "\n{:width$}", "", width = level * INDENT),
+        None => Ok(()),
+    };
+    let level = indent.unwrap_or(0);
+    let inner = indent.map(|i| i + 1);
+
+    match value {
+        Value::Object(fields) if fields.is_empty() => out.write_str("{}"),
+        Value::Object(fields) => {
+            out.write_char('{')?;
+            for (i, (key, value)) in fields.iter().enumerate() {
+                if i > 0 {
+                    out.write_char(',')?;
+                }
+                newline(out, level + 1)?;
+                write_json_string(out, key)?;
+                out.write_str(if indent.is_some() { ": " } else { ":" })?;
+                write_json(out, value, inner)?;
+            }
+            newline(out, level)?;
+            out.write_char('}')
+        }
+        Value::Array(elements) if elements.is_empty() => out.write_str("[]"),
+        Value::Array(elements) => {
+            out.write_char('[')?;
+            for (i, value) in elements.iter().enumerate() {
+                if i > 0 {
+                    out.write_char(',')?;
+                }
+                newline(out, level + 1)?;
+                write_json(out, value, inner)?;
+            }
+            newline(out, level)?;
+            out.write_char(']')
+        }
+        Value::String(s) => write_json_string(out, s),
+        Value::Number(n) => write_json_number(out, *n),
+        Value::Boolean(b) => write!(out, "{}", b),
+        Value::Null => out.write_str("null"),
+    }
+}
+
+pub fn write_json_string<W: Write>(out: &mut W, s: &str) -> std::fmt::Result {
+    out.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => out.write_str("\\\"")?,
+            '\\' => out.write_str("\\\\")?,
+            '\n' => out.write_str("\\n")?,
+            '\t' => out.write_str("\\t")?,
+            '\r' => out.write_str("\\r")?,
+            '\u{8}' => out.write_str("\\b")?,
+            '\u{c}' => out.write_str("\\f")?,
+            c if c < '\u{20}' || c == '\u{7f}' => write!(out, "\\u{:04x}", c as u32)?,
+            c => out.write_char(c)?,
+        }
+    }
+    out.write_char('"')
+}
+
+// integers are printed without a fraction, like jq does
+fn write_json_number<W: Write>(out: &mut W, n: f64) -> std::fmt::Result {
+    if n.is_nan() {
+        out.write_str("null")
+    } else if n.is_infinite() {
+        // jq clamps infinities to the largest finite number
+        let max = if n > 0.0 { f64::MAX } else { f64::MIN };
+        write_exponent(out, max)
+    } else if n.fract() == 0.0 && n.abs() < 1e17 {
+        write!(out, "{}", n as i64)
+    } else if n.abs() >= 1e17 || n.abs() < 1e-4 {
+        write_exponent(out, n)
+    } else {
+        write!(out, "{}", n)
+    }
+}
+
+// scientific notation the way C's `printf` writes it, e.g. `1e+100` and
+// `1.5e-07`, with a signed exponent of at least two digits
+fn write_exponent<W: Write>(out: &mut W, n: f64) -> std::fmt::Result {
+    let formatted = format!("{:e}", n);
+    let (mantissa, exponent) = formatted.split_once('e').unwrap_or((&formatted, "0"));
+    let exponent: i32 = exponent.parse().unwrap_or(0);
+    let sign = if exponent < 0 { '-' } else { '+' };
+    write!(out, "{}e{}{:02}", mantissa, sign, exponent.abs())
+}
+
+#[cfg(test)]
+mod value {
+    use super::*;
+
+    #[test]
+    fn test_display_compact() {
+        let value =
+            crate::parse_str(r#"{"a": [1, 2.5, "x\ny"], "b": {}, "c": [], "d": null}"#).unwrap();
+        assert_eq!(
+            value.to_string(),
+            r#"{"a":[1,2.5,"x\ny"],"b":{},"c":[],"d":null}"#
+        );
+    }
+
+    #[test]
+    fn test_to_json_pretty() {
+        let value = crate::parse_str(r#"{"a": [1, {"b": true}], "c": []}"#).unwrap();
+        assert_eq!(
+            value.to_json_pretty(),
+            [
+                "{",
+                "  \"a\": [",
+                "    1,",
+                "    {",
+                "      \"b\": true",
+                "    }",
+                "  ],",
+                "  \"c\": []",
+                "}",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_write_json_string_escapes() {
+        let mut out = String::new();
+        write_json_string(&mut out, "\"q\" \\ \u{1} \u{7f} é").unwrap();
+        assert_eq!(out, r#""\"q\" \\ \u0001 \u007f é""#);
+    }
+
+    #[test]
+    fn test_ordering() {
+        let sorted = [
+            "null",
+            "false",
+            "true",
+            "-1",
+            "0.5",
+            "10",
+            r#""""#,
+            r#""B""#,
+            r#""a""#,
+            r#""ab""#,
+            "[]",
+            "[1]",
+            "[1, 2]",
+            "[2]",
+            "{}",
+            r#"{"a": 2}"#,
+            r#"{"a": 3}"#,
+            r#"{"a": 1, "b": 1}"#,
+            r#"{"b": 0}"#,
+        ];
+        let values: Vec<Value> = sorted
+            .iter()
+            .map(|s| crate::parse_str(s).unwrap())
+            .collect();
+        for (i, a) in values.iter().enumerate() {
+            for (j, b) in values.iter().enumerate() {
+                assert_eq!(a.cmp(b), i.cmp(&j), "{} <=> {}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_equality() {
+        let parse = |s| crate::parse_str(s).unwrap();
+        assert_eq!(
+            parse(r#"{"a": 1, "b": [2]}"#),
+            parse(r#"{"b": [2.0], "a": 1}"#)
+        );
+        assert_ne!(parse(r#"{"a": 1}"#), parse(r#"{"a": 1, "b": null}"#));
+        assert_eq!(Value::Number(0.0), Value::Number(-0.0));
+        // the total order used for sorting, `nan == nan` is false in filters
+        assert_eq!(
+            Value::Number(f64::NAN).cmp(&Value::Number(f64::NAN)),
+            Ordering::Equal
+        );
+        assert!(Value::Number(f64::NAN) < Value::Number(f64::NEG_INFINITY));
+    }
+
+    #[test]
+    fn test_is_truthy() {
+        assert!(!Value::Null.is_truthy());
+        assert!(!Value::Boolean(false).is_truthy());
+        assert!(Value::Boolean(true).is_truthy());
+        assert!(Value::Number(0.0).is_truthy());
+        assert!(Value::String(String::new()).is_truthy());
+    }
+
+    #[test]
+    fn test_write_json_number() {
+        let format = |n: f64| Value::Number(n).to_string();
+        assert_eq!(format(1.0), "1");
+        assert_eq!(format(-0.5), "-0.5");
+        assert_eq!(format(1e100), "1e+100");
+        assert_eq!(format(-1.5e300), "-1.5e+300");
+        assert_eq!(format(1e17), "1e+17");
+        assert_eq!(format(1e-7), "1e-07");
+        assert_eq!(format(2.5e-100), "2.5e-100");
+        assert_eq!(format(0.0001), "0.0001");
+        assert_eq!(format(0.00001), "1e-05");
+        assert_eq!(format(f64::INFINITY), "1.7976931348623157e+308");
+        assert_eq!(format(f64::NAN), "null");
+    }
+}
@@ -1,20 +1,211 @@
-use jq;
-use log::{error, info};
+use std::io::{BufWriter, IsTerminal, Write};
+use std::iter;
+use std::thread;
+
+use jq::filter::Filter;
+use log::{error, info, warn};
 
 pub mod args;
 
+const STDIN: &str = "-";
+const STDIN_NAME: &str = "<stdin>";
+// enough for values and paths nested as deep as parsing allows, only the
+// pages used are committed
+const STACK_SIZE: usize = 1 << 30;
+
+// values of an input with the line each of them ends on
+type Values = Box<dyn Iterator<Item = anyhow::Result<(jq::Value, usize)>>>;
+
+// what to do with the values of every input
+struct Run<'a> {
+    filter: Option<&'a Filter>,
+    compact: bool,
+    skip_invalid: bool,
+}
+
 fn main() {
-    env_logger::init_from_env(env_logger::Env::default().default_filter_or("debug"));
+    env_logger::init_from_env(env_logger::Env::default().default_filter_or("info"));
 
-    let input = args::parse().input;
+    let ok = thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(process_inputs)
+        .map(|run| run.join().unwrap_or(false))
+        .unwrap_or_else(|e| {
+            error!("Error: {}", e);
+            false
+        });
+    if !ok {
+        std::process::exit(1);
+    }
+}
 
-    let result = match (input.file, input.raw) {
-        (Some(file), None) => jq::process_file(&file),
-        (None, Some(raw)) => jq::process_str(&raw),
-        _ => panic!("Should never happen!"),
+// Processes every input, returns whether everything succeeded.
+fn process_inputs() -> bool {
+    let args = args::parse();
+    let input = args.input;
+
+    let options = jq::ParseOptions {
+        multi: args.multi,
+        all_errors: args.all_errors,
     };
-    match result {
-        Ok(_) => info!("JSON is valid."),
-        Err(e) => error!("Error: {}", e),
+    // records of a stream are checked one by one, unless all errors are wanted
+    let streaming = args.multi && !args.all_errors;
+
+    let filter = args.filter.as_deref().map(|source| {
+        Filter::parse(source).unwrap_or_else(|e| {
+            report_error(&e.into(), Some(source));
+            std::process::exit(1);
+        })
+    });
+    let run = Run {
+        filter: filter.as_ref(),
+        compact: args.compact_output,
+        skip_invalid: args.skip_invalid,
     };
+    let mut out = BufWriter::new(std::io::stdout().lock());
+
+    let mut ok = true;
+    if let Some(raw) = &input.raw {
+        let values: Values = if streaming {
+            Box::new(
+                jq::parse_many(raw)
+                    .with_lines()
+                    .map(|r| r.map_err(anyhow::Error::from)),
+            )
+        } else {
+            values_or_error(jq::parse_str_with_lines(raw, options))
+        };
+        ok &= process(None, values, Some(raw), &run, &mut out);
+    } else {
+        // inputs are processed in order, stdin if none are given
+        let mut files: Vec<String> = input.file.into_iter().chain(args.files).collect();
+        if files.is_empty() {
+            files.push(STDIN.to_string());
+        }
+
+        for file in &files {
+            let (name, values) = if file == STDIN {
+                let stdin = std::io::stdin().lock();
+                let values = if streaming {
+                    records_or_error(jq::parse_many_reader_lines(stdin, STDIN_NAME))
+                } else {
+                    values_or_error(jq::parse_reader_with_lines(stdin, STDIN_NAME, options))
+                };
+                (STDIN_NAME, values)
+            } else {
+                let values = if streaming {
+                    records_or_error(jq::parse_many_file_lines(file))
+                } else {
+                    values_or_error(jq::parse_file_with_lines(file, options))
+                };
+                (file.as_str(), values)
+            };
+            ok &= process(Some(name), values, None, &run, &mut out);
+        }
+    }
+
+    out.flush().is_ok() && ok
+}
+
+fn values_or_error(values: anyhow::Result<Vec<(jq::Value, usize)>>) -> Values {
+    match values {
+        Ok(values) => Box::new(values.into_iter().map(Ok)),
+        Err(e) => Box::new(iter::once(Err(e))),
+    }
+}
+
+fn records_or_error(
+    records: anyhow::Result<impl Iterator<Item = anyhow::Result<(jq::Value, usize)>> + 'static>,
+) -> Values {
+    match records {
+        Ok(records) => Box::new(records),
+        Err(e) => Box::new(iter::once(Err(e))),
+    }
+}
+
+// Runs the filter on every value of an input, or only validates the input
+// without a filter. Reports bad records, stopping at the first one unless
+// `skip_invalid`. Returns whether everything succeeded.
+fn process(
+    name: Option<&str>,
+    values: Values,
+    raw: Option<&str>,
+    run: &Run,
+    out: &mut impl Write,
+) -> bool {
+    let (mut valid, mut invalid) = (0, 0);
+    let mut ok = true;
+    for value in values {
+        match value {
+            Ok((value, line)) => {
+                valid += 1;
+                if let Some(filter) = run.filter {
+                    // where runtime errors happened, like jq reports it
+                    let location = match name {
+                        Some(name) => format!("{}:{}", name, line),
+                        None => "<unknown>".to_string(),
+                    };
+                    ok &= print_outputs(filter, value, &location, run.compact, out);
+                }
+            }
+            Err(e) => {
+                report_error(&e, raw);
+                invalid += 1;
+                if !run.skip_invalid {
+                    break;
+                }
+            }
+        }
+    }
+
+    let prefix = name.map(|name| format!("{}: ", name)).unwrap_or_default();
+    if invalid > 0 && run.skip_invalid {
+        warn!(
+            "{}skipped {} invalid records, {} valid",
+            prefix, invalid, valid
+        );
+    } else if invalid == 0 && run.filter.is_none() {
+        info!("{}JSON is valid.", prefix);
+    }
+    ok && invalid == 0
+}
+
+// prints each output of the filter, the first error ends the outputs
+fn print_outputs(
+    filter: &Filter,
+    value: jq::Value,
+    location: &str,
+    compact: bool,
+    out: &mut impl Write,
+) -> bool {
+    for output in filter.run(value) {
+        let written = match output {
+            Ok(value) if compact => writeln!(out, "{}", value),
+            Ok(value) => writeln!(out, "{}", value.to_json_pretty()),
+            Err(e) => {
+                // keep the outputs before the error in order with it
+                let _ = out.flush();
+                // like jq, errors that aren't messages are marked as such
+                let kind = match e.value() {
+                    jq::Value::String(_) => "",
+                    _ => " (not a string)",
+                };
+                eprintln!("jq: error (at {}){}: {}", location, kind, e);
+                return false;
+            }
+        };
+        if written.is_err() {
+            return false;
+        }
+    }
+    true
+}
+
+fn report_error(e: &anyhow::Error, raw: Option<&str>) {
+    // no escape codes when output is piped or redirected
+    let color = std::io::stdout().is_terminal() && std::io::stderr().is_terminal();
+    match jq::diagnostics::render_error(e, raw, color) {
+        Some(rendered) => eprint!("{}", rendered),
+        None => error!("Error: {:#}", e),
+    }
 }
@@ -0,0 +1,271 @@
+#[cfg(test)]
+mod cli {
+    use std::io::{ErrorKind, Write};
+    use std::path::Path;
+    use std::process::{self, Command, Output, Stdio};
+    use std::{env, fs};
+
+    #[test]
+    fn test_cli_reads_stdin_by_default() {
+        let output = run(&[], "{\"a\": [1, 2]}");
+        assert!(output.status.success());
+
+        let output = run(&[], "[1 2]");
+        assert!(!output.status.success());
+        assert!(stderr(&output).contains("--> <stdin>:1:4"));
+    }
+
+    #[test]
+    fn test_cli_processes_files_in_order() {
+        let output = run(
+            &[
+                "tests/testdata/valid/simple.json",
+                "-",
+                "tests/testdata/invalid/trailing_value.json",
+                "tests/testdata/valid/unicode.json",
+            ],
+            "[1,]",
+        );
+        assert!(!output.status.success());
+
+        let stderr = stderr(&output);
+        let positions: Vec<usize> = [
+            "tests/testdata/valid/simple.json: JSON is valid.",
+            "--> <stdin>:1:3",
+            "--> tests/testdata/invalid/trailing_value.json:1:4",
+            "tests/testdata/valid/unicode.json: JSON is valid.",
+        ]
+        .iter()
+        .map(|s| {
+            stderr
+                .find(s)
+                .unwrap_or_else(|| panic!("{} not in {}", s, stderr))
+        })
+        .collect();
+        assert!(positions.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn test_cli_filters_files_in_order() {
+        let output = run(
+            &[
+                ".",
+                "-c",
+                "tests/testdata/valid/simple.json",
+                "-",
+                "tests/testdata/invalid/trailing_value.json",
+                "tests/testdata/valid/unicode.json",
+            ],
+            "[1,]",
+        );
+        assert!(!output.status.success());
+        assert_eq!(
+            stdout(&output),
+            "{\"id\":1,\"name\":\"Ahmet\"}\n{\"key\":\"smileyface: 😀\"}\n"
+        );
+
+        let stderr = stderr(&output);
+        let stdin_error = stderr.find("--> <stdin>:1:3").unwrap();
+        let file_error = stderr
+            .find("--> tests/testdata/invalid/trailing_value.json:1:4")
+            .unwrap();
+        assert!(stdin_error < file_error);
+        assert!(!stderr.contains("JSON is valid."));
+
+        // only a first argument that is not a file is the filter
+        let output = run(&["-", "tests/testdata/valid/simple.json"], "{}");
+        assert!(output.status.success());
+        assert_eq!(stdout(&output), "");
+        let output = run(&["-c", ".id", "tests/testdata/valid/simple.json"], "");
+        assert_eq!(stdout(&output), "1\n");
+    }
+
+    #[test]
+    fn test_cli_filter_named_like_a_file() {
+        // such a file is only an input when no other input follows
+        let dir = env::temp_dir().join(format!("rust-jq-cli-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("paths"), "[1,]").unwrap();
+        let simple = fs::canonicalize("tests/testdata/valid/simple.json").unwrap();
+        let output = run_in(&dir, &["-c", "paths", simple.to_str().unwrap()], "");
+        let only_paths = run_in(&dir, &["paths"], "");
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(output.status.success());
+        assert_eq!(stdout(&output), "[\"id\"]\n[\"name\"]\n");
+        assert!(!only_paths.status.success());
+        assert!(stderr(&only_paths).contains("--> paths:1:3"));
+    }
+
+    #[test]
+    fn test_cli_rejects_deep_nesting() {
+        let deep = "[".repeat(50_000) + &"]".repeat(50_000);
+        for args in [&[][..], &["-c", "."][..]] {
+            let output = run(args, &deep);
+            assert!(!output.status.success());
+            assert!(stderr(&output).contains("error: Exceeds depth limit for parsing"));
+        }
+
+        let deepest = "[".repeat(10_000) + &"]".repeat(10_000);
+        let output = run(&["-c", "[..] | .[-1]"], &deepest);
+        assert!(output.status.success());
+        assert_eq!(stdout(&output), "[]\n");
+    }
+
+    #[test]
+    fn test_cli_escaped_line_break() {
+        for args in [
+            &[][..],
+            &["--all-errors"][..],
+            &["--multi", "--skip-invalid"][..],
+        ] {
+            let output = run(args, "\"a\\\n\"");
+            assert!(!output.status.success());
+            let stderr = stderr(&output);
+            assert!(stderr.contains("--> <stdin>:1:3"), "{}", stderr);
+            assert!(!stderr.contains("panicked"), "{}", stderr);
+        }
+    }
+
+    #[test]
+    fn test_cli_raw_input() {
+        let output = run(&["--raw", "[1, 2]"], "");
+        assert!(output.status.success());
+
+        let output = run(&["--raw", "[1, 2]", "tests/testdata/valid/simple.json"], "");
+        assert!(!output.status.success());
+
+        let output = run(
+            &["--raw", "[1, 2]", ".", "tests/testdata/valid/simple.json"],
+            "",
+        );
+        assert!(!output.status.success());
+    }
+
+    #[test]
+    fn test_cli_filter() {
+        let input = r#"{"users": [{"name": "a"}, {"name": "b", "tags": [1]}]}"#;
+        let output = run(&[".users[-1]"], input);
+        assert!(output.status.success());
+        assert_eq!(
+            stdout(&output),
+            "{\n  \"name\": \"b\",\n  \"tags\": [\n    1\n  ]\n}\n"
+        );
+
+        let output = run(&["-c", ".users[0] | .name"], input);
+        assert_eq!(stdout(&output), "\"a\"\n");
+
+        let output = run(&["-c", "."], "");
+        assert!(output.status.success());
+        assert_eq!(stdout(&output), "");
+
+        let output = run(&[".users.name"], input);
+        assert!(!output.status.success());
+        assert_eq!(
+            stderr(&output),
+            "jq: error (at <stdin>:1): Cannot index array with \"name\"\n"
+        );
+
+        let config = r#"{"spec": {"replicas": 1, "image": "app:1"}}"#;
+        let output = run(
+            &["-c", r#".spec.replicas = 3 | .spec.image += "-rc""#],
+            config,
+        );
+        assert_eq!(
+            stdout(&output),
+            "{\"spec\":{\"replicas\":3,\"image\":\"app:1-rc\"}}\n"
+        );
+
+        let output = run(
+            &["-c", r#".users[] | @sh "echo \(.name)", @csv "\([.name])""#],
+            input,
+        );
+        assert_eq!(
+            stdout(&output),
+            "\"echo 'a'\"\n\"\\\"a\\\"\"\n\"echo 'b'\"\n\"\\\"b\\\"\"\n"
+        );
+
+        let output = run(&[".users[0"], input);
+        assert!(!output.status.success());
+        assert!(stderr(&output).contains("error: Unexpected end of filter\n --> 1:9\n"));
+    }
+
+    #[test]
+    fn test_cli_runtime_errors() {
+        let input = "{\"a\": 1}\n{\"a\": \"x\"}\n{\"a\": 3}\n";
+        let output = run(&["--multi", "-c", ".a + 1"], input);
+        assert!(!output.status.success());
+        assert_eq!(stdout(&output), "2\n4\n");
+        assert_eq!(
+            stderr(&output),
+            "jq: error (at <stdin>:2): string (\"x\") and number (1) cannot be added\n"
+        );
+
+        let output = run(&["--multi", "-c", "try (.a + 1) catch \"bad\""], input);
+        assert!(output.status.success());
+        assert_eq!(stdout(&output), "2\n\"bad\"\n4\n");
+
+        let output = run(&["error({code: .a})"], "{\n  \"a\": 1\n}\n");
+        assert_eq!(
+            stderr(&output),
+            "jq: error (at <stdin>:3) (not a string): {\"code\":1}\n"
+        );
+
+        let output = run(&["def f: if . < 100000 then . + 1 | f else . end; f"], "0");
+        assert_eq!(stdout(&output), "100000\n");
+
+        let output = run(&["def f: f; f"], "0");
+        assert!(!output.status.success());
+        assert_eq!(
+            stderr(&output),
+            "jq: error (at <stdin>:1): Exceeds depth limit for function calls\n"
+        );
+    }
+
+    #[test]
+    fn test_cli_skip_invalid_records() {
+        let file = "tests/testdata/stream/records.ndjson";
+        let output = run(&["--multi", "-f", file], "");
+        assert!(!output.status.success());
+        let stderr_stop = stderr(&output);
+        assert!(stderr_stop.contains("error: Missing comma in record #2"));
+        assert!(!stderr_stop.contains("record #4"));
+
+        let output = run(&["--multi", "--skip-invalid", "-f", file], "");
+        assert!(!output.status.success());
+        let stderr_skip = stderr(&output);
+        assert!(stderr_skip.contains("error: Unexpected token in record #4"));
+        assert!(stderr_skip.contains("skipped 3 invalid records, 3 valid"));
+
+        let output = run(&["--multi", "--skip-invalid"], "{}\n[]\n");
+        assert!(output.status.success());
+    }
+
+    fn run(args: &[&str], stdin: &str) -> Output {
+        run_in(Path::new("."), args, stdin)
+    }
+
+    fn run_in(dir: &Path, args: &[&str], stdin: &str) -> Output {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_rust-jq"))
+            .current_dir(dir)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("Failed to run rust-jq");
+        // rust-jq may exit before reading all of stdin, e.g. on a usage error
+        match child.stdin.take().unwrap().write_all(stdin.as_bytes()) {
+            Err(e) if e.kind() != ErrorKind::BrokenPipe => panic!("{}", e),
+            _ => {}
+        }
+        child.wait_with_output().unwrap()
+    }
+
+    fn stdout(output: &Output) -> String {
+        String::from_utf8_lossy(&output.stdout).to_string()
+    }
+
+    fn stderr(output: &Output) -> String {
+        String::from_utf8_lossy(&output.stderr).to_string()
+    }
+}
@@ -2,8 +2,6 @@
 mod e2e {
     use std::fs;
 
-    use jq;
-
     #[test]
     fn test_e2e_valid() {
         let dirname = "tests/testdata/valid";
@@ -26,6 +24,194 @@ mod e2e {
         }
     }
 
+    #[test]
+    fn test_e2e_parse_str() {
+        let contents = fs::read_to_string("tests/testdata/valid/simple.json").unwrap();
+        let value = jq::parse_str(&contents).expect("Failed to parse file");
+        assert_eq!(
+            value,
+            jq::Value::Object(
+                vec![
+                    ("id".to_string(), jq::Value::Number(1.0)),
+                    ("name".to_string(), jq::Value::String("Ahmet".to_string())),
+                ]
+                .into()
+            )
+        );
+
+        assert!(jq::parse_str("").is_err());
+    }
+
+    #[test]
+    fn test_e2e_multi() {
+        let multi = jq::ParseOptions {
+            multi: true,
+            ..Default::default()
+        };
+        let dirname = "tests/testdata/valid";
+        for filename in get_all_files(dirname) {
+            let path = format!("{}/{}", dirname, filename);
+            let result = jq::process_file_with(&path, multi);
+            assert!(result.is_ok(), "Error processing file: {}", path);
+        }
+
+        let values = jq::parse_str_with("{} {}\n[]", multi).expect("Failed to parse values");
+        assert_eq!(values.len(), 3);
+
+        assert!(jq::process_str_with("1 2 ]", multi).is_err());
+    }
+
+    #[test]
+    fn test_e2e_error_location() {
+        let path = "tests/testdata/invalid/nested_complex.json";
+        let err = jq::process_file(path).unwrap_err();
+        assert_eq!(err.to_string(), format!("{}:38:3: Missing comma", path));
+
+        let path = "tests/testdata/invalid/invalid_escape.json";
+        let err = jq::process_file(path).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("{}:2:15: Invalid escape sequence `\\x`", path)
+        );
+    }
+
+    #[test]
+    fn test_e2e_all_errors() {
+        for (dirname, valid) in [
+            ("tests/testdata/valid", true),
+            ("tests/testdata/invalid", false),
+        ] {
+            for filename in get_all_files(dirname) {
+                let path = format!("{}/{}", dirname, filename);
+                let contents = fs::read_to_string(&path).unwrap();
+                let diagnostics = jq::diagnose_str(&contents, jq::ParseOptions::default());
+                assert_eq!(
+                    diagnostics.is_empty(),
+                    valid,
+                    "Unexpected diagnostics for file: {}",
+                    path
+                );
+            }
+        }
+
+        let contents = fs::read_to_string("tests/testdata/invalid/many_errors.json").unwrap();
+        let diagnostics = jq::diagnose_str(&contents, jq::ParseOptions::default());
+        let locations: Vec<_> = diagnostics
+            .iter()
+            .map(|d| (d.span.line, d.span.column))
+            .collect();
+        assert_eq!(
+            locations,
+            vec![(3, 13), (4, 19), (4, 24), (5, 14), (6, 18), (7, 20)]
+        );
+    }
+
+    #[test]
+    fn test_e2e_process_reader() {
+        let file = fs::File::open("tests/testdata/valid/nested_complex.json").unwrap();
+        assert!(jq::process_reader(file, "nested_complex.json").is_ok());
+
+        let err = jq::process_reader("[1 2]".as_bytes(), "<stdin>").unwrap_err();
+        assert_eq!(err.to_string(), "<stdin>:1:4: Missing comma");
+
+        let err = jq::process_file("tests/testdata/missing.json").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Could not open tests/testdata/missing.json"
+        );
+    }
+
+    #[test]
+    fn test_e2e_value_lines() {
+        let lines = |contents, multi| {
+            let options = jq::ParseOptions {
+                multi,
+                ..Default::default()
+            };
+            jq::parse_str_with_lines(contents, options)
+                .unwrap()
+                .into_iter()
+                .map(|(_, line)| line)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(lines("{\n  \"a\": 1\n}\n\n", false), vec![3]);
+        assert_eq!(lines("1 2\n[\n3]\n", true), vec![1, 1, 3]);
+        assert!(jq::parse_str_with_lines("1 2", Default::default()).is_err());
+    }
+
+    #[test]
+    fn test_e2e_parse_many() {
+        let values: Vec<_> = jq::parse_many("1 [2]\n{\"a\": 3}").collect();
+        assert_eq!(values.len(), 3);
+        assert!(values.iter().all(|v| v.is_ok()));
+
+        let records: Vec<_> = jq::parse_many_file("tests/testdata/stream/records.ndjson")
+            .unwrap()
+            .collect();
+        let ids: Vec<f64> = records
+            .iter()
+            .filter_map(|r| match r {
+                Ok(jq::Value::Object(fields)) => match fields[0].1 {
+                    jq::Value::Number(id) => Some(id),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect();
+        assert_eq!(ids, vec![1.0, 3.0, 5.0]);
+
+        let errors: Vec<String> = records
+            .iter()
+            .filter_map(|r| r.as_ref().err())
+            .map(|e| e.to_string())
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                "tests/testdata/stream/records.ndjson:2:10: Missing comma (in record #2 starting on line 2)",
+                "tests/testdata/stream/records.ndjson:4:19: Unexpected token (in record #4 starting on line 4)",
+                "tests/testdata/stream/records.ndjson:6:3: Unexpected end of input (in record #6 starting on line 6)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_e2e_filter() {
+        let value = jq::parse_file_with(
+            "tests/testdata/valid/nested_complex.json",
+            jq::ParseOptions::default(),
+        )
+        .unwrap()
+        .remove(0);
+
+        let filter = jq::filter::Filter::parse(".phone_numbers[-1] | .number").unwrap();
+        let outputs: Vec<_> = filter.run(value.clone()).collect();
+        assert_eq!(outputs, vec![Ok(jq::Value::String("555-5678".to_string()))]);
+
+        let filter = jq::filter::Filter::parse(r#".address["coordinates"].latitude"#).unwrap();
+        let outputs: Vec<_> = filter.run(value.clone()).collect();
+        assert_eq!(outputs, vec![Ok(jq::Value::Number(40.7128))]);
+
+        let filter = jq::filter::Filter::parse("[.. | .number? // empty]").unwrap();
+        let outputs: Vec<_> = filter
+            .run(value.clone())
+            .map(|v| v.unwrap().to_string())
+            .collect();
+        assert_eq!(outputs, vec![r#"["555-1234","555-5678"]"#]);
+
+        let filter =
+            jq::filter::Filter::parse(r#"del(.. | .type?) | pick(.phone_numbers[].number)"#)
+                .unwrap();
+        let outputs: Vec<_> = filter.run(value).map(|v| v.unwrap().to_string()).collect();
+        assert_eq!(
+            outputs,
+            vec![r#"{"phone_numbers":[{"number":"555-1234"},{"number":"555-5678"}]}"#]
+        );
+
+        let err = jq::filter::Filter::parse(".a |").unwrap_err();
+        assert_eq!(err.to_string(), "1:5: Unexpected end of filter");
+    }
+
     fn get_all_files(dir: &str) -> Vec<String> {
         let entries = fs::read_dir(dir).unwrap();
         let mut files = Vec::new();
@@ -0,0 +1,100 @@
+#[cfg(test)]
+mod throughput {
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use jq::filter::Filter;
+    use jq::tokenizer::Tokenizer;
+
+    // an array of small log records, roughly `records * 100` bytes
+    fn generate_json(records: usize) -> String {
+        let mut s = String::from("[");
+        for i in 0..records {
+            if i > 0 {
+                s.push(',');
+            }
+            s.push_str(&format!(
+                r#"{{"id": {}, "level": "info", "message": "café ✓ in {}ms", "ok": true}}"#,
+                i,
+                i % 1000
+            ));
+        }
+        s.push(']');
+        s
+    }
+
+    // Fails if `time` of 4x the input takes more than 8x as long. Linear is
+    // ~4x and quadratic ~16x, so either side has room for scheduler noise.
+    // Each size is timed as the best of a few runs.
+    fn assert_linear(what: &str, size: usize, time: impl Fn(usize) -> Duration) {
+        let best = |size| (0..3).map(|_| time(size)).min().unwrap();
+        let small_time = best(size);
+        let large_time = best(size * 4);
+
+        let ratio = large_time.as_secs_f64() / small_time.as_secs_f64();
+        assert!(
+            ratio < 8.0,
+            "{} 4x input took {:.2}x longer ({:?} vs {:?})",
+            what,
+            ratio,
+            large_time,
+            small_time
+        );
+    }
+
+    #[test]
+    fn test_tokenizer_scales_linearly() {
+        assert_linear("Tokenizing", 5_000, |records| {
+            let contents = generate_json(records);
+            let start = Instant::now();
+            let tokens = Tokenizer::new(&contents).try_collect().unwrap();
+            let elapsed = start.elapsed();
+            assert!(!tokens.is_empty());
+            elapsed
+        });
+    }
+
+    #[test]
+    fn test_reduce_scales_linearly() {
+        // copying the accumulator on every step would make this quadratic
+        let filter = Filter::parse("reduce .[] as $x ([]; . + [$x])").unwrap();
+        assert_linear("Reducing", 50_000, |len| {
+            let input = numbers(len);
+            let start = Instant::now();
+            let outputs: Vec<_> = filter.run(input).collect();
+            let elapsed = start.elapsed();
+            assert!(outputs.iter().all(|output| output.is_ok()));
+            elapsed
+        });
+    }
+
+    #[test]
+    fn test_assignment_updates_in_place() {
+        // an array that nothing else holds comes back as the same allocation
+        let filter =
+            Filter::parse(".[0] += 1 | .[1] |= . * 2 | .[2] = 5 | .[3] //= 1 | .[1:3] |= map(-.)")
+                .unwrap();
+        let input = numbers(10);
+        let jq::Value::Array(elements) = &input else {
+            unreachable!()
+        };
+        let allocation = Arc::as_ptr(elements);
+
+        let output = filter.run(input).next().unwrap().unwrap();
+        assert_eq!(output.to_string(), "[1,-2,-5,3,4,5,6,7,8,9]");
+        let jq::Value::Array(elements) = &output else {
+            unreachable!()
+        };
+        assert_eq!(Arc::as_ptr(elements), allocation);
+    }
+
+    // an array of the numbers up to `len`
+    fn numbers(len: usize) -> jq::Value {
+        jq::Value::Array(
+            (0..len)
+                .map(|i| jq::Value::Number(i as f64))
+                .collect::<Vec<_>>()
+                .into(),
+        )
+    }
+}